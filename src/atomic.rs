@@ -0,0 +1,284 @@
+// MIT/Apache2 License
+
+//! The atomic types the locks are built on.
+//!
+//! Under loom these are loom's model-checked atomics. With the `critical-section` feature, on
+//! targets that can load and store a `usize` atomically but have no compare-and-swap, the
+//! read-modify-write operations are emulated inside a critical section. With the
+//! `portable-atomic` feature, they come from `portable_atomic`, which takes precedence over
+//! `critical-section`.
+
+pub(crate) use core::sync::atomic::Ordering;
+
+#[cfg(all(
+    not(loom),
+    not(feature = "portable-atomic"),
+    not(all(feature = "critical-section", not(target_has_atomic = "ptr")))
+))]
+pub(crate) use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize};
+#[cfg(all(
+    not(loom),
+    not(feature = "portable-atomic"),
+    feature = "critical-section",
+    not(target_has_atomic = "ptr")
+))]
+pub(crate) use fallback::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize};
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize};
+#[cfg(all(not(loom), feature = "portable-atomic"))]
+pub(crate) use portable_atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize};
+
+#[cfg(all(feature = "tsan", not(loom)))]
+use core::sync::atomic::fence;
+#[cfg(all(feature = "tsan", loom))]
+use loom::sync::atomic::fence;
+
+/// Called just before a reader that speculatively incremented the count backs out.
+///
+/// The back-out is a relaxed read-modify-write, which is enough: it comes after the increment in
+/// the state's modification order, so the next writer's compare-and-swap sees it. `ThreadSanitizer`
+/// doesn't follow that argument and reports a race, so with the `tsan` feature this issues a
+/// release fence to make the edge explicit. Otherwise it does nothing.
+#[inline]
+pub(crate) fn rollback_fence() {
+    #[cfg(feature = "tsan")]
+    fence(Ordering::Release);
+}
+
+// Counters that never take part in synchronization, so they stay plain atomics even under loom.
+#[cfg(feature = "metrics")]
+#[cfg(all(
+    not(feature = "portable-atomic"),
+    not(all(feature = "critical-section", not(target_has_atomic = "ptr")))
+))]
+pub(crate) use core::sync::atomic::AtomicUsize as CounterUsize;
+#[cfg(feature = "metrics")]
+#[cfg(all(
+    not(feature = "portable-atomic"),
+    feature = "critical-section",
+    not(target_has_atomic = "ptr")
+))]
+pub(crate) use fallback::AtomicUsize as CounterUsize;
+#[cfg(feature = "metrics")]
+#[cfg(feature = "portable-atomic")]
+pub(crate) use portable_atomic::AtomicUsize as CounterUsize;
+
+/// Atomics whose read-modify-write operations run inside a critical section.
+///
+/// Every update is exclusive, so a compare-and-swap never fails spuriously and the retry loops
+/// around it never spin for nothing. Waiting for a lock that someone else holds still spins. The
+/// `Ordering`s passed to read-modify-write operations are ignored, since the critical section
+/// already orders them.
+#[cfg(any(
+    all(
+        not(feature = "portable-atomic"),
+        feature = "critical-section",
+        not(target_has_atomic = "ptr")
+    ),
+    all(test, feature = "critical-section")
+))]
+mod fallback {
+    use core::sync::atomic::{self, Ordering};
+
+    macro_rules! rmw_atomic {
+        ($name:ident, $int:ty) => {
+            pub(crate) struct $name(atomic::$name);
+
+            // not every width needs every operation
+            #[allow(dead_code)]
+            impl $name {
+                #[inline]
+                pub(crate) const fn new(value: $int) -> Self {
+                    $name(atomic::$name::new(value))
+                }
+
+                #[inline]
+                pub(crate) fn load(&self, order: Ordering) -> $int {
+                    self.0.load(order)
+                }
+
+                #[inline]
+                pub(crate) fn store(&self, value: $int, order: Ordering) {
+                    self.0.store(value, order);
+                }
+
+                #[inline]
+                fn update(&self, f: impl FnOnce($int) -> $int) -> $int {
+                    critical_section::with(|_| {
+                        let old = self.0.load(Ordering::Relaxed);
+                        self.0.store(f(old), Ordering::Relaxed);
+                        old
+                    })
+                }
+
+                #[inline]
+                pub(crate) fn fetch_add(&self, value: $int, _order: Ordering) -> $int {
+                    self.update(|old| old.wrapping_add(value))
+                }
+
+                #[inline]
+                pub(crate) fn fetch_sub(&self, value: $int, _order: Ordering) -> $int {
+                    self.update(|old| old.wrapping_sub(value))
+                }
+
+                #[inline]
+                pub(crate) fn fetch_and(&self, value: $int, _order: Ordering) -> $int {
+                    self.update(|old| old & value)
+                }
+
+                #[inline]
+                pub(crate) fn fetch_or(&self, value: $int, _order: Ordering) -> $int {
+                    self.update(|old| old | value)
+                }
+
+                #[inline]
+                pub(crate) fn fetch_xor(&self, value: $int, _order: Ordering) -> $int {
+                    self.update(|old| old ^ value)
+                }
+
+                #[inline]
+                pub(crate) fn fetch_max(&self, value: $int, _order: Ordering) -> $int {
+                    self.update(|old| old.max(value))
+                }
+
+                #[inline]
+                pub(crate) fn compare_exchange(
+                    &self,
+                    current: $int,
+                    new: $int,
+                    _success: Ordering,
+                    _failure: Ordering,
+                ) -> Result<$int, $int> {
+                    critical_section::with(|_| {
+                        let old = self.0.load(Ordering::Relaxed);
+                        if old == current {
+                            self.0.store(new, Ordering::Relaxed);
+                            Ok(old)
+                        } else {
+                            Err(old)
+                        }
+                    })
+                }
+
+                #[inline]
+                pub(crate) fn compare_exchange_weak(
+                    &self,
+                    current: $int,
+                    new: $int,
+                    success: Ordering,
+                    failure: Ordering,
+                ) -> Result<$int, $int> {
+                    self.compare_exchange(current, new, success, failure)
+                }
+            }
+        };
+    }
+
+    rmw_atomic!(AtomicUsize, usize);
+    rmw_atomic!(AtomicU32, u32);
+    rmw_atomic!(AtomicU8, u8);
+
+    pub(crate) struct AtomicBool(atomic::AtomicBool);
+
+    impl AtomicBool {
+        #[inline]
+        pub(crate) const fn new(value: bool) -> Self {
+            AtomicBool(atomic::AtomicBool::new(value))
+        }
+
+        #[inline]
+        pub(crate) fn load(&self, order: Ordering) -> bool {
+            self.0.load(order)
+        }
+
+        #[inline]
+        pub(crate) fn store(&self, value: bool, order: Ordering) {
+            self.0.store(value, order);
+        }
+
+        #[inline]
+        pub(crate) fn compare_exchange(
+            &self,
+            current: bool,
+            new: bool,
+            _success: Ordering,
+            _failure: Ordering,
+        ) -> Result<bool, bool> {
+            critical_section::with(|_| {
+                let old = self.0.load(Ordering::Relaxed);
+                if old == current {
+                    self.0.store(new, Ordering::Relaxed);
+                    Ok(old)
+                } else {
+                    Err(old)
+                }
+            })
+        }
+
+        #[inline]
+        pub(crate) fn compare_exchange_weak(
+            &self,
+            current: bool,
+            new: bool,
+            success: Ordering,
+            failure: Ordering,
+        ) -> Result<bool, bool> {
+            self.compare_exchange(current, new, success, failure)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize, Ordering::SeqCst};
+
+        #[test]
+        fn usize_ops() {
+            let atomic = AtomicUsize::new(0b0110);
+            assert_eq!(atomic.fetch_add(1, SeqCst), 0b0110);
+            assert_eq!(atomic.fetch_sub(2, SeqCst), 0b0111);
+            assert_eq!(atomic.fetch_and(0b0001, SeqCst), 0b0101);
+            assert_eq!(atomic.fetch_xor(0b0011, SeqCst), 0b0001);
+            assert_eq!(atomic.fetch_or(0b1000, SeqCst), 0b0010);
+            assert_eq!(atomic.fetch_xor(0b1000, SeqCst), 0b1010);
+            assert_eq!(atomic.compare_exchange(0, 4, SeqCst, SeqCst), Err(0b0010));
+            assert_eq!(atomic.compare_exchange_weak(2, 4, SeqCst, SeqCst), Ok(2));
+            atomic.store(usize::MAX, SeqCst);
+            assert_eq!(atomic.fetch_add(1, SeqCst), usize::MAX);
+            assert_eq!(atomic.load(SeqCst), 0);
+
+            let atomic = AtomicU32::new(u32::MAX);
+            assert_eq!(atomic.fetch_add(1, SeqCst), u32::MAX);
+            assert_eq!(atomic.load(SeqCst), 0);
+
+            let atomic = AtomicU8::new(u8::MAX);
+            assert_eq!(atomic.fetch_add(1, SeqCst), u8::MAX);
+            assert_eq!(atomic.load(SeqCst), 0);
+        }
+
+        #[test]
+        fn bool_ops() {
+            let atomic = AtomicBool::new(false);
+            assert_eq!(
+                atomic.compare_exchange(true, false, SeqCst, SeqCst),
+                Err(false)
+            );
+            assert_eq!(
+                atomic.compare_exchange_weak(false, true, SeqCst, SeqCst),
+                Ok(false)
+            );
+            atomic.store(false, SeqCst);
+            assert!(!atomic.load(SeqCst));
+        }
+    }
+}
+
+#[cfg(all(test, not(loom), feature = "portable-atomic"))]
+mod tests {
+    // the lock state has to be the `portable_atomic` type with the feature on
+    #[test]
+    fn portable_atomic() {
+        let state: portable_atomic::AtomicUsize = super::AtomicUsize::new(0);
+        state.fetch_add(1, super::Ordering::Relaxed);
+        assert_eq!(state.load(super::Ordering::Relaxed), 1);
+    }
+}
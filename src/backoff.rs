@@ -0,0 +1,427 @@
+// MIT/Apache2 License
+
+//! Strategies for waiting out a contended lock.
+
+use super::spin_loop;
+// a plain `core` atomic even under loom, since it has to live in a `static`
+use core::sync::atomic::{AtomicU32, Ordering};
+
+#[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+use core::{arch::wasm32::memory_atomic_wait32, sync::atomic::AtomicI32};
+#[cfg(loom)]
+use loom::thread::yield_now;
+#[cfg(all(feature = "std", not(loom)))]
+use std::thread::yield_now;
+
+/// A strategy for busy-waiting while a lock is contended.
+///
+/// A fresh backoff is created at the start of each acquisition, and `spin` is called every time
+/// an attempt to take the lock fails.
+pub trait Backoff {
+    /// Create a new backoff for a single acquisition.
+    fn new() -> Self;
+
+    /// Create a new backoff for a single acquisition of a write lock, including an upgrade.
+    ///
+    /// Writers retry with a compare-and-swap, so many of them contending at once can keep failing
+    /// in lockstep. A backoff can override this to wait differently for them, as
+    /// [`JitteredBackoff`] does. It is [`new`](Backoff::new) by default.
+    #[inline]
+    #[must_use]
+    fn new_exclusive() -> Self
+    where
+        Self: Sized,
+    {
+        Self::new()
+    }
+
+    /// Wait a little before the next attempt.
+    fn spin(&mut self);
+}
+
+/// The backoff used by the acquisition loops.
+pub type DefaultBackoff = ExponentialBackoff;
+
+/// Spins once per failed attempt, with no backoff at all.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpinOnly;
+
+/// An alias for [`SpinOnly`].
+pub type NoBackoff = SpinOnly;
+
+impl Backoff for SpinOnly {
+    #[inline]
+    fn new() -> Self {
+        SpinOnly
+    }
+
+    #[inline]
+    fn spin(&mut self) {
+        spin_loop();
+    }
+}
+
+/// Issues a number of spin hints per failed attempt chosen for the target architecture.
+///
+/// On `x86_64`, where `spin_loop` is a single `PAUSE`, issuing several per attempt can take some
+/// pressure off a contended cache line. This issues `X86_64_HINTS` of them there, one by default.
+/// Elsewhere it always issues one, where a single `YIELD` or its equivalent is already the right
+/// step.
+///
+/// ```
+/// use spinny::{ArchSpin, RawRwSpinlock};
+///
+/// // four `PAUSE`s per attempt on x86_64, one elsewhere
+/// let lock = lock_api::RwLock::<RawRwSpinlock<ArchSpin<4>>, _>::new(0);
+/// *lock.write() += 1;
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ArchSpin<const X86_64_HINTS: u32 = 1>;
+
+impl<const X86_64_HINTS: u32> ArchSpin<X86_64_HINTS> {
+    /// The number of spin hints issued per failed attempt on this target.
+    pub const HINTS: u32 = if cfg!(target_arch = "x86_64") {
+        X86_64_HINTS
+    } else {
+        1
+    };
+
+    #[inline]
+    fn spin_with(mut hint: impl FnMut()) {
+        for _ in 0..Self::HINTS {
+            hint();
+        }
+    }
+}
+
+impl<const X86_64_HINTS: u32> Backoff for ArchSpin<X86_64_HINTS> {
+    #[inline]
+    fn new() -> Self {
+        ArchSpin
+    }
+
+    #[inline]
+    fn spin(&mut self) {
+        Self::spin_with(spin_loop);
+    }
+}
+
+/// Yields to the OS scheduler on every failed attempt.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct YieldingBackoff;
+
+#[cfg(feature = "std")]
+impl Backoff for YieldingBackoff {
+    #[inline]
+    fn new() -> Self {
+        YieldingBackoff
+    }
+
+    #[inline]
+    fn spin(&mut self) {
+        yield_now();
+    }
+}
+
+/// Spins for `SPIN_LIMIT` failed attempts, then yields to the OS scheduler on every attempt after.
+///
+/// This suits locks that may be held for a long time on an oversubscribed machine, where
+/// spinning forever would keep the lock holder from being scheduled.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpinThenYield<const SPIN_LIMIT: usize = 100> {
+    spins: usize,
+}
+
+#[cfg(feature = "std")]
+impl<const SPIN_LIMIT: usize> Backoff for SpinThenYield<SPIN_LIMIT> {
+    #[inline]
+    fn new() -> Self {
+        SpinThenYield { spins: 0 }
+    }
+
+    #[inline]
+    fn spin(&mut self) {
+        if self.spins < SPIN_LIMIT {
+            self.spins += 1;
+            spin_loop();
+        } else {
+            yield_now();
+        }
+    }
+}
+
+/// Spins for `SPIN_LIMIT` failed attempts, then sleeps in `memory.atomic.wait32` on every attempt
+/// after.
+///
+/// On `wasm32` there is no scheduler to yield to, so a thread that keeps spinning uses up its
+/// whole time slice while the lock holder waits to run. Waiting in `memory.atomic.wait32` with a
+/// timeout hands the CPU back to the host instead. Nothing ever notifies the wait, so each one
+/// lasts its full timeout: one microsecond at first, doubling up to a millisecond.
+///
+/// This is only available on `wasm32` with shared memory, built with
+/// `RUSTFLAGS="-C target-feature=+atomics,+bulk-memory"` and a standard library rebuilt to match,
+/// as with `cargo +nightly build -Z build-std=std,panic_abort`. Browsers don't allow the main
+/// thread to wait, so only use this on locks taken from workers.
+#[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+#[derive(Debug, Clone, Copy)]
+pub struct WasmWaitBackoff<const SPIN_LIMIT: usize = 100> {
+    spins: usize,
+    timeout_ns: i64,
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+impl<const SPIN_LIMIT: usize> WasmWaitBackoff<SPIN_LIMIT> {
+    const FIRST_WAIT_NS: i64 = 1_000;
+    const MAX_WAIT_NS: i64 = 1_000_000;
+}
+
+// the address waited on; it is never changed or notified, so every wait runs to its timeout
+#[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+static WAIT_CELL: AtomicI32 = AtomicI32::new(0);
+
+#[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+impl<const SPIN_LIMIT: usize> Backoff for WasmWaitBackoff<SPIN_LIMIT> {
+    #[inline]
+    fn new() -> Self {
+        WasmWaitBackoff {
+            spins: 0,
+            timeout_ns: Self::FIRST_WAIT_NS,
+        }
+    }
+
+    #[inline]
+    fn spin(&mut self) {
+        if self.spins < SPIN_LIMIT {
+            self.spins += 1;
+            spin_loop();
+        } else {
+            // SAFETY: `WAIT_CELL` is a valid, aligned `i32` that lives for the whole program
+            unsafe { memory_atomic_wait32(WAIT_CELL.as_ptr(), 0, self.timeout_ns) };
+            self.timeout_ns = (self.timeout_ns * 2).min(Self::MAX_WAIT_NS);
+        }
+    }
+}
+
+/// Doubles the number of spins per failed attempt, up to a cap.
+///
+/// Once the cap has been reached, this yields to the OS scheduler on `std` targets, and keeps
+/// spinning at the cap otherwise.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExponentialBackoff {
+    step: u32,
+}
+
+impl ExponentialBackoff {
+    /// The maximum number of spins per attempt is `1 << SPIN_LIMIT`.
+    pub const SPIN_LIMIT: u32 = 6;
+}
+
+impl Backoff for ExponentialBackoff {
+    #[inline]
+    fn new() -> Self {
+        ExponentialBackoff { step: 0 }
+    }
+
+    #[inline]
+    fn spin(&mut self) {
+        if self.step <= Self::SPIN_LIMIT {
+            for _ in 0..1u32 << self.step {
+                spin_loop();
+            }
+            self.step += 1;
+        } else {
+            #[cfg(any(feature = "std", loom))]
+            yield_now();
+            #[cfg(not(any(feature = "std", loom)))]
+            for _ in 0..1u32 << Self::SPIN_LIMIT {
+                spin_loop();
+            }
+        }
+    }
+}
+
+/// Like [`ExponentialBackoff`], but writers wait a random number of spins up to the current step.
+///
+/// When dozens of writers contend at once, they all fail their compare-and-swap together, back
+/// off for the same time, and fail together again. Jitter spreads their retries out so that one
+/// of them can get in. Readers don't retry with a compare-and-swap, so they back off exactly like
+/// `ExponentialBackoff`.
+///
+/// The randomness comes from a tiny xorshift generator, seeded from the address of the waiting
+/// thread's stack and a count of the waits so far, so it needs no `std`. It is only seeded once a
+/// writer first fails to get in, so uncontended locks never touch the count. It only has to tell
+/// threads and their waits apart, and is nowhere near good enough for anything else.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JitteredBackoff {
+    step: u32,
+    // readers don't jitter
+    jitter: bool,
+    // zero until the first jittered wait
+    rng: u32,
+}
+
+// bumped by every jittered wait, so that the waits on one thread don't all replay the same jitter;
+// a plain load and store, since a lost update only costs a repeated seed somewhere else, and the
+// counter has to work on targets without atomic read-modify-writes
+static WAITS: AtomicU32 = AtomicU32::new(0);
+
+impl JitteredBackoff {
+    /// The maximum number of spins per attempt is `1 << SPIN_LIMIT`.
+    pub const SPIN_LIMIT: u32 = ExponentialBackoff::SPIN_LIMIT;
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn seed() -> u32 {
+        let local = 0_u8;
+        // every thread has its own stack, so the address tells them apart; the multiply brings
+        // the bits that differ between stacks up to the top 32
+        let addr = core::ptr::addr_of!(local) as usize;
+        let mixed = (addr.wrapping_mul(0x9E37_79B9) >> (usize::BITS - 32)) as u32;
+        // and the count tells apart the waits on one thread
+        let waits = WAITS.load(Ordering::Relaxed);
+        WAITS.store(waits.wrapping_add(1), Ordering::Relaxed);
+        (mixed ^ waits.wrapping_mul(0x9E37_79B9)) | 1
+    }
+
+    // the number of spins to wait for this attempt, at least one and at most `1 << step`
+    fn spins(&mut self, step: u32) -> u32 {
+        if !self.jitter {
+            return 1 << step;
+        }
+        if self.rng == 0 {
+            self.rng = Self::seed();
+        }
+        // xorshift32, which never reaches zero from a nonzero seed
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        1 + self.rng % (1 << step)
+    }
+}
+
+impl Backoff for JitteredBackoff {
+    #[inline]
+    fn new() -> Self {
+        JitteredBackoff {
+            step: 0,
+            jitter: false,
+            rng: 0,
+        }
+    }
+
+    #[inline]
+    fn new_exclusive() -> Self {
+        JitteredBackoff {
+            step: 0,
+            jitter: true,
+            rng: 0,
+        }
+    }
+
+    #[inline]
+    fn spin(&mut self) {
+        if self.step <= Self::SPIN_LIMIT {
+            for _ in 0..self.spins(self.step) {
+                spin_loop();
+            }
+            self.step += 1;
+        } else {
+            #[cfg(any(feature = "std", loom))]
+            yield_now();
+            #[cfg(not(any(feature = "std", loom)))]
+            for _ in 0..self.spins(Self::SPIN_LIMIT) {
+                spin_loop();
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::{ArchSpin, Backoff, ExponentialBackoff, JitteredBackoff, SpinOnly};
+
+    #[test]
+    fn exponential_caps() {
+        let mut backoff = ExponentialBackoff::new();
+        for _ in 0..ExponentialBackoff::SPIN_LIMIT * 2 {
+            backoff.spin();
+        }
+        assert_eq!(backoff.step, ExponentialBackoff::SPIN_LIMIT + 1);
+
+        let mut backoff = SpinOnly::new();
+        backoff.spin();
+    }
+
+    // test that the configured number of hints is issued on x86_64, and one elsewhere
+    #[test]
+    fn arch_spin() {
+        fn hints<const N: u32>() -> u32 {
+            let mut count = 0;
+            ArchSpin::<N>::spin_with(|| count += 1);
+            count
+        }
+
+        let expected = if cfg!(target_arch = "x86_64") { 4 } else { 1 };
+        assert_eq!(ArchSpin::<4>::HINTS, expected);
+        assert_eq!(hints::<4>(), expected);
+        assert_eq!(hints::<1>(), 1);
+        assert_eq!(ArchSpin::<1>::HINTS, <ArchSpin>::HINTS);
+
+        let mut backoff = ArchSpin::<4>::new();
+        backoff.spin();
+    }
+
+    // test that writers wait a varying number of spins within the exponential bound, and readers
+    // don't jitter at all
+    #[test]
+    fn jittered() {
+        let mut backoff = JitteredBackoff::new_exclusive();
+        assert_eq!(backoff.rng, 0);
+        let waits: std::vec::Vec<u32> = (0..64)
+            .map(|_| backoff.spins(JitteredBackoff::SPIN_LIMIT))
+            .collect();
+        assert!(waits
+            .iter()
+            .all(|&spins| (1..=1 << JitteredBackoff::SPIN_LIMIT).contains(&spins)));
+        assert!(waits.iter().any(|&spins| spins != waits[0]));
+
+        let mut backoff = JitteredBackoff::new();
+        assert_eq!(backoff.spins(3), 8);
+        for _ in 0..JitteredBackoff::SPIN_LIMIT * 2 {
+            backoff.spin();
+        }
+        assert_eq!(backoff.step, JitteredBackoff::SPIN_LIMIT + 1);
+
+        // each wait is seeded afresh, on this thread as on any other
+        let seed = || {
+            let mut backoff = JitteredBackoff::new_exclusive();
+            backoff.spins(0);
+            backoff.rng
+        };
+        let other = std::thread::spawn(seed).join().unwrap();
+        let (first, second) = (seed(), seed());
+        assert_ne!(first, second);
+        assert_ne!(other, first);
+        assert_ne!(other, second);
+    }
+
+    // only built for wasm32 with atomics; see the docs on `WasmWaitBackoff` for the flags
+    #[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+    #[test]
+    fn wasm_wait() {
+        use super::WasmWaitBackoff;
+        use crate::RawRwSpinlock;
+
+        let mut backoff = WasmWaitBackoff::<2>::new();
+        for _ in 0..4 {
+            backoff.spin();
+        }
+        assert_eq!(backoff.spins, 2);
+        assert_eq!(backoff.timeout_ns, 4_000);
+
+        let rwlock = lock_api::RwLock::<RawRwSpinlock<WasmWaitBackoff>, _>::new(0);
+        *rwlock.write() += 1;
+        assert_eq!(*rwlock.read(), 1);
+    }
+}
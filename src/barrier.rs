@@ -0,0 +1,133 @@
+// MIT/Apache2 License
+
+//! A spin-based barrier, for threads that need to rendezvous without parking.
+
+use super::{spin_loop, AtomicUsize, Ordering, RwLock};
+use core::fmt;
+
+/// A barrier that makes a fixed number of threads wait for each other.
+///
+/// Unlike `std::sync::Barrier`, waiting threads spin instead of parking, and nothing is
+/// allocated. The barrier can be reused once every thread has passed through it.
+pub struct Barrier {
+    /// The number of threads that have to call `wait` before any of them are released.
+    n: usize,
+    /// The number of threads that have arrived in the current generation.
+    count: AtomicUsize,
+    /// Bumped by the last thread to arrive, which releases everyone waiting on the old value.
+    generation: RwLock<usize>,
+}
+
+/// Returned by [`Barrier::wait`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    /// Whether this thread was the last to arrive.
+    ///
+    /// Exactly one thread per generation is the leader.
+    #[inline]
+    #[must_use]
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+impl Barrier {
+    /// Create a barrier that releases threads once `n` of them are waiting on it.
+    ///
+    /// A barrier for zero threads behaves like a barrier for one.
+    #[inline]
+    #[must_use]
+    pub fn new(n: usize) -> Self {
+        Barrier {
+            n,
+            count: AtomicUsize::new(0),
+            generation: RwLock::new(0),
+        }
+    }
+
+    /// Spin until all `n` threads have called `wait`.
+    pub fn wait(&self) -> BarrierWaitResult {
+        // arrive under the read lock, so the generation we wait on is the one we were counted in
+        let guard = self.generation.read();
+        let generation = *guard;
+        let arrived = self.count.fetch_add(1, Ordering::AcqRel) + 1;
+        drop(guard);
+
+        if arrived >= self.n {
+            // everyone else in this generation is spinning, so no one can arrive until the
+            // generation moves on
+            let mut guard = self.generation.write();
+            self.count.store(0, Ordering::Relaxed);
+            *guard = guard.wrapping_add(1);
+            BarrierWaitResult(true)
+        } else {
+            while *self.generation.read() == generation {
+                spin_loop();
+            }
+            BarrierWaitResult(false)
+        }
+    }
+}
+
+impl fmt::Debug for Barrier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Barrier")
+            .field("n", &self.n)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::Barrier;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        vec::Vec,
+    };
+
+    #[test]
+    fn single() {
+        let barrier = Barrier::new(1);
+        assert!(barrier.wait().is_leader());
+        assert!(barrier.wait().is_leader());
+    }
+
+    // test that four threads rendezvous twice, with one leader each time
+    #[test]
+    fn phases() {
+        const THREADS: usize = 4;
+
+        let barrier = Arc::new(Barrier::new(THREADS));
+        let arrived = Arc::new(AtomicUsize::new(0));
+        let leaders = Arc::new(AtomicUsize::new(0));
+        let mut joiners = Vec::new();
+        for _ in 0..THREADS {
+            let bclone = barrier.clone();
+            let aclone = arrived.clone();
+            let lclone = leaders.clone();
+            joiners.push(thread::spawn(move || {
+                for phase in 1..=2 {
+                    aclone.fetch_add(1, Ordering::SeqCst);
+                    if bclone.wait().is_leader() {
+                        lclone.fetch_add(1, Ordering::SeqCst);
+                    }
+                    // nobody gets past the barrier until everyone has arrived
+                    assert!(aclone.load(Ordering::SeqCst) >= phase * THREADS);
+                    bclone.wait();
+                }
+            }));
+        }
+
+        for j in joiners {
+            j.join().unwrap();
+        }
+        assert_eq!(arrived.load(Ordering::SeqCst), 2 * THREADS);
+        assert_eq!(leaders.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,189 @@
+// MIT/Apache2 License
+
+//! A variant of the spinlock rwlock that caps how many readers can hold it at once.
+
+use super::{
+    Acquire, Backoff, DefaultBackoff, OrderingPolicy, RawRwSpinlock, Standard, READER, READER_LIMIT,
+};
+use core::fmt;
+use lock_api::{GuardSend, RawRwLock, RawRwLockRecursive, RwLock as LARwLock};
+#[cfg(feature = "upgrade")]
+use lock_api::{RawRwLockDowngrade, RawRwLockUpgrade, RawRwLockUpgradeDowngrade};
+
+/// Raw spinlock rwlock that lets a bounded number of readers in, wrapped in the `lock_api`
+/// `RwLock` struct.
+///
+/// This is a [`RawRwSpinlock`] with a cap on readers, created by
+/// [`RawRwSpinlock::with_max_readers`]. The cap is kept next to the state rather than in every
+/// `RawRwSpinlock`, so only the locks that want one pay a word for it. Writers and upgradable
+/// readers behave exactly as they do on a `RawRwSpinlock`.
+///
+/// `INIT` has no cap beyond what the reader count can represent, which is also the limit of a
+/// plain `RawRwSpinlock`.
+pub struct RawCappedRwSpinlock<
+    B: Backoff = DefaultBackoff,
+    O: OrderingPolicy = Standard,
+    G = GuardSend,
+> {
+    inner: RawRwSpinlock<B, O, G>,
+    // new readers are turned away once the state reaches this
+    reader_limit: usize,
+}
+
+impl<B: Backoff, O: OrderingPolicy, G> RawRwSpinlock<B, O, G> {
+    /// Create a new lock that lets at most `max` readers hold it at once.
+    ///
+    /// Once `max` readers hold the lock, `try_lock_shared` fails and `lock_shared` spins, even
+    /// though there is room for more in the state. This is for systems that need an explicit bound
+    /// on concurrency; [`RwLockExt::with_max_readers`](crate::RwLockExt::with_max_readers) makes
+    /// an `RwLock` around one. A `max` larger than the reader count can represent is clamped to
+    /// what it can, which is also the limit of a lock created the usual way. With a `max` of zero,
+    /// no reader is ever let in.
+    ///
+    /// A reader that is backing out of a failed attempt briefly counts towards `max`, so an
+    /// attempt made while the lock holds `max - 1` readers can fail spuriously. Downgrading a
+    /// write or upgradable lock always succeeds, so a thread that downgrades can take the count
+    /// past `max`.
+    #[must_use]
+    pub const fn with_max_readers(max: usize) -> RawCappedRwSpinlock<B, O, G> {
+        let reader_limit = if max < READER_LIMIT / READER {
+            max * READER
+        } else {
+            READER_LIMIT
+        };
+        RawCappedRwSpinlock {
+            inner: RawRwSpinlock::INIT,
+            reader_limit,
+        }
+    }
+}
+
+impl<B: Backoff, O: OrderingPolicy, G> RawCappedRwSpinlock<B, O, G> {
+    /// Get the most readers that can hold the lock at once.
+    ///
+    /// See [`with_max_readers`](RawRwSpinlock::with_max_readers).
+    #[must_use]
+    pub fn max_readers(&self) -> usize {
+        self.reader_limit / READER
+    }
+
+    /// Get the number of readers currently holding the lock.
+    ///
+    /// See [`RawRwSpinlock::reader_count`] for caveats.
+    #[must_use]
+    pub fn reader_count(&self) -> usize {
+        self.inner.reader_count()
+    }
+}
+
+impl<B: Backoff, O: OrderingPolicy, G> fmt::Debug for RawCappedRwSpinlock<B, O, G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawCappedRwSpinlock")
+            .field("lock", &self.inner)
+            .field("max_readers", &self.max_readers())
+            .finish_non_exhaustive()
+    }
+}
+
+unsafe impl<B: Backoff, O: OrderingPolicy, G> RawRwLock for RawCappedRwSpinlock<B, O, G> {
+    const INIT: RawCappedRwSpinlock<B, O, G> = RawCappedRwSpinlock {
+        inner: RawRwSpinlock::INIT,
+        reader_limit: READER_LIMIT,
+    };
+
+    type GuardMarker = G;
+
+    fn lock_shared(&self) {
+        self.inner.check_reentry();
+        self.inner.acquire(Acquire::Shared, |raw| {
+            raw.try_lock_shared_below(self.reader_limit)
+        });
+    }
+
+    fn try_lock_shared(&self) -> bool {
+        self.inner.try_lock_shared_below(self.reader_limit)
+    }
+
+    fn try_lock_exclusive(&self) -> bool {
+        self.inner.try_lock_exclusive()
+    }
+
+    fn lock_exclusive(&self) {
+        self.inner.lock_exclusive();
+    }
+
+    unsafe fn unlock_shared(&self) {
+        self.inner.unlock_shared();
+    }
+
+    unsafe fn unlock_exclusive(&self) {
+        self.inner.unlock_exclusive();
+    }
+
+    fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    fn is_locked_exclusive(&self) -> bool {
+        self.inner.is_locked_exclusive()
+    }
+}
+
+unsafe impl<B: Backoff, O: OrderingPolicy, G> RawRwLockRecursive for RawCappedRwSpinlock<B, O, G> {
+    fn lock_shared_recursive(&self) {
+        self.inner.acquire(Acquire::Recursive, |raw| {
+            raw.try_lock_shared_recursive_below(self.reader_limit)
+        });
+    }
+
+    fn try_lock_shared_recursive(&self) -> bool {
+        self.inner
+            .try_lock_shared_recursive_below(self.reader_limit)
+    }
+}
+
+#[cfg(feature = "upgrade")]
+unsafe impl<B: Backoff, O: OrderingPolicy, G> RawRwLockUpgrade for RawCappedRwSpinlock<B, O, G> {
+    fn lock_upgradable(&self) {
+        self.inner.lock_upgradable();
+    }
+
+    fn try_lock_upgradable(&self) -> bool {
+        self.inner.try_lock_upgradable()
+    }
+
+    unsafe fn unlock_upgradable(&self) {
+        self.inner.unlock_upgradable();
+    }
+
+    unsafe fn upgrade(&self) {
+        self.inner.upgrade();
+    }
+
+    unsafe fn try_upgrade(&self) -> bool {
+        self.inner.try_upgrade()
+    }
+}
+
+#[cfg(feature = "upgrade")]
+unsafe impl<B: Backoff, O: OrderingPolicy, G> RawRwLockDowngrade for RawCappedRwSpinlock<B, O, G> {
+    unsafe fn downgrade(&self) {
+        self.inner.downgrade();
+    }
+}
+
+#[cfg(feature = "upgrade")]
+unsafe impl<B: Backoff, O: OrderingPolicy, G> RawRwLockUpgradeDowngrade
+    for RawCappedRwSpinlock<B, O, G>
+{
+    unsafe fn downgrade_upgradable(&self) {
+        self.inner.downgrade_upgradable();
+    }
+
+    unsafe fn downgrade_to_upgradable(&self) {
+        self.inner.downgrade_to_upgradable();
+    }
+}
+
+/// A read-write lock that uses a spinlock internally, and lets a bounded number of readers in.
+pub type CappedRwLock<T> = LARwLock<RawCappedRwSpinlock, T>;
@@ -0,0 +1,41 @@
+// MIT/Apache2 License
+
+//! Clocks for the timed locking methods.
+
+/// A monotonic source of time that timed lock acquisitions can spin against.
+///
+/// On `std` targets, [`StdClock`] is backed by `std::time::Instant`. On `no_std` targets, this
+/// can be implemented over whatever monotonic timer the platform provides.
+pub trait Clock {
+    /// A point in time, as measured by this clock.
+    type Instant: Copy + Ord;
+    /// A span of time, as measured by this clock.
+    type Duration;
+
+    /// Get the current time.
+    fn now() -> Self::Instant;
+
+    /// Get the time `duration` after `instant`, or `None` if that can't be represented.
+    fn checked_add(instant: Self::Instant, duration: Self::Duration) -> Option<Self::Instant>;
+}
+
+/// A clock backed by `std::time::Instant`.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdClock;
+
+#[cfg(feature = "std")]
+impl Clock for StdClock {
+    type Instant = std::time::Instant;
+    type Duration = std::time::Duration;
+
+    #[inline]
+    fn now() -> Self::Instant {
+        std::time::Instant::now()
+    }
+
+    #[inline]
+    fn checked_add(instant: Self::Instant, duration: Self::Duration) -> Option<Self::Instant> {
+        instant.checked_add(duration)
+    }
+}
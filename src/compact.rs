@@ -0,0 +1,105 @@
+// MIT/Apache2 License
+
+//! A variant of the spinlock rwlock whose state fits in 32 bits.
+
+use super::{
+    atomic::{rollback_fence, AtomicU32},
+    narrow::impl_narrow_lock,
+    spin_loop, Backoff, DefaultBackoff, Ordering,
+};
+use core::{fmt, marker::PhantomData};
+use lock_api::{GuardSend, RawRwLock, RawRwLockFair, RawRwLockRecursive, RwLock as LARwLock};
+#[cfg(feature = "upgrade")]
+use lock_api::{RawRwLockDowngrade, RawRwLockUpgrade, RawRwLockUpgradeDowngrade};
+
+#[cfg(loom)]
+use crate::loom_lazy::LoomLazy;
+
+// the same layout as `RawRwSpinlock`, in 32 bits
+#[allow(clippy::cast_possible_truncation)]
+const READER: u32 = super::READER as u32;
+#[allow(clippy::cast_possible_truncation)]
+const UPGRADED: u32 = super::UPGRADED as u32;
+#[allow(clippy::cast_possible_truncation)]
+const WRITER: u32 = super::WRITER as u32;
+const READER_LIMIT: u32 = !(u32::MAX >> 1);
+
+/// Raw spinlock rwlock with a 32-bit state, wrapped in the `lock_api` `RwLock` struct.
+///
+/// This behaves exactly like [`RawRwSpinlock`](crate::RawRwSpinlock), but takes 4 bytes instead
+/// of 8 on 64-bit targets, for programs that keep many locks. The price is a lower reader limit:
+/// the reader count has 30 bits, and new readers are turned away once 2<sup>29</sup> of them hold
+/// the lock at once. The `metrics` counters and the timed methods are only available on
+/// `RawRwSpinlock`.
+pub struct RawRwSpinlock32<B: Backoff = DefaultBackoff> {
+    #[cfg(not(loom))]
+    state: AtomicU32,
+    #[cfg(loom)]
+    state: LoomLazy<AtomicU32>,
+    _backoff: PhantomData<fn() -> B>,
+}
+
+impl<B: Backoff> RawRwSpinlock32<B> {
+    /// Add a reader unless one of the `refuse` flags is set or the count is full.
+    #[inline]
+    fn add_reader(&self, refuse: u32) -> bool {
+        let value = self.ulock().fetch_add(READER, Ordering::Acquire);
+
+        if value & refuse != 0 || value >= READER_LIMIT {
+            rollback_fence();
+            self.ulock().fetch_sub(READER, Ordering::Relaxed);
+            false
+        } else {
+            true
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+impl<B: Backoff> RawRwSpinlock32<B> {
+    fn set_state(&self, value: u32) {
+        self.ulock().store(value, Ordering::Relaxed);
+    }
+}
+
+impl_narrow_lock! {
+    RawRwSpinlock32, AtomicU32;
+
+    unsafe fn downgrade_upgradable(&self) {
+        self.ulock().fetch_add(READER, Ordering::Acquire);
+        self.unlock_upgradable();
+    }
+}
+
+/// A read-write lock that uses a spinlock with a 32-bit state internally.
+pub type RwLock32<T> = LARwLock<RawRwSpinlock32, T>;
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::{RawRwSpinlock32, READER, READER_LIMIT};
+    #[cfg(feature = "upgrade")]
+    use crate::{test_util::script, RawRwSpinlock};
+    use lock_api::{RawRwLock, RawRwLockRecursive};
+
+    #[cfg(feature = "upgrade")]
+    #[test]
+    fn parity() {
+        assert_eq!(script::<RawRwSpinlock32>(), script::<RawRwSpinlock>());
+    }
+
+    #[test]
+    fn size() {
+        assert_eq!(core::mem::size_of::<RawRwSpinlock32>(), 4);
+    }
+
+    #[test]
+    fn reader_limit() {
+        let raw = <RawRwSpinlock32>::INIT;
+        raw.set_state(READER_LIMIT - READER);
+        assert!(raw.try_lock_shared());
+        assert!(!raw.try_lock_shared());
+        assert!(!raw.try_lock_shared_recursive());
+        unsafe { raw.unlock_shared() };
+        assert!(raw.try_lock_shared());
+    }
+}
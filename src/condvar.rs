@@ -0,0 +1,201 @@
+// MIT/Apache2 License
+
+//! A spin-based condition variable.
+
+use super::{spin_loop, AtomicUsize, Ordering};
+use core::fmt;
+use lock_api::{
+    MutexGuard as LAMutexGuard, RawMutex, RawRwLock, RwLockWriteGuard as LARwLockWriteGuard,
+};
+
+#[cfg(loom)]
+use crate::loom_lazy::LoomLazy;
+
+/// A condition variable that waiters spin on, for use with [`Mutex`](crate::Mutex) or
+/// [`RwLock`](crate::RwLock).
+///
+/// Waiting releases the lock, spins until the condition variable is notified, and takes the lock
+/// again. Notifications bump a sequence counter, so a notification sent after a thread starts
+/// waiting is never missed, even if it comes before the lock is released.
+///
+/// Wakeups can be spurious: [`notify_one`](Condvar::notify_one) wakes every thread that is
+/// waiting at the time, and only one of them may find what it was waiting for. Always wait in a
+/// loop that rechecks the condition:
+///
+/// ```
+/// use spinny::{Condvar, Mutex};
+///
+/// let ready = Mutex::new(true);
+/// let condvar = Condvar::new();
+///
+/// let mut guard = ready.lock();
+/// while !*guard {
+///     condvar.wait(&mut guard);
+/// }
+/// ```
+pub struct Condvar {
+    #[cfg(not(loom))]
+    seq: AtomicUsize,
+    #[cfg(loom)]
+    seq: LoomLazy<AtomicUsize>,
+}
+
+#[cfg(not(loom))]
+impl Condvar {
+    #[inline]
+    fn seq(&self) -> &AtomicUsize {
+        &self.seq
+    }
+}
+
+#[cfg(loom)]
+impl Condvar {
+    #[inline]
+    fn seq(&self) -> &AtomicUsize {
+        self.seq.get_or_init(|| AtomicUsize::new(0))
+    }
+}
+
+impl Condvar {
+    /// Create a new condition variable.
+    #[inline]
+    #[must_use]
+    pub const fn new() -> Self {
+        Condvar {
+            #[cfg(not(loom))]
+            seq: AtomicUsize::new(0),
+            #[cfg(loom)]
+            seq: LoomLazy::new(),
+        }
+    }
+
+    /// Release the mutex, wait for a notification, and lock it again.
+    ///
+    /// This may wake up spuriously; see the [type-level docs](Condvar).
+    pub fn wait<R: RawMutex, T: ?Sized>(&self, guard: &mut LAMutexGuard<'_, R, T>) {
+        // read the counter while still holding the lock, so that a notification sent after the
+        // waited-for state was checked can't slip by
+        let seq = self.seq().load(Ordering::Acquire);
+        LAMutexGuard::unlocked(guard, || self.spin_while(seq));
+    }
+
+    /// Release the write lock, wait for a notification, and lock it for writing again.
+    ///
+    /// This may wake up spuriously; see the [type-level docs](Condvar).
+    pub fn wait_write<R: RawRwLock, T: ?Sized>(&self, guard: &mut LARwLockWriteGuard<'_, R, T>) {
+        let seq = self.seq().load(Ordering::Acquire);
+        LARwLockWriteGuard::unlocked(guard, || self.spin_while(seq));
+    }
+
+    /// Wake up the threads that are waiting.
+    ///
+    /// Every waiting thread is woken, just as with [`notify_all`](Condvar::notify_all); this
+    /// exists so that code written against other condition variables reads the same.
+    #[inline]
+    pub fn notify_one(&self) {
+        self.notify_all();
+    }
+
+    /// Wake up every thread that is waiting.
+    #[inline]
+    pub fn notify_all(&self) {
+        self.seq().fetch_add(1, Ordering::Release);
+    }
+
+    #[inline]
+    fn spin_while(&self, seq: usize) {
+        while self.seq().load(Ordering::Acquire) == seq {
+            spin_loop();
+        }
+    }
+}
+
+impl Default for Condvar {
+    #[inline]
+    fn default() -> Self {
+        Condvar::new()
+    }
+}
+
+impl fmt::Debug for Condvar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Condvar").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Condvar;
+    use crate::Mutex;
+
+    #[cfg(loom)]
+    use loom::thread;
+    #[cfg(not(loom))]
+    use std::thread;
+
+    use std::sync::Arc;
+
+    // test that a consumer wakes up once the producer sets the flag
+    fn producer_consumer_kernel() {
+        let mutex = Arc::new(Mutex::new(false));
+        let condvar = Arc::new(Condvar::new());
+
+        let producer = {
+            let mclone = mutex.clone();
+            let cclone = condvar.clone();
+            thread::spawn(move || {
+                *mclone.lock() = true;
+                cclone.notify_one();
+            })
+        };
+
+        let mut guard = mutex.lock();
+        let mut wakeups = 0;
+        while !*guard {
+            condvar.wait(&mut guard);
+            wakeups += 1;
+        }
+        // there is only one notification to wake up for
+        assert!(wakeups <= 1);
+        drop(guard);
+        producer.join().unwrap();
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn producer_consumer() {
+        loom::model(|| producer_consumer_kernel());
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn producer_consumer() {
+        for _ in 0..if cfg!(miri) { 5 } else { 100 } {
+            producer_consumer_kernel();
+        }
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn rwlock() {
+        use crate::RwLock;
+
+        let rwlock = RwLock::new(0);
+        let condvar = Condvar::new();
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                let mut guard = rwlock.write();
+                while *guard == 0 {
+                    condvar.wait_write(&mut guard);
+                }
+                *guard += 1;
+            });
+
+            *rwlock.write() = 1;
+            condvar.notify_all();
+        });
+
+        assert_eq!(*rwlock.read(), 2);
+    }
+}
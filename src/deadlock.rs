@@ -0,0 +1,54 @@
+// MIT/Apache2 License
+
+//! Panicking on acquisitions that spin for too long, enabled by the `deadlock-detection` feature.
+
+// A plain `core` atomic even under loom, since it has to live in a `static`.
+use core::{
+    convert::TryFrom,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use std::time::{Duration, Instant};
+
+const DEFAULT_TIMEOUT_MS: u64 = 5_000;
+
+static TIMEOUT_MS: AtomicU64 = AtomicU64::new(DEFAULT_TIMEOUT_MS);
+
+/// Set how long an acquisition of a `RawRwSpinlock` may spin before it panics.
+///
+/// This applies to every lock in the program, and defaults to five seconds. It is rounded down to
+/// the millisecond.
+pub fn set_deadlock_timeout(timeout: Duration) {
+    let millis = u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX);
+    TIMEOUT_MS.store(millis, Ordering::Relaxed);
+}
+
+/// Get how long an acquisition of a `RawRwSpinlock` may spin before it panics.
+#[must_use]
+pub fn deadlock_timeout() -> Duration {
+    Duration::from_millis(TIMEOUT_MS.load(Ordering::Relaxed))
+}
+
+/// Tracks how long a single acquisition has been spinning.
+pub(crate) struct Watchdog {
+    start: Option<Instant>,
+}
+
+impl Watchdog {
+    #[inline]
+    pub(crate) fn new() -> Self {
+        Watchdog { start: None }
+    }
+
+    /// Called on every failed attempt; panics once the acquisition has spun for too long.
+    pub(crate) fn check(&mut self, kind: &str) {
+        // only start the clock once the lock turns out to be contended
+        let elapsed = self.start.get_or_insert_with(Instant::now).elapsed();
+        let timeout = deadlock_timeout();
+        assert!(
+            elapsed < timeout,
+            "possible deadlock: a {} acquisition of a spinny lock has spun for over {:?}",
+            kind,
+            timeout
+        );
+    }
+}
@@ -0,0 +1,83 @@
+// MIT/Apache2 License
+
+//! Errors returned by the fallible locking methods.
+
+use core::fmt;
+
+/// The reason a lock could not be acquired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum TryLockError {
+    /// The lock was still held by someone else when the timeout elapsed.
+    Timeout,
+}
+
+impl fmt::Display for TryLockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryLockError::Timeout => f.write_str("timed out waiting for the lock"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TryLockError {}
+
+/// A lock was taken after a thread panicked while holding its write guard.
+///
+/// The data may have been left half-updated. The error still holds the guard, or whatever else
+/// the locking method would have returned, so callers that know how to cope with that can carry
+/// on through [`into_inner`](PoisonError::into_inner).
+#[cfg(feature = "poison")]
+pub struct PoisonError<G> {
+    guard: G,
+}
+
+#[cfg(feature = "poison")]
+impl<G> PoisonError<G> {
+    /// Create a new `PoisonError` holding `guard`.
+    #[inline]
+    #[must_use]
+    pub fn new(guard: G) -> Self {
+        PoisonError { guard }
+    }
+
+    /// Get the guard, ignoring the poison.
+    #[inline]
+    #[must_use]
+    pub fn into_inner(self) -> G {
+        self.guard
+    }
+
+    /// Get a reference to the guard.
+    #[inline]
+    #[must_use]
+    pub fn get_ref(&self) -> &G {
+        &self.guard
+    }
+
+    /// Get a mutable reference to the guard.
+    #[inline]
+    #[must_use]
+    pub fn get_mut(&mut self) -> &mut G {
+        &mut self.guard
+    }
+}
+
+// the guard is left out, so that this doesn't need `G: Debug` and never locks anything
+#[cfg(feature = "poison")]
+impl<G> fmt::Debug for PoisonError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoisonError").finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "poison")]
+impl<G> fmt::Display for PoisonError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a thread panicked while holding the write lock")
+    }
+}
+
+#[cfg(feature = "poison")]
+impl<G> std::error::Error for PoisonError<G> {}
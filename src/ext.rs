@@ -0,0 +1,1286 @@
+// MIT/Apache2 License
+
+//! Extra methods for `RwLock`s backed by a `RawRwSpinlock`, and for their guards.
+
+#[cfg(feature = "std")]
+use super::TryLockError;
+use super::{spin_loop, Backoff, OrderingPolicy, RawCappedRwSpinlock, RawRwSpinlock};
+use core::{fmt, iter::FromIterator, mem};
+#[cfg(feature = "upgrade")]
+use lock_api::RwLockUpgradableReadGuard as LARwLockUpgradableReadGuard;
+use lock_api::{
+    RawRwLock, RwLock as LARwLock, RwLockReadGuard as LARwLockReadGuard,
+    RwLockWriteGuard as LARwLockWriteGuard,
+};
+#[cfg(feature = "std")]
+use std::{sync::PoisonError, time::Duration};
+#[cfg(feature = "alloc")]
+use {
+    crate::{RcRwLockReadGuard, RcRwLockWriteGuard},
+    alloc::rc::Rc,
+};
+
+/// Extra methods for `RwLock`s backed by a [`RawRwSpinlock`].
+pub trait RwLockExt<T: ?Sized> {
+    /// The raw lock backing this `RwLock`.
+    type Raw: lock_api::RawRwLock;
+
+    /// The raw lock backing an `RwLock` made by [`with_max_readers`](RwLockExt::with_max_readers).
+    type CappedRaw: lock_api::RawRwLock;
+
+    /// Create a new, unlocked lock holding the value `f` returns.
+    ///
+    /// `f` runs exactly once, here. This is `RwLock::new(f())`, for symmetry with the other lock
+    /// crates that have it:
+    ///
+    /// ```
+    /// use spinny::{RwLock, RwLockExt};
+    ///
+    /// let lock = RwLock::new_with(|| vec![0_u8; 16]);
+    /// assert_eq!(lock.read().len(), 16);
+    /// ```
+    fn new_with<F: FnOnce() -> T>(f: F) -> Self
+    where
+        Self: Sized,
+        T: Sized;
+
+    /// Create a new, unlocked lock holding `value`, which lets at most `max` readers hold it at
+    /// once.
+    ///
+    /// Once `max` readers hold the lock, `try_read` returns `None` and `read` spins. The cap lives
+    /// in a [`RawCappedRwSpinlock`], so the lock is of that type rather than of `Self`. See
+    /// [`RawRwSpinlock::with_max_readers`] for the details, including how a `max` that doesn't
+    /// fit is clamped.
+    ///
+    /// ```
+    /// use spinny::{CappedRwLock, RwLock, RwLockExt};
+    ///
+    /// let lock: CappedRwLock<_> = RwLock::with_max_readers(0, 2);
+    /// let guards = [lock.read(), lock.read()];
+    /// assert!(lock.try_read().is_none());
+    /// ```
+    fn with_max_readers(value: T, max: usize) -> LARwLock<Self::CappedRaw, T>
+    where
+        T: Sized;
+
+    /// Create a new, unlocked lock holding the data of a `std::sync::RwLock`.
+    ///
+    /// This is for migrating between the two gradually. A poisoned `lock` is taken as it is:
+    /// this lock has no notion of poisoning, so the data is moved across and the poison is
+    /// dropped. Check `is_poisoned` first to treat that case differently.
+    #[cfg(feature = "std")]
+    fn from_std(lock: std::sync::RwLock<T>) -> Self
+    where
+        Self: Sized,
+        T: Sized;
+
+    /// Consume the lock and move its data into a new, unpoisoned `std::sync::RwLock`.
+    #[cfg(feature = "std")]
+    fn into_std(self) -> std::sync::RwLock<T>
+    where
+        Self: Sized,
+        T: Sized;
+
+    /// Get the number of readers currently holding the lock.
+    ///
+    /// See [`RawRwSpinlock::reader_count`] for caveats; the result is only a snapshot.
+    fn reader_count(&self) -> usize;
+
+    /// Get the number of times an acquisition of this lock has had to spin.
+    ///
+    /// See [`RawRwSpinlock::contention_count`] for caveats.
+    #[cfg(feature = "metrics")]
+    fn contention_count(&self) -> u64;
+
+    /// Get a summary of how contended this lock has been, with its current state.
+    ///
+    /// See [`RawRwSpinlock::stats`] for caveats.
+    #[cfg(feature = "metrics")]
+    fn stats(&self) -> crate::LockStats;
+
+    /// Get the number of writers spinning while they wait for this lock.
+    ///
+    /// See [`RawRwSpinlock::waiting_writers`] for caveats.
+    #[cfg(feature = "metrics")]
+    fn waiting_writers(&self) -> usize;
+
+    /// Get the thread holding the write lock, or `None` if no thread does.
+    ///
+    /// See [`RawRwSpinlock::write_owner`] for how threads are identified, and for caveats.
+    #[cfg(feature = "owner-tracking")]
+    fn write_owner(&self) -> Option<core::num::NonZeroUsize>;
+
+    /// Spin until nobody holds the lock, without taking it.
+    ///
+    /// See [`RawRwSpinlock::wait_until_unlocked`] for caveats.
+    fn wait_until_unlocked(&self);
+
+    /// Panic in debug builds if anyone but the caller could hold the lock.
+    ///
+    /// See [`RawRwSpinlock::assert_unique_access`].
+    #[track_caller]
+    fn assert_unique_access(&self);
+
+    /// Put the lock back in its unlocked state, whatever state it is in.
+    ///
+    /// See [`RawRwSpinlock::reset`].
+    ///
+    /// # Safety
+    ///
+    /// No guard of this lock may be alive, and nobody may hold it or be trying to take it.
+    unsafe fn reset(&self);
+
+    /// Try to acquire a read lock, making at most `attempts` attempts.
+    ///
+    /// This tries a bit harder than `try_read`, with a `spin_loop` between attempts, but without
+    /// the clock `try_read_for` needs, so it also suits `no_std`. Returns `None` if every attempt
+    /// failed, and straight away if `attempts` is zero.
+    fn try_read_spin(&self, attempts: usize) -> Option<LARwLockReadGuard<'_, Self::Raw, T>>;
+
+    /// Try to acquire a write lock, making at most `attempts` attempts.
+    ///
+    /// See [`try_read_spin`](RwLockExt::try_read_spin).
+    fn try_write_spin(&self, attempts: usize) -> Option<LARwLockWriteGuard<'_, Self::Raw, T>>;
+
+    /// Acquire a read lock through an `Rc`, returning a guard that owns a clone of it.
+    ///
+    /// This is `RwLock::read_arc` for an `Rc<RwLock<T>>`, so the guard can outlive the borrow it
+    /// was taken through, such as by being stored in a struct. Unlike the `Arc` guards, it is
+    /// never `Send`; see [`RcRwLockReadGuard`].
+    #[cfg(feature = "alloc")]
+    fn read_rc(self: &Rc<Self>) -> RcRwLockReadGuard<T, Self::Raw>
+    where
+        Self: Sized;
+
+    /// Try to acquire a read lock through an `Rc` without spinning.
+    ///
+    /// See [`read_rc`](RwLockExt::read_rc).
+    #[cfg(feature = "alloc")]
+    fn try_read_rc(self: &Rc<Self>) -> Option<RcRwLockReadGuard<T, Self::Raw>>
+    where
+        Self: Sized;
+
+    /// Acquire a write lock through an `Rc`, returning a guard that owns a clone of it.
+    ///
+    /// See [`read_rc`](RwLockExt::read_rc).
+    #[cfg(feature = "alloc")]
+    fn write_rc(self: &Rc<Self>) -> RcRwLockWriteGuard<T, Self::Raw>
+    where
+        Self: Sized;
+
+    /// Try to acquire a write lock through an `Rc` without spinning.
+    ///
+    /// See [`read_rc`](RwLockExt::read_rc).
+    #[cfg(feature = "alloc")]
+    fn try_write_rc(self: &Rc<Self>) -> Option<RcRwLockWriteGuard<T, Self::Raw>>
+    where
+        Self: Sized;
+
+    /// Acquire a read lock, calling `on_spin` after every failed attempt instead of backing off.
+    ///
+    /// This is for cooperative runtimes that need to keep polling their other tasks while they
+    /// wait, rather than spinning. `on_spin` replaces the backoff entirely, so it should issue a
+    /// `spin_loop` itself if there is nothing better to do.
+    fn read_with_hint<F: FnMut()>(&self, on_spin: F) -> LARwLockReadGuard<'_, Self::Raw, T>;
+
+    /// Acquire a write lock, calling `on_spin` after every failed attempt instead of backing off.
+    ///
+    /// See [`read_with_hint`](RwLockExt::read_with_hint).
+    fn write_with_hint<F: FnMut()>(&self, on_spin: F) -> LARwLockWriteGuard<'_, Self::Raw, T>;
+
+    /// Try to acquire a read lock, spinning for at most `timeout`.
+    ///
+    /// This is `RwLock::try_read_for`, reporting a failure as a [`TryLockError`] instead of
+    /// `None`. It can't share that name, since inherent methods always take precedence.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TryLockError::Timeout`] if the lock could not be acquired before `timeout`
+    /// elapsed.
+    #[cfg(feature = "std")]
+    fn try_read_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<LARwLockReadGuard<'_, Self::Raw, T>, TryLockError>;
+
+    /// Try to acquire a write lock, spinning for at most `timeout`.
+    ///
+    /// This is `RwLock::try_write_for`, reporting a failure as a [`TryLockError`] instead of
+    /// `None`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TryLockError::Timeout`] if the lock could not be acquired before `timeout`
+    /// elapsed.
+    #[cfg(feature = "std")]
+    fn try_write_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<LARwLockWriteGuard<'_, Self::Raw, T>, TryLockError>;
+
+    /// Acquire a read lock without producing a guard.
+    ///
+    /// This is for code that can't keep a guard around between locking and unlocking, such as an
+    /// FFI callback that locks and another that unlocks. It spins just like `read`, and the lock
+    /// is held until a matching call to [`raw_unlock_shared`](RwLockExt::raw_unlock_shared).
+    ///
+    /// # Safety
+    ///
+    /// Every call must be matched by exactly one later call to `raw_unlock_shared`, and the data
+    /// may only be read, through `data_ptr`, in between. Forgetting to unlock blocks writers
+    /// forever.
+    unsafe fn raw_lock_shared(&self);
+
+    /// Release a read lock taken with [`raw_lock_shared`](RwLockExt::raw_lock_shared).
+    ///
+    /// # Safety
+    ///
+    /// The lock must be held for reading through `raw_lock_shared`, and no reference to the data
+    /// obtained under that lock may be used afterward. Unlocking a read lock held by a guard,
+    /// or one that isn't held at all, corrupts the lock's state.
+    unsafe fn raw_unlock_shared(&self);
+
+    /// Acquire a write lock without producing a guard.
+    ///
+    /// See [`raw_lock_shared`](RwLockExt::raw_lock_shared). The lock is held until a matching
+    /// call to [`raw_unlock_exclusive`](RwLockExt::raw_unlock_exclusive).
+    ///
+    /// # Safety
+    ///
+    /// Every call must be matched by exactly one later call to `raw_unlock_exclusive`, and the
+    /// data may only be accessed, through `data_ptr`, in between. Forgetting to unlock blocks
+    /// every other locker forever, and locking again on the same thread first never returns.
+    unsafe fn raw_lock_exclusive(&self);
+
+    /// Release a write lock taken with [`raw_lock_exclusive`](RwLockExt::raw_lock_exclusive).
+    ///
+    /// # Safety
+    ///
+    /// The lock must be held for writing through `raw_lock_exclusive`, and no reference to the
+    /// data obtained under that lock may be used afterward.
+    unsafe fn raw_unlock_exclusive(&self);
+
+    /// Run `f` with a read lock held, and release it as soon as `f` returns.
+    ///
+    /// The lock is also released if `f` panics.
+    fn with_read<R, F: FnOnce(&T) -> R>(&self, f: F) -> R;
+
+    /// Run `f` with a write lock held, and release it as soon as `f` returns.
+    ///
+    /// The lock is also released if `f` panics.
+    fn with_write<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R;
+
+    /// Run `f` with a read lock held, where the reference `f` is given can't escape the call.
+    ///
+    /// `f` must accept a reference of any lifetime, and `R` is chosen before that lifetime is,
+    /// so neither the reference nor anything borrowed from it can be returned or stored:
+    ///
+    /// ```compile_fail
+    /// use spinny::{RwLock, RwLockExt};
+    ///
+    /// let lock = RwLock::new(String::from("secret"));
+    /// let smuggled: &str = lock.read_scope(|value| value.as_str());
+    /// ```
+    ///
+    /// [`with_read`](RwLockExt::with_read) gives the same guarantee, since an elided lifetime in a
+    /// closure bound is just as higher-ranked. This spells the bound out in the signature, for
+    /// code that is audited for where references to locked data can end up.
+    fn read_scope<R, F>(&self, f: F) -> R
+    where
+        F: for<'a> FnOnce(&'a T) -> R;
+
+    /// Run `f` with a write lock held, where the reference `f` is given can't escape the call.
+    ///
+    /// See [`read_scope`](RwLockExt::read_scope).
+    ///
+    /// ```compile_fail
+    /// use spinny::{RwLock, RwLockExt};
+    ///
+    /// let lock = RwLock::new(0);
+    /// let smuggled: &mut i32 = lock.write_scope(|value| value);
+    /// ```
+    fn write_scope<R, F>(&self, f: F) -> R
+    where
+        F: for<'a> FnOnce(&'a mut T) -> R;
+
+    /// Update the data in place under a write lock, and return what `f` returns.
+    ///
+    /// This is [`with_write`](RwLockExt::with_write) under the name atomics use, for locks that
+    /// stand in for an atomic, such as `RwLock<u64>` counters:
+    ///
+    /// ```
+    /// use spinny::{RwLock, RwLockExt};
+    ///
+    /// let counter = RwLock::new(0_u64);
+    /// let previous = counter.update(|count| {
+    ///     *count += 1;
+    ///     *count - 1
+    /// });
+    /// assert_eq!(previous, 0);
+    /// assert_eq!(*counter.read(), 1);
+    /// ```
+    fn update<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R;
+
+    /// Put `value` in the lock under a write lock, and return the data it replaced.
+    ///
+    /// The write lock is held for the length of the swap and no longer, so this suits double
+    /// buffering: prepare the next buffer without the lock, then swap it in and get the old one
+    /// back to reuse. For a `RwLock<Box<_>>`, only the boxes are swapped, and nothing is
+    /// reallocated:
+    ///
+    /// ```
+    /// use spinny::{RwLock, RwLockExt};
+    ///
+    /// let front = RwLock::new(vec![0_u8; 4].into_boxed_slice());
+    /// let mut back = vec![1_u8; 4].into_boxed_slice();
+    /// back = front.replace(back);
+    /// assert_eq!(*back, [0; 4]);
+    /// assert_eq!(**front.read(), [1; 4]);
+    /// ```
+    fn replace(&self, value: T) -> T
+    where
+        T: Sized;
+
+    /// Replace the data with `f` of it, under a single write lock.
+    ///
+    /// `f` takes the data by value, so it can consume it rather than mutate it in place. To take
+    /// it out of the lock, `T::default()` is swapped in for the length of the call. If `f`
+    /// panics, the lock is released holding that default, so the data the panic interrupted is
+    /// never seen again. For a `T` without a cheap default, use [`update`](RwLockExt::update)
+    /// with `mem::replace` and a placeholder of your own.
+    ///
+    /// ```
+    /// use spinny::{RwLock, RwLockExt};
+    ///
+    /// let words = RwLock::new(vec!["b", "a"]);
+    /// words.map_in_place(|words| words.into_iter().rev().collect());
+    /// assert_eq!(*words.read(), ["a", "b"]);
+    /// ```
+    fn map_in_place<F: FnOnce(T) -> T>(&self, f: F)
+    where
+        T: Sized + Default;
+
+    /// Acquire a write lock only if `pred` holds for the data, returning `None` if it doesn't.
+    ///
+    /// `pred` runs under an upgradable read, which is then upgraded in place. No writer can get
+    /// in between the check and the upgrade, so the data the write guard sees is the data `pred`
+    /// approved. Plain readers can still share the lock while `pred` runs, but only one
+    /// `write_if` or upgradable reader runs at a time.
+    ///
+    /// ```
+    /// use spinny::{RwLock, RwLockExt};
+    ///
+    /// let state = RwLock::new((true, 0));
+    /// if let Some(mut guard) = state.write_if(|(dirty, _)| *dirty) {
+    ///     *guard = (false, guard.1 + 1);
+    /// }
+    /// assert!(state.write_if(|(dirty, _)| *dirty).is_none());
+    /// assert_eq!(*state.read(), (false, 1));
+    /// ```
+    #[cfg(feature = "upgrade")]
+    fn write_if<F: FnOnce(&T) -> bool>(
+        &self,
+        pred: F,
+    ) -> Option<LARwLockWriteGuard<'_, Self::Raw, T>>;
+
+    /// Empty the collection in the lock and collect its items into a `C`.
+    ///
+    /// The write lock is only held long enough to swap the collection for an empty one, so
+    /// collecting, and whatever processing happens afterward, runs without it. The lock is left
+    /// holding `T::default()`, so a `Vec` or `VecDeque` loses its capacity.
+    ///
+    /// ```
+    /// use spinny::{RwLock, RwLockExt};
+    /// use std::collections::VecDeque;
+    ///
+    /// let jobs = RwLock::new(VecDeque::from(vec!["a", "b"]));
+    /// for job in jobs.drain_collect::<Vec<_>>() {
+    ///     // process `job` without holding the lock
+    /// #   let _ = job;
+    /// }
+    /// assert!(jobs.read().is_empty());
+    /// ```
+    fn drain_collect<C>(&self) -> C
+    where
+        T: Default + IntoIterator + Sized,
+        C: FromIterator<T::Item>;
+
+    /// Clone the data into a new, independent lock.
+    ///
+    /// This is the `Clone` implementation `RwLock` can't have, since it is a `lock_api` type. The
+    /// new lock starts unlocked and shares no state with this one. Cloning takes a recursive read
+    /// lock for as long as `T::clone` runs, so it also works while this thread holds a read or
+    /// upgradable lock.
+    #[must_use]
+    fn clone_locked(&self) -> LARwLock<Self::Raw, T>
+    where
+        T: Clone;
+
+    /// Compare the data in two locks.
+    ///
+    /// This is the `PartialEq` implementation `RwLock` can't have, for the same reason as
+    /// [`clone_locked`](RwLockExt::clone_locked). It takes recursive read locks on both, so
+    /// comparing a lock with itself doesn't deadlock, and neither does comparing while this
+    /// thread holds a read or upgradable lock on either.
+    ///
+    /// It still blocks while another thread holds a write lock on either one. Don't call it while
+    /// this thread holds a write lock on either, or it will never return.
+    fn eq_locked<U: ?Sized>(&self, other: &LARwLock<Self::Raw, U>) -> bool
+    where
+        T: PartialEq<U>;
+
+    /// Get a wrapper whose `Debug` implementation reports the lock's live state.
+    ///
+    /// Unlike the `Debug` implementation on `RwLock` itself, this prints the reader count and
+    /// the writer and upgradable flags alongside the data. The data is read without blocking,
+    /// and is printed as `<locked>` if a writer holds the lock.
+    fn debug_state(&self) -> DebugState<'_, Self> {
+        DebugState(self)
+    }
+}
+
+/// Extra methods for read guards.
+pub trait RwLockReadGuardExt<'a, T: ?Sized> {
+    /// Forget the guard, leaving the lock held for reading forever, and return the data.
+    ///
+    /// This is one-way: writers can never acquire the lock again.
+    fn leak(guard: Self) -> &'a T;
+}
+
+/// Extra methods for write guards.
+pub trait RwLockWriteGuardExt<'a, T: ?Sized> {
+    /// Forget the guard, leaving the lock held for writing forever, and return the data.
+    ///
+    /// This is one-way: nobody else can ever acquire the lock again. It suits a value that is
+    /// initialized once and then never freed or shared.
+    fn leak(guard: Self) -> &'a mut T;
+}
+
+impl<'a, R: RawRwLock + 'a, T: ?Sized + 'a> RwLockReadGuardExt<'a, T>
+    for LARwLockReadGuard<'a, R, T>
+{
+    fn leak(guard: Self) -> &'a T {
+        let rwlock = LARwLockReadGuard::rwlock(&guard);
+        mem::forget(guard);
+        // SAFETY: the shared lock is never released, so no writer can ever get at the data
+        unsafe { &*rwlock.data_ptr() }
+    }
+}
+
+impl<'a, R: RawRwLock + 'a, T: ?Sized + 'a> RwLockWriteGuardExt<'a, T>
+    for LARwLockWriteGuard<'a, R, T>
+{
+    fn leak(guard: Self) -> &'a mut T {
+        let rwlock = LARwLockWriteGuard::rwlock(&guard);
+        mem::forget(guard);
+        // SAFETY: the exclusive lock is never released, so this is the only access to the data
+        unsafe { &mut *rwlock.data_ptr() }
+    }
+}
+
+/// A wrapper that reports the live state of a lock, returned by [`RwLockExt::debug_state`].
+pub struct DebugState<'a, L: ?Sized>(&'a L);
+
+impl<B: Backoff, O: OrderingPolicy, G, T: ?Sized + fmt::Debug> fmt::Debug
+    for DebugState<'_, LARwLock<RawRwSpinlock<B, O, G>, T>>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // SAFETY: we only inspect the lock's state and never unlock it
+        let state = unsafe { self.0.raw() };
+        let mut d = f.debug_struct("RwLock");
+        // a recursive read isn't turned away by an upgradable reader
+        match self.0.try_read_recursive() {
+            Some(guard) => d.field("data", &&*guard),
+            None => d.field("data", &format_args!("<locked>")),
+        };
+        d.field("state", state).finish()
+    }
+}
+
+impl<B: Backoff, O: OrderingPolicy, G, T: ?Sized> RwLockExt<T>
+    for LARwLock<RawRwSpinlock<B, O, G>, T>
+{
+    type Raw = RawRwSpinlock<B, O, G>;
+    type CappedRaw = RawCappedRwSpinlock<B, O, G>;
+
+    #[inline]
+    fn new_with<F: FnOnce() -> T>(f: F) -> Self
+    where
+        T: Sized,
+    {
+        LARwLock::new(f())
+    }
+
+    #[inline]
+    fn with_max_readers(value: T, max: usize) -> LARwLock<RawCappedRwSpinlock<B, O, G>, T>
+    where
+        T: Sized,
+    {
+        LARwLock::from_raw(RawRwSpinlock::with_max_readers(max), value)
+    }
+
+    #[cfg(feature = "std")]
+    #[inline]
+    fn from_std(lock: std::sync::RwLock<T>) -> Self
+    where
+        T: Sized,
+    {
+        LARwLock::new(lock.into_inner().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    #[cfg(feature = "std")]
+    #[inline]
+    fn into_std(self) -> std::sync::RwLock<T>
+    where
+        T: Sized,
+    {
+        std::sync::RwLock::new(self.into_inner())
+    }
+
+    #[inline]
+    fn reader_count(&self) -> usize {
+        // SAFETY: we only inspect the lock's state and never unlock it
+        unsafe { self.raw() }.reader_count()
+    }
+
+    #[inline]
+    fn wait_until_unlocked(&self) {
+        // SAFETY: we only inspect the lock's state and never unlock it
+        unsafe { self.raw() }.wait_until_unlocked();
+    }
+
+    #[inline]
+    fn assert_unique_access(&self) {
+        // SAFETY: we only inspect the lock's state and never unlock it
+        unsafe { self.raw() }.assert_unique_access();
+    }
+
+    #[inline]
+    unsafe fn reset(&self) {
+        self.raw().reset();
+    }
+
+    fn try_read_spin(&self, attempts: usize) -> Option<LARwLockReadGuard<'_, Self::Raw, T>> {
+        for attempt in 0..attempts {
+            if attempt != 0 {
+                spin_loop();
+            }
+            if let Some(guard) = self.try_read() {
+                return Some(guard);
+            }
+        }
+        None
+    }
+
+    fn try_write_spin(&self, attempts: usize) -> Option<LARwLockWriteGuard<'_, Self::Raw, T>> {
+        for attempt in 0..attempts {
+            if attempt != 0 {
+                spin_loop();
+            }
+            if let Some(guard) = self.try_write() {
+                return Some(guard);
+            }
+        }
+        None
+    }
+
+    #[cfg(feature = "alloc")]
+    #[inline]
+    fn read_rc(self: &Rc<Self>) -> RcRwLockReadGuard<T, Self::Raw> {
+        // SAFETY: the guard takes over the read lock taken here
+        unsafe {
+            self.raw().lock_shared();
+            RcRwLockReadGuard::new(self.clone())
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[inline]
+    fn try_read_rc(self: &Rc<Self>) -> Option<RcRwLockReadGuard<T, Self::Raw>> {
+        // SAFETY: the guard takes over the read lock, if one was taken
+        unsafe {
+            if self.raw().try_lock_shared() {
+                Some(RcRwLockReadGuard::new(self.clone()))
+            } else {
+                None
+            }
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[inline]
+    fn write_rc(self: &Rc<Self>) -> RcRwLockWriteGuard<T, Self::Raw> {
+        // SAFETY: the guard takes over the write lock taken here
+        unsafe {
+            self.raw().lock_exclusive();
+            RcRwLockWriteGuard::new(self.clone())
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[inline]
+    fn try_write_rc(self: &Rc<Self>) -> Option<RcRwLockWriteGuard<T, Self::Raw>> {
+        // SAFETY: the guard takes over the write lock, if one was taken
+        unsafe {
+            if self.raw().try_lock_exclusive() {
+                Some(RcRwLockWriteGuard::new(self.clone()))
+            } else {
+                None
+            }
+        }
+    }
+
+    fn read_with_hint<F: FnMut()>(&self, mut on_spin: F) -> LARwLockReadGuard<'_, Self::Raw, T> {
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            on_spin();
+        }
+    }
+
+    fn write_with_hint<F: FnMut()>(&self, mut on_spin: F) -> LARwLockWriteGuard<'_, Self::Raw, T> {
+        loop {
+            if let Some(guard) = self.try_write() {
+                return guard;
+            }
+            on_spin();
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[inline]
+    fn try_read_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<LARwLockReadGuard<'_, Self::Raw, T>, TryLockError> {
+        self.try_read_for(timeout).ok_or(TryLockError::Timeout)
+    }
+
+    #[cfg(feature = "std")]
+    #[inline]
+    fn try_write_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<LARwLockWriteGuard<'_, Self::Raw, T>, TryLockError> {
+        self.try_write_for(timeout).ok_or(TryLockError::Timeout)
+    }
+
+    #[inline]
+    unsafe fn raw_lock_shared(&self) {
+        self.raw().lock_shared();
+    }
+
+    #[inline]
+    unsafe fn raw_unlock_shared(&self) {
+        self.raw().unlock_shared();
+    }
+
+    #[inline]
+    unsafe fn raw_lock_exclusive(&self) {
+        self.raw().lock_exclusive();
+    }
+
+    #[inline]
+    unsafe fn raw_unlock_exclusive(&self) {
+        self.raw().unlock_exclusive();
+    }
+
+    #[inline]
+    fn with_read<R, F: FnOnce(&T) -> R>(&self, f: F) -> R {
+        f(&self.read())
+    }
+
+    #[inline]
+    fn with_write<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R {
+        f(&mut self.write())
+    }
+
+    #[inline]
+    fn update<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R {
+        self.with_write(f)
+    }
+
+    #[inline]
+    fn replace(&self, value: T) -> T
+    where
+        T: Sized,
+    {
+        mem::replace(&mut *self.write(), value)
+    }
+
+    #[inline]
+    fn map_in_place<F: FnOnce(T) -> T>(&self, f: F)
+    where
+        T: Sized + Default,
+    {
+        let mut guard = self.write();
+        let value = mem::take(&mut *guard);
+        *guard = f(value);
+    }
+
+    #[cfg(feature = "upgrade")]
+    #[inline]
+    fn write_if<F: FnOnce(&T) -> bool>(
+        &self,
+        pred: F,
+    ) -> Option<LARwLockWriteGuard<'_, Self::Raw, T>> {
+        let guard = self.upgradable_read();
+        if pred(&guard) {
+            Some(LARwLockUpgradableReadGuard::upgrade(guard))
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn read_scope<R, F>(&self, f: F) -> R
+    where
+        F: for<'a> FnOnce(&'a T) -> R,
+    {
+        f(&self.read())
+    }
+
+    #[inline]
+    fn write_scope<R, F>(&self, f: F) -> R
+    where
+        F: for<'a> FnOnce(&'a mut T) -> R,
+    {
+        f(&mut self.write())
+    }
+
+    #[inline]
+    fn drain_collect<C>(&self) -> C
+    where
+        T: Default + IntoIterator,
+        C: FromIterator<T::Item>,
+    {
+        let items = mem::take(&mut *self.write());
+        items.into_iter().collect()
+    }
+
+    #[inline]
+    fn clone_locked(&self) -> LARwLock<Self::Raw, T>
+    where
+        T: Clone,
+    {
+        LARwLock::new(self.read_recursive().clone())
+    }
+
+    #[inline]
+    fn eq_locked<U: ?Sized>(&self, other: &LARwLock<Self::Raw, U>) -> bool
+    where
+        T: PartialEq<U>,
+    {
+        *self.read_recursive() == *other.read_recursive()
+    }
+
+    #[cfg(feature = "metrics")]
+    #[inline]
+    fn contention_count(&self) -> u64 {
+        // SAFETY: we only inspect the lock's state and never unlock it
+        unsafe { self.raw() }.contention_count()
+    }
+
+    #[cfg(feature = "metrics")]
+    #[inline]
+    fn stats(&self) -> crate::LockStats {
+        // SAFETY: we only inspect the lock's state and never unlock it
+        unsafe { self.raw() }.stats()
+    }
+
+    #[cfg(feature = "metrics")]
+    #[inline]
+    fn waiting_writers(&self) -> usize {
+        // SAFETY: we only inspect the lock's state and never unlock it
+        unsafe { self.raw() }.waiting_writers()
+    }
+
+    #[cfg(feature = "owner-tracking")]
+    #[inline]
+    fn write_owner(&self) -> Option<core::num::NonZeroUsize> {
+        // SAFETY: we only inspect the lock's state and never unlock it
+        unsafe { self.raw() }.write_owner()
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::RwLockExt;
+    use crate::RwLock;
+    use core::mem;
+    use std::{
+        boxed::Box,
+        collections::BTreeSet,
+        format,
+        panic::{catch_unwind, AssertUnwindSafe},
+        string::String,
+        vec,
+        vec::Vec,
+    };
+
+    #[test]
+    fn new_with() {
+        let mut calls = 0;
+        let rwlock = RwLock::new_with(|| {
+            calls += 1;
+            7
+        });
+        assert_eq!(calls, 1);
+        assert!(!rwlock.is_locked());
+        assert_eq!(rwlock.into_inner(), 7);
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn reader_count() {
+        let rwlock = RwLock::new(());
+        assert_eq!(rwlock.reader_count(), 0);
+
+        let guards = [rwlock.read(), rwlock.read(), rwlock.read()];
+        assert_eq!(rwlock.reader_count(), 3);
+
+        drop(guards);
+        assert_eq!(rwlock.reader_count(), 0);
+
+        let _guard = rwlock.write();
+        assert_eq!(rwlock.reader_count(), 0);
+    }
+
+    #[test]
+    fn max_readers() {
+        use crate::CappedRwLock;
+
+        let rwlock = RwLock::with_max_readers(5, 2);
+        let raw = unsafe { rwlock.raw() };
+        assert_eq!(raw.max_readers(), 2);
+        let guards = [rwlock.read(), rwlock.read()];
+        assert_eq!(raw.reader_count(), 2);
+        assert!(rwlock.try_read().is_none());
+        assert!(rwlock.try_read_recursive().is_none());
+        assert_eq!(raw.reader_count(), 2);
+
+        drop(guards);
+        assert!(rwlock.try_read().is_some());
+        assert_eq!(*rwlock.write(), 5);
+
+        let max = unsafe { RwLock::with_max_readers((), usize::MAX).raw() }.max_readers();
+        assert_eq!(max, unsafe { CappedRwLock::new(()).raw() }.max_readers());
+        let rwlock = RwLock::with_max_readers((), 0);
+        assert!(rwlock.try_read().is_none());
+        assert!(rwlock.try_write().is_some());
+    }
+
+    // test that a lock whose guards were leaked can be reset and used again
+    #[cfg(feature = "upgrade")]
+    #[test]
+    fn reset() {
+        let rwlock = RwLock::new(0);
+        mem::forget(rwlock.read());
+        mem::forget(rwlock.upgradable_read());
+        assert!(rwlock.try_write().is_none());
+        unsafe { rwlock.reset() };
+        assert!(!rwlock.is_locked());
+        *rwlock.write() += 1;
+
+        mem::forget(rwlock.write());
+        unsafe {
+            rwlock.force_unlock_write();
+            rwlock.reset();
+        }
+        assert_eq!(rwlock.reader_count(), 0);
+        let guards = [rwlock.read(), rwlock.read()];
+        assert_eq!(*guards[1], 1);
+        drop(guards);
+        assert!(rwlock.try_upgradable_read().is_some());
+        assert_eq!(*rwlock.write(), 1);
+    }
+
+    #[test]
+    fn wait_until_unlocked() {
+        use std::{
+            sync::atomic::{AtomicBool, Ordering},
+            thread,
+            time::Duration,
+        };
+
+        let rwlock = RwLock::new(0);
+        rwlock.wait_until_unlocked();
+
+        let released = AtomicBool::new(false);
+        let guard = rwlock.write();
+        thread::scope(|s| {
+            let waiter = s.spawn(|| {
+                rwlock.wait_until_unlocked();
+                assert!(released.load(Ordering::Relaxed));
+            });
+            thread::sleep(Duration::from_millis(20));
+            assert!(!waiter.is_finished());
+
+            released.store(true, Ordering::Relaxed);
+            drop(guard);
+        });
+        assert!(!rwlock.is_locked());
+    }
+
+    #[test]
+    fn try_spin() {
+        use std::{thread, time::Duration};
+
+        let rwlock = RwLock::new(0);
+        assert!(rwlock.try_write_spin(0).is_none());
+        *rwlock.try_write_spin(1).unwrap() += 1;
+
+        let guard = rwlock.write();
+        assert!(rwlock.try_write_spin(10).is_none());
+        assert!(rwlock.try_read_spin(10).is_none());
+
+        // a lock that is only held briefly is taken once it is released
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                drop(guard);
+            });
+            assert_eq!(*rwlock.try_read_spin(usize::MAX).unwrap(), 1);
+        });
+
+        let guard = rwlock.read();
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                drop(guard);
+            });
+            assert!(rwlock.try_write_spin(usize::MAX).is_some());
+        });
+    }
+
+    // test locking in one function and unlocking in another, as FFI callbacks would
+    #[test]
+    fn raw_lock() {
+        fn begin_write(rwlock: &RwLock<i32>) {
+            unsafe { rwlock.raw_lock_exclusive() };
+        }
+        fn end_write(rwlock: &RwLock<i32>) {
+            unsafe { rwlock.raw_unlock_exclusive() };
+        }
+        fn begin_read(rwlock: &RwLock<i32>) {
+            unsafe { rwlock.raw_lock_shared() };
+        }
+        fn end_read(rwlock: &RwLock<i32>) {
+            unsafe { rwlock.raw_unlock_shared() };
+        }
+
+        let rwlock = RwLock::new(0);
+        begin_write(&rwlock);
+        assert!(rwlock.is_locked_exclusive());
+        assert!(rwlock.try_read().is_none());
+        unsafe { *rwlock.data_ptr() += 1 };
+        end_write(&rwlock);
+        assert!(!rwlock.is_locked());
+
+        begin_read(&rwlock);
+        begin_read(&rwlock);
+        assert_eq!(rwlock.reader_count(), 2);
+        assert!(rwlock.try_write().is_none());
+        assert_eq!(unsafe { *rwlock.data_ptr() }, 1);
+        end_read(&rwlock);
+        end_read(&rwlock);
+        assert!(rwlock.try_write().is_some());
+    }
+
+    #[test]
+    fn with_lock() {
+        let rwlock = RwLock::new(1);
+        assert_eq!(rwlock.with_read(|value| *value + 1), 2);
+        assert!(rwlock.try_write().is_some());
+
+        rwlock.with_write(|value| *value = 3);
+        assert!(rwlock.try_write().is_some());
+        assert_eq!(*rwlock.read(), 3);
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            rwlock.with_write(|_| panic!("closure failed"));
+        }));
+        assert!(result.is_err());
+        assert!(rwlock.try_write().is_some());
+    }
+
+    #[test]
+    fn scope() {
+        let rwlock = RwLock::new(String::from("abc"));
+        assert_eq!(rwlock.read_scope(String::len), 3);
+        rwlock.write_scope(|value| value.push('d'));
+        assert!(rwlock.try_write().is_some());
+        assert_eq!(rwlock.read_scope(String::clone), "abcd");
+    }
+
+    #[test]
+    fn map_in_place() {
+        let list = RwLock::new(vec![3, 1, 2]);
+        list.map_in_place(|mut list| {
+            list.sort_unstable();
+            list
+        });
+        assert!(list.try_write().is_some());
+        assert_eq!(*list.read(), [1, 2, 3]);
+
+        let name = RwLock::new(String::from("old"));
+        name.map_in_place(|name| name + "er");
+        assert_eq!(*name.read(), "older");
+
+        // a panic leaves the default behind, and doesn't keep the lock
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            name.map_in_place(|_| panic!("closure failed"));
+        }));
+        assert!(result.is_err());
+        assert!(name.try_write().is_some());
+        assert_eq!(*name.read(), "");
+        name.map_in_place(|_| String::from("new"));
+        assert_eq!(name.into_inner(), "new");
+    }
+
+    #[test]
+    fn update_replace() {
+        let counter = RwLock::new(5_u64);
+        assert_eq!(counter.update(|count| mem::replace(count, *count * 2)), 5);
+        assert_eq!(*counter.read(), 10);
+        assert_eq!(counter.replace(1), 10);
+        assert!(counter.try_write().is_some());
+
+        // swapping boxes moves the buffers without copying them
+        let front: Box<[u8]> = vec![0; 8].into_boxed_slice();
+        let front_ptr = front.as_ptr();
+        let buffer = RwLock::new(front);
+        let back = buffer.replace(vec![1; 8].into_boxed_slice());
+        assert_eq!(back.as_ptr(), front_ptr);
+        assert_eq!(*back, [0; 8]);
+        assert_eq!(**buffer.read(), [1; 8]);
+
+        let name = RwLock::new(String::from("old"));
+        assert_eq!(name.replace(String::from("new")), "old");
+        assert_eq!(name.update(|name| name.len()), 3);
+        assert_eq!(*name.read(), "new");
+    }
+
+    #[test]
+    fn drain_collect() {
+        let rwlock = RwLock::new(vec![1, 2, 3]);
+        let drained: Vec<i32> = rwlock.drain_collect();
+        assert_eq!(drained, [1, 2, 3]);
+        assert!(rwlock.read().is_empty());
+        assert!(rwlock.try_write().is_some());
+
+        rwlock.write().push(4);
+        assert_eq!(rwlock.drain_collect::<BTreeSet<_>>().len(), 1);
+        assert!(rwlock.drain_collect::<Vec<_>>().is_empty());
+    }
+
+    // test that a write_if guard always sees the data its predicate approved, under contention
+    #[cfg(feature = "upgrade")]
+    #[test]
+    fn write_if() {
+        use std::{
+            sync::atomic::{AtomicUsize, Ordering},
+            thread,
+        };
+
+        let rwlock = RwLock::new((false, 0));
+        assert!(rwlock.write_if(|(dirty, _)| *dirty).is_none());
+
+        let (skipped, marked) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for _ in 0..200 {
+                        let mut guard = rwlock.write();
+                        if !guard.0 {
+                            guard.0 = true;
+                            marked.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                });
+                s.spawn(|| {
+                    for _ in 0..200 {
+                        if let Some(mut guard) = rwlock.write_if(|(dirty, _)| *dirty) {
+                            assert!(guard.0, "the data changed between the check and the write");
+                            *guard = (false, guard.1 + 1);
+                        } else {
+                            skipped.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+
+        let (dirty, cleaned) = *rwlock.read();
+        assert_eq!(cleaned + usize::from(dirty), marked.into_inner());
+        assert_eq!(cleaned + skipped.into_inner(), 400);
+    }
+
+    #[cfg(feature = "upgrade")]
+    #[test]
+    fn clone_locked() {
+        let rwlock = RwLock::new(vec![1, 2, 3]);
+        let clone = rwlock.clone_locked();
+        rwlock.write().push(4);
+        assert_eq!(*rwlock.read(), [1, 2, 3, 4]);
+        assert_eq!(*clone.read(), [1, 2, 3]);
+
+        let _guard = rwlock.upgradable_read();
+        assert_eq!(*rwlock.clone_locked().read(), [1, 2, 3, 4]);
+        assert!(!clone.is_locked());
+    }
+
+    #[cfg(feature = "upgrade")]
+    #[test]
+    fn eq_locked() {
+        let rwlock = RwLock::new(1);
+        assert!(rwlock.eq_locked(&RwLock::new(1)));
+        assert!(!rwlock.eq_locked(&RwLock::new(2)));
+
+        assert!(rwlock.eq_locked(&rwlock));
+        let _guard = rwlock.upgradable_read();
+        assert!(rwlock.eq_locked(&rwlock));
+        assert!(!RwLock::new(f64::NAN).eq_locked(&RwLock::new(f64::NAN)));
+    }
+
+    #[test]
+    fn with_hint() {
+        use std::{sync::Barrier, thread};
+
+        let rwlock = RwLock::new(0);
+        let mut spins = 0;
+        assert_eq!(*rwlock.read_with_hint(|| spins += 1), 0);
+        *rwlock.write_with_hint(|| spins += 1) += 1;
+        assert_eq!(spins, 0);
+
+        // the holder waits for the first spin before releasing, so the hint runs at least once
+        let barrier = Barrier::new(2);
+        thread::scope(|s| {
+            let guard = rwlock.write();
+            s.spawn(|| {
+                barrier.wait();
+                drop(guard);
+            });
+            let mut spins = 0;
+            let value = *rwlock.read_with_hint(|| {
+                if spins == 0 {
+                    barrier.wait();
+                }
+                spins += 1;
+            });
+            assert_eq!(value, 1);
+            assert!(spins >= 1);
+
+            let guard = rwlock.read();
+            s.spawn(|| {
+                barrier.wait();
+                drop(guard);
+            });
+            let mut spins = 0;
+            *rwlock.write_with_hint(|| {
+                if spins == 0 {
+                    barrier.wait();
+                }
+                spins += 1;
+            }) += 1;
+            assert!(spins >= 1);
+        });
+        assert_eq!(rwlock.into_inner(), 2);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn std_conversion() {
+        use std::{sync::Arc, thread};
+
+        let rwlock = RwLock::new(vec![1, 2]);
+        let std_lock = rwlock.into_std();
+        std_lock.write().unwrap().push(3);
+        let rwlock = RwLock::from_std(std_lock);
+        assert_eq!(*rwlock.read(), [1, 2, 3]);
+        assert!(!rwlock.is_locked());
+
+        // poison is dropped along the way
+        let std_lock = Arc::new(std::sync::RwLock::new(String::from("kept")));
+        let sclone = std_lock.clone();
+        let result = thread::spawn(move || {
+            let _guard = sclone.write().unwrap();
+            panic!("poisoning the std lock");
+        })
+        .join();
+        assert!(result.is_err());
+        let std_lock = Arc::try_unwrap(std_lock).unwrap();
+        assert!(std_lock.is_poisoned());
+        assert_eq!(RwLock::from_std(std_lock).into_inner(), "kept");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn try_timeout() {
+        use crate::TryLockError;
+        use std::time::Duration;
+
+        let rwlock = RwLock::new(0);
+        assert!(rwlock.try_read_timeout(Duration::from_millis(10)).is_ok());
+        let guard = rwlock.try_write_timeout(Duration::from_millis(10)).unwrap();
+
+        assert_eq!(
+            rwlock
+                .try_read_timeout(Duration::from_millis(10))
+                .unwrap_err(),
+            TryLockError::Timeout
+        );
+        assert_eq!(
+            rwlock
+                .try_write_timeout(Duration::from_millis(10))
+                .unwrap_err(),
+            TryLockError::Timeout
+        );
+        drop(guard);
+    }
+
+    #[test]
+    fn leak() {
+        use super::{RwLockReadGuardExt, RwLockWriteGuardExt};
+        use crate::{RwLockReadGuard, RwLockWriteGuard};
+
+        let rwlock = RwLock::new(1);
+        let value = RwLockWriteGuard::leak(rwlock.write());
+        *value += 1;
+        assert_eq!(*value, 2);
+        assert!(rwlock.is_locked_exclusive());
+        assert!(rwlock.try_read().is_none());
+
+        let rwlock = RwLock::new(1);
+        let value = RwLockReadGuard::leak(rwlock.read());
+        assert_eq!(*value, 1);
+        assert_eq!(*rwlock.read(), 1);
+        assert!(rwlock.try_write().is_none());
+    }
+
+    #[test]
+    fn debug_state() {
+        let rwlock = RwLock::new(5);
+        assert_eq!(
+            format!("{:?}", rwlock.debug_state()),
+            "RwLock { data: 5, state: RawRwSpinlock { readers: 0, writer: false, upgraded: false } }"
+        );
+
+        #[cfg(feature = "upgrade")]
+        {
+            let guard = rwlock.upgradable_read();
+            assert_eq!(
+                format!("{:?}", rwlock.debug_state()),
+                "RwLock { data: 5, state: RawRwSpinlock { readers: 0, writer: false, upgraded: true } }"
+            );
+            drop(guard);
+        }
+
+        let _guard = rwlock.write();
+        assert_eq!(
+            format!("{:?}", rwlock.debug_state()),
+            "RwLock { data: <locked>, state: RawRwSpinlock { readers: 0, writer: true, upgraded: false } }"
+        );
+    }
+}
@@ -0,0 +1,334 @@
+// MIT/Apache2 License
+
+//! A variant of the spinlock rwlock that serves writers in the order they arrive.
+
+use super::{atomic::AtomicUsize, Backoff, DefaultBackoff, Ordering, RawRwSpinlock};
+use core::fmt;
+#[cfg(feature = "upgrade")]
+use lock_api::RawRwLockDowngrade;
+use lock_api::{GuardSend, RawRwLock, RawRwLockFair, RawRwLockRecursive, RwLock as LARwLock};
+
+#[cfg(loom)]
+use crate::loom_lazy::LoomLazy;
+
+// Writers take the `next` ticket and wait for `serving` to reach it. Both wrap around, and only
+// their difference matters.
+struct Tickets {
+    next: AtomicUsize,
+    serving: AtomicUsize,
+}
+
+impl Tickets {
+    #[cfg(not(loom))]
+    const fn new() -> Self {
+        Tickets {
+            next: AtomicUsize::new(0),
+            serving: AtomicUsize::new(0),
+        }
+    }
+
+    // loom's atomics can't be created in a `const fn`
+    #[cfg(loom)]
+    fn new() -> Self {
+        Tickets {
+            next: AtomicUsize::new(0),
+            serving: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// Raw spinlock rwlock that queues writers, wrapped in the `lock_api` `RwLock` struct.
+///
+/// Writers contending on a [`RawRwSpinlock`](crate::RawRwSpinlock) race for every release, so an
+/// unlucky one can lose indefinitely. Here, each blocking writer takes a ticket and waits for its
+/// turn, so writers are served first-come, first-served and a writer never waits for more than
+/// the writers that arrived before it. Readers share the lock freely as long as no writer holds a
+/// ticket, and new readers are turned away while one does.
+///
+/// Recursive reads ignore the queue, so a thread that already holds a read lock can take another
+/// one without deadlocking against a queued writer. `try_write` only succeeds when no writer is
+/// queued; it never jumps the queue. Bumping a write guard puts its writer at the back of the
+/// queue.
+///
+/// The queue has nowhere to put an upgradable reader, so this lock has no upgradable reads.
+pub struct RawFairRwSpinlock<B: Backoff = DefaultBackoff> {
+    inner: RawRwSpinlock<B>,
+    #[cfg(not(loom))]
+    tickets: Tickets,
+    #[cfg(loom)]
+    tickets: LoomLazy<Tickets>,
+}
+
+#[cfg(not(loom))]
+impl<B: Backoff> RawFairRwSpinlock<B> {
+    #[inline]
+    fn tickets(&self) -> &Tickets {
+        &self.tickets
+    }
+}
+
+#[cfg(loom)]
+impl<B: Backoff> RawFairRwSpinlock<B> {
+    #[inline]
+    fn tickets(&self) -> &Tickets {
+        self.tickets.get_or_init(Tickets::new)
+    }
+}
+
+impl<B: Backoff> RawFairRwSpinlock<B> {
+    /// Get the number of writers that hold a ticket, including one that holds the lock.
+    ///
+    /// This is a snapshot taken with relaxed loads, for debugging.
+    #[must_use]
+    pub fn queued_writers(&self) -> usize {
+        let tickets = self.tickets();
+        let serving = tickets.serving.load(Ordering::Relaxed);
+        tickets.next.load(Ordering::Relaxed).wrapping_sub(serving)
+    }
+
+    /// Let the writer with the next ticket in.
+    #[inline]
+    fn next_writer(&self) {
+        self.tickets().serving.fetch_add(1, Ordering::Release);
+    }
+}
+
+impl<B: Backoff> fmt::Debug for RawFairRwSpinlock<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawFairRwSpinlock")
+            .field("lock", &self.inner)
+            .field("queued_writers", &self.queued_writers())
+            .finish_non_exhaustive()
+    }
+}
+
+unsafe impl<B: Backoff> RawRwLock for RawFairRwSpinlock<B> {
+    const INIT: RawFairRwSpinlock<B> = RawFairRwSpinlock {
+        inner: RawRwSpinlock::INIT,
+        #[cfg(not(loom))]
+        tickets: Tickets::new(),
+        #[cfg(loom)]
+        tickets: LoomLazy::new(),
+    };
+
+    type GuardMarker = GuardSend;
+
+    fn lock_shared(&self) {
+        let mut backoff = B::new();
+        while !self.try_lock_shared() {
+            backoff.spin();
+        }
+    }
+
+    fn try_lock_shared(&self) -> bool {
+        // the queue is only advisory, since the inner lock is what keeps readers and writers apart
+        self.queued_writers() == 0 && self.inner.try_lock_shared()
+    }
+
+    fn try_lock_exclusive(&self) -> bool {
+        let tickets = self.tickets();
+        let serving = tickets.serving.load(Ordering::Acquire);
+        if tickets
+            .next
+            .compare_exchange(
+                serving,
+                serving.wrapping_add(1),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            return false;
+        }
+
+        // we are being served, but readers may still hold the lock
+        if self.inner.try_lock_exclusive() {
+            true
+        } else {
+            self.next_writer();
+            false
+        }
+    }
+
+    fn lock_exclusive(&self) {
+        let tickets = self.tickets();
+        let ticket = tickets.next.fetch_add(1, Ordering::Relaxed);
+
+        let mut backoff = B::new();
+        while tickets.serving.load(Ordering::Acquire) != ticket {
+            backoff.spin();
+        }
+
+        // other writers are waiting their turn and new readers are turned away, so this only
+        // waits for the readers already holding the lock
+        self.inner.lock_exclusive();
+    }
+
+    unsafe fn unlock_shared(&self) {
+        self.inner.unlock_shared();
+    }
+
+    unsafe fn unlock_exclusive(&self) {
+        self.inner.unlock_exclusive();
+        self.next_writer();
+    }
+
+    fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    fn is_locked_exclusive(&self) -> bool {
+        self.inner.is_locked_exclusive()
+    }
+}
+
+// A fair unlock is a regular unlock, since writers are always handed the lock in order. Bumping
+// a write lock takes a new ticket, so every writer that queued up in the meantime goes first.
+unsafe impl<B: Backoff> RawRwLockFair for RawFairRwSpinlock<B> {
+    unsafe fn unlock_shared_fair(&self) {
+        self.unlock_shared();
+    }
+
+    unsafe fn unlock_exclusive_fair(&self) {
+        self.unlock_exclusive();
+    }
+
+    unsafe fn bump_shared(&self) {
+        if self.queued_writers() != 0 {
+            self.unlock_shared();
+            self.lock_shared();
+        }
+    }
+
+    unsafe fn bump_exclusive(&self) {
+        if self.queued_writers() > 1 {
+            self.unlock_exclusive();
+            self.lock_exclusive();
+        }
+    }
+}
+
+unsafe impl<B: Backoff> RawRwLockRecursive for RawFairRwSpinlock<B> {
+    fn lock_shared_recursive(&self) {
+        self.inner.lock_shared_recursive();
+    }
+
+    fn try_lock_shared_recursive(&self) -> bool {
+        self.inner.try_lock_shared_recursive()
+    }
+}
+
+#[cfg(feature = "upgrade")]
+unsafe impl<B: Backoff> RawRwLockDowngrade for RawFairRwSpinlock<B> {
+    unsafe fn downgrade(&self) {
+        self.inner.downgrade();
+        self.next_writer();
+    }
+}
+
+/// A read-write lock that uses a spinlock internally and serves writers in order.
+pub type FairRwLock<T> = LARwLock<RawFairRwSpinlock, T>;
+
+#[cfg(test)]
+mod tests {
+    use super::FairRwLock;
+
+    #[cfg(loom)]
+    use loom::thread;
+    #[cfg(not(loom))]
+    use std::thread;
+
+    use std::{sync::Arc, vec::Vec};
+
+    #[cfg(all(not(loom), feature = "upgrade"))]
+    #[test]
+    fn basics() {
+        let rwlock = FairRwLock::new(0);
+        let first = rwlock.read();
+        let second = rwlock.read();
+        assert!(rwlock.try_write().is_none());
+        drop((first, second));
+
+        let mut guard = rwlock.try_write().unwrap();
+        assert_eq!(unsafe { rwlock.raw() }.queued_writers(), 1);
+        assert!(rwlock.try_write().is_none());
+        assert!(rwlock.try_read().is_none());
+        *guard += 1;
+        let guard = lock_api::RwLockWriteGuard::downgrade(guard);
+        assert_eq!(unsafe { rwlock.raw() }.queued_writers(), 0);
+        assert!(rwlock.try_read().is_some());
+        drop(guard);
+
+        assert_eq!(unsafe { rwlock.raw() }.queued_writers(), 0);
+        assert_eq!(rwlock.into_inner(), 1);
+    }
+
+    // test that writers are let in in the order they queued up, and readers wait behind them
+    #[cfg(not(loom))]
+    #[test]
+    fn fifo() {
+        use std::sync::Mutex;
+
+        let rwlock = FairRwLock::new(());
+        let order = Mutex::new(Vec::new());
+        let raw = unsafe { rwlock.raw() };
+
+        let guard = rwlock.write();
+        thread::scope(|s| {
+            for i in 0..3 {
+                let (rwlock, order) = (&rwlock, &order);
+                s.spawn(move || {
+                    let _guard = rwlock.write();
+                    order.lock().unwrap().push(i);
+                });
+                while raw.queued_writers() != i + 2 {
+                    thread::yield_now();
+                }
+            }
+
+            assert!(rwlock.try_read().is_none());
+            drop(guard);
+        });
+
+        assert_eq!(*order.lock().unwrap(), [0, 1, 2]);
+        assert!(rwlock.try_read().is_some());
+    }
+
+    // test that three contending writers all get the lock
+    fn writers_kernel() {
+        let rwlock = Arc::new(FairRwLock::new(Vec::new()));
+        let mut joiners = Vec::new();
+        for i in 0..2 {
+            let rclone = rwlock.clone();
+            joiners.push(thread::spawn(move || {
+                rclone.write().push(i);
+            }));
+        }
+
+        rwlock.write().push(2);
+        for j in joiners {
+            j.join().unwrap();
+        }
+
+        let mut writers = rwlock.read().clone();
+        writers.sort_unstable();
+        assert_eq!(writers, [0, 1, 2]);
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn writers() {
+        // three spinning threads are too many for loom to explore exhaustively
+        let mut builder = loom::model::Builder::new();
+        builder.preemption_bound = Some(1);
+        builder.check(|| writers_kernel());
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn writers() {
+        for _ in 0..if cfg!(miri) { 5 } else { 100 } {
+            writers_kernel();
+        }
+    }
+}
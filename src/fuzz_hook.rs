@@ -0,0 +1,163 @@
+// MIT/Apache2 License
+
+//! A hook run at every spin point, enabled by the `fuzz-hook` feature, and a per-thread step that
+//! replaces the spin hint, enabled by the `test-spin-deterministic` feature.
+
+// A plain `core` atomic even under loom, since it has to live in a `static`.
+use core::{
+    mem, ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+#[cfg(all(feature = "test-spin-deterministic", not(loom)))]
+use std::cell::Cell;
+
+// a `fn()` cast to a pointer, or null for no hook
+static HOOK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+#[cfg(all(feature = "test-spin-deterministic", not(loom)))]
+std::thread_local! {
+    static STEP: Cell<Option<fn()>> = const { Cell::new(None) };
+}
+
+/// Run `hook` every time a lock in this crate spins while waiting.
+///
+/// This is for testing only. A fuzzer can use it to drive interleavings deterministically outside
+/// of loom, by yielding, sleeping or parking the current thread from inside the hook according to
+/// its input. It applies to every lock in the program, and replaces any hook already installed.
+/// The hook runs just before each spin hint, so a waiting thread calls it over and over; it must
+/// not take any of this crate's locks, or it may spin forever.
+///
+/// By default there is no hook, and the spin points only cost the check for one.
+pub fn set_spin_hook(hook: fn()) {
+    HOOK.store(hook as *mut (), Ordering::Release);
+}
+
+/// Remove the hook installed with [`set_spin_hook`], if any.
+pub fn clear_spin_hook() {
+    HOOK.store(ptr::null_mut(), Ordering::Release);
+}
+
+/// Replace the spin hint with `step` whenever a lock spins on the current thread.
+///
+/// This is for tests that need to know exactly how many times a thread spins, for instance to
+/// release a lock at a known point in another thread's wait. Unlike the hook from
+/// [`set_spin_hook`], the step only applies to the thread that installed it, so cooperating test
+/// threads don't disturb each other or the rest of the test suite. A global hook still runs
+/// first. With a [`Backoff`](crate::Backoff) that spins once per failed attempt, such as
+/// [`SpinOnly`](crate::SpinOnly), each step is one failed attempt to take the lock.
+///
+/// The step is kept in a thread local, so this isn't available under loom, which has thread
+/// locals of its own.
+#[cfg(all(feature = "test-spin-deterministic", not(loom)))]
+pub fn set_thread_spin_step(step: fn()) {
+    STEP.with(|current| current.set(Some(step)));
+}
+
+/// Go back to the usual spin hint on the current thread, after [`set_thread_spin_step`].
+#[cfg(all(feature = "test-spin-deterministic", not(loom)))]
+pub fn clear_thread_spin_step() {
+    STEP.with(|current| current.set(None));
+}
+
+/// Run the hook, then issue the usual spin hint, or this thread's step in its place.
+#[inline]
+pub(crate) fn spin_loop() {
+    let hook = HOOK.load(Ordering::Acquire);
+    if !hook.is_null() {
+        // SAFETY: only `set_spin_hook` stores a non-null pointer, and it is always a `fn()`
+        let hook = unsafe { mem::transmute::<*mut (), fn()>(hook) };
+        hook();
+    }
+
+    #[cfg(all(feature = "test-spin-deterministic", not(loom)))]
+    if let Some(step) = STEP.with(Cell::get) {
+        step();
+        return;
+    }
+
+    #[cfg(not(loom))]
+    core::hint::spin_loop();
+    #[cfg(loom)]
+    loom::hint::spin_loop();
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::{clear_spin_hook, set_spin_hook};
+    use crate::RwLock;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    fn count() {
+        CALLS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // test that the hook runs while a reader waits on a writer
+    #[test]
+    fn counts_spins() {
+        set_spin_hook(count);
+        let rwlock = RwLock::new(0);
+        let guard = rwlock.write();
+        let before = CALLS.load(Ordering::Relaxed);
+        thread::scope(|s| {
+            let reader = s.spawn(|| *rwlock.read());
+            // other tests' locks may spin too, so only count on this one spinning at least once
+            while CALLS.load(Ordering::Relaxed) == before {
+                thread::yield_now();
+            }
+            drop(guard);
+            assert_eq!(reader.join().unwrap(), 0);
+        });
+        clear_spin_hook();
+        assert!(CALLS.load(Ordering::Relaxed) > before);
+    }
+
+    // test releasing a writer's lock at a known point in a reader's wait
+    #[cfg(feature = "test-spin-deterministic")]
+    #[test]
+    fn deterministic_interleaving() {
+        use super::{clear_thread_spin_step, set_thread_spin_step};
+        use crate::{RawRwSpinlock, SpinOnly};
+
+        // the reader reports each step it reaches, then waits for the test to let it carry on
+        static ARRIVED: AtomicUsize = AtomicUsize::new(0);
+        static GRANTED: AtomicUsize = AtomicUsize::new(0);
+
+        fn step() {
+            let step = ARRIVED.fetch_add(1, Ordering::SeqCst) + 1;
+            while GRANTED.load(Ordering::SeqCst) < step {
+                thread::yield_now();
+            }
+        }
+
+        let rwlock = lock_api::RwLock::<RawRwSpinlock<SpinOnly>, _>::new(0);
+        let mut guard = Some(rwlock.write());
+        thread::scope(|s| {
+            let reader = s.spawn(|| {
+                set_thread_spin_step(step);
+                let value = *rwlock.read();
+                clear_thread_spin_step();
+                value
+            });
+
+            for step in 1..=3 {
+                while ARRIVED.load(Ordering::SeqCst) < step {
+                    thread::yield_now();
+                }
+                // the reader is parked in its third step, so its next attempt is the one after
+                // the release
+                if step == 3 {
+                    let mut guard = guard.take().unwrap();
+                    *guard += 1;
+                }
+                GRANTED.store(step, Ordering::SeqCst);
+            }
+
+            assert_eq!(reader.join().unwrap(), 1);
+        });
+        assert_eq!(ARRIVED.load(Ordering::SeqCst), 3);
+    }
+}
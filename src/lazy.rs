@@ -0,0 +1,162 @@
+// MIT/Apache2 License
+
+//! A value that is initialized on first access, for `static`s.
+
+use super::Once;
+use core::{fmt, ops::Deref};
+
+/// A value that is computed by `F` the first time it is dereferenced.
+///
+/// This is built on [`Once`], so it works in `no_std`, and threads that dereference it while the
+/// initializer is running spin until it finishes:
+///
+/// ```
+/// static TABLE: spinny::Lazy<[u32; 4]> = spinny::Lazy::new(|| [1, 2, 4, 8]);
+///
+/// assert_eq!(TABLE[3], 8);
+/// ```
+///
+/// If the initializer panics, the panic propagates out of the dereference and the value is left
+/// uninitialized rather than poisoned. The next dereference, including one by a thread that was
+/// waiting, runs the initializer again. That is why `F` must be `Fn` rather than `FnOnce`.
+pub struct Lazy<T, F = fn() -> T> {
+    once: Once<T>,
+    init: F,
+}
+
+impl<T, F> Lazy<T, F> {
+    /// Create a new `Lazy` that will be initialized by `init`.
+    #[inline]
+    #[must_use]
+    pub const fn new(init: F) -> Self {
+        Lazy {
+            once: Once::new(),
+            init,
+        }
+    }
+
+    /// Get the value, if it has been initialized.
+    #[inline]
+    #[must_use]
+    pub fn get(this: &Self) -> Option<&T> {
+        this.once.get()
+    }
+}
+
+impl<T, F: Fn() -> T> Lazy<T, F> {
+    /// Initialize the value if it hasn't been initialized yet, and return it.
+    ///
+    /// This is the same as dereferencing.
+    #[inline]
+    pub fn force(this: &Self) -> &T {
+        this.once.call_once_retrying(&this.init)
+    }
+}
+
+impl<T, F: Fn() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        Lazy::force(self)
+    }
+}
+
+impl<T: Default> Default for Lazy<T> {
+    #[inline]
+    fn default() -> Self {
+        Lazy::new(T::default)
+    }
+}
+
+impl<T: fmt::Debug, F> fmt::Debug for Lazy<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match Lazy::get(self) {
+            Some(value) => f.debug_struct("Lazy").field("data", value).finish(),
+            None => f
+                .debug_struct("Lazy")
+                .field("data", &format_args!("<uninit>"))
+                .finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lazy;
+
+    #[cfg(loom)]
+    use loom::thread;
+    #[cfg(not(loom))]
+    use std::thread;
+
+    use crate::atomic::{AtomicUsize, Ordering};
+    use std::{sync::Arc, vec::Vec};
+
+    #[cfg(not(loom))]
+    #[test]
+    fn basics() {
+        use std::format;
+
+        static VALUE: Lazy<usize> = Lazy::new(|| 3);
+        assert!(Lazy::get(&VALUE).is_none());
+        assert_eq!(format!("{VALUE:?}"), "Lazy { data: <uninit> }");
+        assert_eq!(*VALUE, 3);
+        assert_eq!(Lazy::get(&VALUE), Some(&3));
+        assert_eq!(format!("{VALUE:?}"), "Lazy { data: 3 }");
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn retry_after_panic() {
+        use std::panic::catch_unwind;
+
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        static VALUE: Lazy<usize> = Lazy::new(|| {
+            let calls = CALLS.fetch_add(1, Ordering::Relaxed);
+            assert!(calls != 0, "initializer failed");
+            7
+        });
+
+        assert!(catch_unwind(|| *VALUE).is_err());
+        assert!(Lazy::get(&VALUE).is_none());
+        assert_eq!(*VALUE, 7);
+        assert_eq!(*VALUE, 7);
+        assert_eq!(CALLS.load(Ordering::Relaxed), 2);
+    }
+
+    // test that racing first dereferences only run the initializer once
+    fn race_kernel() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let cclone = calls.clone();
+        let init = move || {
+            cclone.fetch_add(1, Ordering::Relaxed);
+            5
+        };
+        let lazy = Arc::new(Lazy::new(init));
+        let mut joiners = Vec::new();
+        for _ in 0..2 {
+            let lclone = lazy.clone();
+            joiners.push(thread::spawn(move || {
+                assert_eq!(**lclone, 5);
+            }));
+        }
+
+        for j in joiners {
+            j.join().unwrap();
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn race() {
+        loom::model(|| race_kernel());
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn race() {
+        race_kernel();
+    }
+}
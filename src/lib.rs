@@ -1,165 +1,1040 @@
 // MIT/Apache2 License
 
 //! Implementation of a basic spin-based RwLock.
-//! 
+//!
 //! ## This is now deprecated in favor of [`spin-rs`].
-//! 
+//!
 //! [`spin-rs`]: https://crates.io/crates/spin
+//!
+//! ## Guards
+//!
+//! Every guard type, including the mapped and `Arc` guards, is `#[must_use]`. A guard that is
+//! dropped as soon as it is created releases the lock straight away, so forgetting to bind one is
+//! caught by the `unused_must_use` lint:
+//!
+//! ```compile_fail
+//! #![deny(unused_must_use)]
+//! let lock = spinny::RwLock::new(0);
+//! lock.write();
+//! ```
+//!
+//! `RwLock::try_read`, `RwLock::try_write` and `RwLock::try_upgradable_read` make a single
+//! attempt at the lock and never spin, returning `None` if it is held in a conflicting way.
+//! `RwLock::try_read_recursive` is the same for recursive reads, for code that already holds a
+//! read guard and must not spin: it only fails while a writer holds the lock, and ignores an
+//! upgradable reader.
+//!
+//! `RwLockUpgradableReadGuard::try_upgrade` is the same for upgrades: it makes one attempt and,
+//! if readers still hold the lock, hands the upgradable guard back in the `Err` so that nothing is
+//! lost. `RwLockUpgradableReadGuard::upgrade` spins until the readers are gone instead. Only one
+//! upgradable guard exists at a time and plain reads wait for it, so `try_upgrade` only fails
+//! because of readers that got in first or used `RwLock::read_recursive`.
+//!
+//! A plain `RwLock::read` never succeeds on a thread that already holds an upgradable or write
+//! guard on the same lock, so it would spin forever. In debug builds with the `std` feature, it
+//! panics instead, pointing at `RwLock::read_recursive`, which can be taken alongside an
+//! upgradable guard. Release builds don't check.
+//!
+//! `RwLockUpgradableReadGuard::with_upgraded` upgrades a guard for the length of a closure, then
+//! downgrades it back to an upgradable read, even if the closure panics.
+//! `RwLockWriteGuard::unlocked` and its counterparts on the other guards do the opposite: they
+//! release the lock for the length of a closure and take it again afterwards, also when the
+//! closure panics.
+//!
+//! `RwLockWriteGuard::bump` and `RwLockReadGuard::bump` give waiting lockers a turn in one call,
+//! for long loops that hold a guard throughout. They release the lock, spin once so that a waiter
+//! can get in, and take the lock back, spinning as long as that waiter holds it. There is no
+//! queue of waiters, so nothing guarantees that one gets in during the window.
+//!
+//! ## Mapped guards
+//!
+//! `RwLockReadGuard::map` and `RwLockWriteGuard::map` narrow a guard down to part of the locked
+//! data, giving a [`MappedRwLockReadGuard`] or [`MappedRwLockWriteGuard`]. `try_map` does the same
+//! with a closure that may fail, handing back the original guard if it does. These need no
+//! features and work in `no_std`.
+//!
+//! ## Recovering a stuck lock
+//!
+//! If a guard is leaked, as with `mem::forget` or a task that was aborted mid-critical-section,
+//! the lock stays held with no owner. `RwLock::force_unlock_write` and
+//! `RwLock::force_unlock_read` release it without a guard, with the same ordering as a normal
+//! unlock. They are a last resort: calling either while the lock is actually in use breaks the
+//! exclusion guarantees the lock exists to provide.
+//!
+//! ## Exclusive access
+//!
+//! `RwLock::get_mut` borrows the data through a `&mut RwLock` without touching the lock's state
+//! at all. The mutable borrow already proves that no guard exists, so there is nothing to wait
+//! for and no atomic operation to pay for. `RwLock::into_inner` likewise consumes the lock and
+//! returns the data without blocking, since owning the lock proves the same thing.
+//!
+//! ## Raw pointers
+//!
+//! `RwLock::data_ptr` returns a pointer to the protected data without locking, for handing to FFI
+//! while exclusivity is guaranteed some other way, usually by holding a guard. Nothing checks
+//! that: dereferencing the pointer is only sound while the accesses through it follow the same
+//! rules a guard would, so writes need exclusive access and reads must not overlap any write.
+//!
+//! ## Features
+//!
+//! - `upgrade`, on by default, implements upgradable reads and downgrades. Turning it off with
+//!   `default-features = false` leaves only plain reads, recursive reads and writes, which is less
+//!   code, and means a plain read only has to check for a writer. This removes the
+//!   `RwLockUpgradableReadGuard` aliases, and the lock types' `RawRwLockUpgrade`,
+//!   `RawRwLockDowngrade` and `RawRwLockUpgradeDowngrade` implementations with them.
+//! - `std` enables timed locking through `std::time`, `LockedVec`, and `StdThreadId` for
+//!   [`ReentrantMutex`].
+//! - `arc_lock` adds guards that own an `Arc` of their lock, through `RwLock::read_arc`,
+//!   `RwLock::write_arc` and `RwLock::upgradable_read_arc`. This only needs `alloc`.
+//! - `alloc` adds guards that own an `Rc` of their lock, for single-threaded code that has no use
+//!   for an atomic reference count, through `read_rc` and `write_rc` on `RwLockExt`. Like the
+//!   `Rc` they hold, these guards are neither `Send` nor `Sync`.
+//! - `metrics` counts how often each lock has had to spin, through `contention_count`, and
+//!   summarizes acquisitions and spins through `stats`. It also counts the writers waiting for
+//!   each lock, through `waiting_writers`.
+//! - `critical-section` lets the crate build on targets that can load and store atomics but have
+//!   no compare-and-swap, such as `thumbv6m-none-eabi`. On those targets every update to a lock's
+//!   state runs inside `critical_section::with`, so updates never contend with each other and
+//!   never spin; waiting for a lock that is actually held still spins. The public API is the
+//!   same, and the feature has no effect on targets with compare-and-swap.
+//! - `portable-atomic` builds the locks on `portable_atomic`'s atomics instead of `core`'s,
+//!   with the same state layout and orderings. This covers targets such as RISC-V without the
+//!   `A` extension, given one of the `cfg`s or features `portable_atomic` needs there. It takes
+//!   precedence over `critical-section`.
+//! - `tracing` wraps every blocking acquisition of a `RawRwSpinlock` in a `TRACE`-level
+//!   `acquire` span, recording the kind of lock taken, whether it contended, how many times it
+//!   spun and how long it waited in nanoseconds. The timing needs `std`, so this enables it.
+//! - `deadlock-detection` makes a blocking acquisition of a `RawRwSpinlock` panic once it has
+//!   spun for longer than `deadlock_timeout`, five seconds unless changed with
+//!   `set_deadlock_timeout`. This is a debugging aid for hangs such as taking the same write lock
+//!   twice on one thread, and needs `std`, so this enables it. Try and timed acquisitions are
+//!   unaffected.
+//! - `log` logs a single warning through the `log` crate the first time an acquisition of a
+//!   `RawRwSpinlock` fails more than `long_spin_threshold` times, 100,000 unless changed with
+//!   `set_long_spin_threshold`. Each lock only warns once, so this is cheap enough to leave on in
+//!   production; it is meant for `std` programs with a logger installed, and enables `std`.
+//! - `tsan` adds explicit fences where a reader backs out of a lock it failed to take, so that
+//!   `ThreadSanitizer` can see happens-before edges it would otherwise miss and report as races.
+//!   This is purely for sanitizer cleanliness: the locks are correct without it, and the fences
+//!   only cost time. Leave it off outside of sanitizer runs.
+//! - `poison` adds `PoisonRwLock`, which is poisoned when a thread panics while holding its
+//!   write guard, like `std::sync::RwLock`. Telling whether a thread is panicking needs `std`, so
+//!   this enables it.
+//! - `fuzz-hook` adds `set_spin_hook`, which installs a function that every lock in the crate
+//!   calls each time it spins while waiting, so that a fuzzer can drive interleavings. This is
+//!   for testing only; without a hook installed, each spin still pays for checking for one.
+//! - `lock-ordering` adds `OrderedRwLock`, a lock with a level. In debug builds, taking one at
+//!   or below the level of one already held panics, which catches lock orderings that could
+//!   deadlock. The levels held are tracked per thread, so this enables `std`.
+//! - `test-spin-deterministic` adds `set_thread_spin_step`, which replaces the spin hint with a
+//!   function of the test's choosing on the current thread, so that a test can control exactly
+//!   how many times a thread spins before another releases a lock. This is for tests only, and
+//!   enables `fuzz-hook` and `std`.
+//! - `owner-tracking` records which thread holds the write lock of each `RawRwSpinlock`, for
+//!   diagnostics, through `write_owner`. Threads are identified as by `StdThreadId`, so this
+//!   enables `std`. Without it, locks don't store an owner at all.
+//! - `serde` implements `Serialize` and `Deserialize` for `RwLock<T>`. Serializing takes a read
+//!   lock, so serializing a lock that the current thread holds a write guard on will spin forever.
 
 #![no_std]
 #![warn(clippy::pedantic)]
 #![allow(clippy::same_item_push)]
 #![deprecated(since = "0.2.4", note = "Use spin-rs instead")]
 
-#[cfg(any(test, loom))]
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(any(test, loom, feature = "std"))]
 extern crate std;
 
+#[cfg(all(feature = "arc_lock", feature = "upgrade"))]
+use lock_api::ArcRwLockUpgradableReadGuard as LAArcRwLockUpgradableReadGuard;
+#[cfg(feature = "std")]
+use lock_api::RawRwLockTimed;
+#[cfg(all(feature = "std", feature = "upgrade"))]
+use lock_api::RawRwLockUpgradeTimed;
+#[cfg(feature = "arc_lock")]
 use lock_api::{
-    GuardSend, RawRwLock, RawRwLockDowngrade, RawRwLockUpgrade, RwLock as LARwLock,
-    RwLockReadGuard as LARwLockReadGuard, RwLockUpgradableReadGuard as LARwLockUpgradableReadGuard,
-    RwLockWriteGuard as LARwLockWriteGuard,
+    ArcRwLockReadGuard as LAArcRwLockReadGuard, ArcRwLockWriteGuard as LAArcRwLockWriteGuard,
 };
-
-#[cfg(not(loom))]
-use core::{
-    hint::spin_loop,
-    sync::atomic::{AtomicUsize, Ordering},
+use lock_api::{
+    MappedRwLockReadGuard as LAMappedRwLockReadGuard,
+    MappedRwLockWriteGuard as LAMappedRwLockWriteGuard, RawRwLock, RawRwLockFair,
+    RawRwLockRecursive, RwLock as LARwLock, RwLockReadGuard as LARwLockReadGuard,
+    RwLockWriteGuard as LARwLockWriteGuard,
 };
-#[cfg(loom)]
-use loom::{
-    hint::spin_loop,
-    sync::atomic::{AtomicUsize, Ordering},
+#[cfg(feature = "upgrade")]
+use lock_api::{
+    RawRwLockDowngrade, RawRwLockUpgrade, RawRwLockUpgradeDowngrade,
+    RwLockUpgradableReadGuard as LARwLockUpgradableReadGuard,
 };
+
+use atomic::{rollback_fence, AtomicBool, AtomicUsize, Ordering};
+#[cfg(all(not(loom), not(feature = "fuzz-hook")))]
+use core::hint::spin_loop;
+#[cfg(feature = "fuzz-hook")]
+use fuzz_hook::spin_loop;
+#[cfg(all(loom, not(feature = "fuzz-hook")))]
+use loom::hint::spin_loop;
 #[cfg(loom)]
-use once_cell::sync::OnceCell;
+use loom_lazy::LoomLazy;
 
-#[cfg(not(loom))]
-/// Raw spinlock rwlock, wrapped in the `lock_api` RwLock struct.
-pub struct RawRwSpinlock(AtomicUsize);
+#[cfg(feature = "tracing")]
+use core::convert::TryFrom;
+use core::{fmt, marker::PhantomData, mem};
+#[cfg(feature = "metrics")]
+use metrics::Metrics;
+use owner::WriteOwner;
+#[cfg(feature = "log")]
+use spin_warning::SpinWarning;
 
+mod atomic;
+mod backoff;
+mod barrier;
+mod capped;
+mod clock;
+mod compact;
+mod condvar;
+#[cfg(feature = "deadlock-detection")]
+mod deadlock;
+mod error;
+mod ext;
+mod fair;
+#[cfg(feature = "fuzz-hook")]
+mod fuzz_hook;
+mod lazy;
+#[cfg(feature = "std")]
+mod locked_vec;
 #[cfg(loom)]
+mod loom_lazy;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod multi;
+mod mutex;
+mod narrow;
+mod once;
+#[cfg(feature = "lock-ordering")]
+mod ordered;
+mod ordering;
+mod owner;
+mod padded;
+#[cfg(feature = "poison")]
+mod poison;
+mod polite;
+#[cfg(feature = "alloc")]
+mod rc_lock;
+mod reentrant;
+mod reentry;
+mod semaphore;
+#[cfg(feature = "log")]
+mod spin_warning;
+mod state;
+#[cfg(test)]
+mod test_util;
+mod tiny;
+mod writer_pref;
+#[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+pub use backoff::WasmWaitBackoff;
+pub use backoff::{
+    ArchSpin, Backoff, DefaultBackoff, ExponentialBackoff, JitteredBackoff, NoBackoff, SpinOnly,
+};
+#[cfg(feature = "std")]
+pub use backoff::{SpinThenYield, YieldingBackoff};
+pub use barrier::{Barrier, BarrierWaitResult};
+pub use capped::{CappedRwLock, RawCappedRwSpinlock};
+pub use clock::Clock;
+#[cfg(feature = "std")]
+pub use clock::StdClock;
+pub use compact::{RawRwSpinlock32, RwLock32};
+pub use condvar::Condvar;
+#[cfg(feature = "deadlock-detection")]
+pub use deadlock::{deadlock_timeout, set_deadlock_timeout};
+#[cfg(feature = "poison")]
+pub use error::PoisonError;
+pub use error::TryLockError;
+pub use ext::{DebugState, RwLockExt, RwLockReadGuardExt, RwLockWriteGuardExt};
+pub use fair::{FairRwLock, RawFairRwSpinlock};
+#[cfg(feature = "fuzz-hook")]
+pub use fuzz_hook::{clear_spin_hook, set_spin_hook};
+#[cfg(all(feature = "test-spin-deterministic", not(loom)))]
+pub use fuzz_hook::{clear_thread_spin_step, set_thread_spin_step};
+pub use lazy::Lazy;
+pub use lock_api::{GetThreadId, GuardNoSend, GuardSend};
+#[cfg(feature = "std")]
+pub use locked_vec::{LockedVec, LockedVecIter};
+#[cfg(feature = "metrics")]
+pub use metrics::LockStats;
+pub use multi::{lock_all, lock_two};
+pub use mutex::{Mutex, MutexGuard, RawSpinMutex};
+pub use once::{Once, RawOnce};
+#[cfg(feature = "lock-ordering")]
+pub use ordered::{LevelGuard, OrderedRwLock};
+pub use ordering::{OrderingPolicy, SeqCstAll, Standard};
+pub use padded::{CachePadded, PaddedRwLock};
+#[cfg(feature = "poison")]
+pub use poison::{PoisonResult, PoisonRwLock, PoisonWriteGuard};
+pub use polite::{PoliteRwLock, RawPoliteRwSpinlock};
+#[cfg(feature = "alloc")]
+pub use rc_lock::{RcRwLockReadGuard, RcRwLockWriteGuard};
+#[cfg(feature = "std")]
+pub use reentrant::StdThreadId;
+pub use reentrant::{ReentrantMutex, ReentrantMutexGuard};
+pub use semaphore::Semaphore;
+#[cfg(feature = "log")]
+pub use spin_warning::{long_spin_threshold, set_long_spin_threshold};
+pub use state::{LockState, READER, READER_SHIFT, UPGRADED, WRITER};
+pub use tiny::{RawRwSpinlockU8, RwLockU8};
+pub use writer_pref::{RawWriterPreferringRwSpinlock, WriterPreferringRwLock};
+
 /// Raw spinlock rwlock, wrapped in the `lock_api` RwLock struct.
-pub struct RawRwSpinlock(OnceCell<AtomicUsize>);
+///
+/// `B` is the [`Backoff`] used while the lock is contended. To use something other than the
+/// default, name the raw lock explicitly, as in `lock_api::RwLock<RawRwSpinlock<SpinOnly>, T>`.
+/// `O` is the [`OrderingPolicy`] that supplies the memory orderings used on the lock's state.
+/// `G` is the [`GuardMarker`](RawRwLock::GuardMarker), which decides whether guards are `Send`:
+/// [`GuardSend`] by default, or [`GuardNoSend`] as in [`RawRwSpinlockLocal`].
+///
+/// ## Layout
+///
+/// The lock is [`LOCK_SIZE`] bytes, a constant for laying out `static`s and other structures
+/// that embed one. It is a single `usize` holding the state, and aligned like one, so an
+/// `RwLock<()>` is exactly that size as well. This is checked when the crate is built, so a change
+/// that grew it would not compile. It does grow with the `metrics`, `log` and `owner-tracking`
+/// features, which keep their own state in every lock. A cap on
+/// readers takes a word of its own, so it lives in [`RawCappedRwSpinlock`] instead. For a lock
+/// that fits in a single `u32`, see [`RawRwSpinlock32`].
+pub struct RawRwSpinlock<B: Backoff = DefaultBackoff, O: OrderingPolicy = Standard, G = GuardSend> {
+    #[cfg(not(loom))]
+    state: AtomicUsize,
+    #[cfg(loom)]
+    state: LoomLazy<AtomicUsize>,
+    #[cfg(feature = "metrics")]
+    metrics: Metrics,
+    #[cfg(feature = "log")]
+    spin_warning: SpinWarning,
+    owner: WriteOwner,
+    _backoff: PhantomData<fn() -> B>,
+    _ordering: PhantomData<fn() -> O>,
+    _guard_marker: PhantomData<fn() -> G>,
+}
+
+/// The size of a [`RawRwSpinlock`] in bytes, and of an `RwLock<()>`.
+///
+/// This depends on the features enabled; see the [layout](RawRwSpinlock#layout) of the lock.
+pub const LOCK_SIZE: usize = mem::size_of::<RawRwSpinlock>();
+
+#[cfg(not(any(loom, feature = "metrics", feature = "log", feature = "owner-tracking")))]
+const _: () = assert!(
+    LOCK_SIZE == mem::size_of::<usize>()
+        && mem::align_of::<RawRwSpinlock>() == mem::align_of::<usize>()
+);
+const _: () = assert!(mem::size_of::<LARwLock<RawRwSpinlock, ()>>() == LOCK_SIZE);
 
 #[cfg(not(loom))]
-impl RawRwSpinlock {
+impl<B: Backoff, O: OrderingPolicy, G> RawRwSpinlock<B, O, G> {
     #[inline]
     fn ulock(&self) -> &AtomicUsize {
-        &self.0
+        &self.state
     }
 }
 
 #[cfg(loom)]
-impl RawRwSpinlock {
+impl<B: Backoff, O: OrderingPolicy, G> RawRwSpinlock<B, O, G> {
     #[inline]
     fn ulock(&self) -> &AtomicUsize {
-        self.0.get_or_init(|| AtomicUsize::new(0))
+        self.state.get_or_init(|| AtomicUsize::new(0))
     }
 }
 
-// flags stored in the usize struct
-const READER: usize = 1 << 2;
-const UPGRADED: usize = 1 << 1;
-const WRITER: usize = 1 << 0;
+#[cfg(all(test, not(loom)))]
+std::thread_local! {
+    // the compare-and-swaps made by this thread's blocking writers, for testing how they spin
+    static CAS_ATTEMPTS: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+}
 
-unsafe impl RawRwLock for RawRwSpinlock {
-    #[cfg(not(loom))]
-    const INIT: RawRwSpinlock = RawRwSpinlock(AtomicUsize::new(0));
-    #[cfg(loom)]
-    const INIT: RawRwSpinlock = RawRwSpinlock(OnceCell::new());
+#[cfg(all(test, not(loom)))]
+impl<B: Backoff, O: OrderingPolicy, G> RawRwSpinlock<B, O, G> {
+    fn set_state(&self, value: usize) {
+        self.ulock().store(value, Ordering::Relaxed);
+    }
+}
+
+impl<B: Backoff, O: OrderingPolicy, G> RawRwSpinlock<B, O, G> {
+    /// Get the number of times an acquisition of this lock has had to spin.
+    ///
+    /// The counter is monotonic and updated with relaxed atomics, so it only gives a rough
+    /// picture of how contended the lock is.
+    #[cfg(feature = "metrics")]
+    #[must_use]
+    pub fn contention_count(&self) -> u64 {
+        self.metrics.spins()
+    }
 
-    type GuardMarker = GuardSend;
+    /// Get the number of writers spinning while they wait for this lock.
+    ///
+    /// A writer counts from its first failed attempt at a blocking write lock or upgrade until it
+    /// gets the lock. Try and timed acquisitions never count. This is a relaxed snapshot, meant
+    /// for adaptive policies that only need an approximate answer, such as readers that hold back
+    /// while a writer waits.
+    #[cfg(feature = "metrics")]
+    #[must_use]
+    pub fn waiting_writers(&self) -> usize {
+        self.metrics.waiting_writers()
+    }
 
-    fn lock_shared(&self) {
-        while !self.try_lock_shared() {
-            spin_loop()
+    /// Get the thread holding the write lock, or `None` if no thread does.
+    ///
+    /// The thread is identified by the id [`StdThreadId`] gives it through
+    /// [`GetThreadId::nonzero_thread_id`]. Upgrading counts as taking the write lock, and
+    /// downgrading as giving it up. This is a relaxed snapshot for diagnostics, so it can be stale
+    /// by the time it is returned, and it may briefly be `None` just after a thread has taken the
+    /// write lock or still name a thread that is just letting go of it.
+    #[cfg(feature = "owner-tracking")]
+    #[must_use]
+    pub fn write_owner(&self) -> Option<core::num::NonZeroUsize> {
+        self.owner.get()
+    }
+
+    /// Get a summary of how contended this lock has been, with its current state.
+    ///
+    /// See [`LockStats`] for caveats.
+    #[cfg(feature = "metrics")]
+    #[must_use]
+    pub fn stats(&self) -> LockStats {
+        self.metrics.stats(self.state())
+    }
+
+    #[inline]
+    #[cfg_attr(not(feature = "metrics"), allow(clippy::unused_self))]
+    fn contended(&self) {
+        #[cfg(feature = "metrics")]
+        self.metrics.spin();
+    }
+
+    #[inline]
+    #[cfg_attr(not(feature = "metrics"), allow(clippy::unused_self, unused_variables))]
+    fn acquired(&self, spins: usize) {
+        #[cfg(feature = "metrics")]
+        self.metrics.acquired(spins);
+    }
+
+    // called before a plain read spins, to catch a thread that holds this lock exclusively
+    #[inline]
+    fn check_reentry(&self) {
+        reentry::check_read(self, || {
+            self.ulock().load(O::RELAXED) & (WRITER | UPGRADED) != 0
+        });
+    }
+
+    /// Acquire a shared lock, returning the number of failed attempts before it was acquired.
+    ///
+    /// This is [`lock_shared`](RawRwLock::lock_shared) for profiling. Each failed attempt backs
+    /// off once, and an attempt can fail without any real contention, so under contention the
+    /// count is only a rough indicator of how long the acquisition waited. The lock is released
+    /// with [`unlock_shared`](RawRwLock::unlock_shared), as usual.
+    #[must_use]
+    pub fn lock_shared_counted(&self) -> usize {
+        self.check_reentry();
+        self.acquire(Acquire::Shared, Self::try_lock_shared)
+    }
+
+    /// Acquire an exclusive lock, returning the number of failed attempts before it was acquired.
+    ///
+    /// See [`lock_shared_counted`](RawRwSpinlock::lock_shared_counted) for caveats. The lock is
+    /// released with [`unlock_exclusive`](RawRwLock::unlock_exclusive).
+    #[must_use]
+    pub fn lock_exclusive_counted(&self) -> usize {
+        let spins = self.acquire(Acquire::Exclusive, |raw| {
+            // A relaxed load predicts whether the compare-and-swap can succeed, so that a
+            // contended lock is spun on with loads rather than with doomed read-modify-writes.
+            // In particular, a writer waiting for readers to drain only loads the state until the
+            // last of them has left.
+            raw.ulock().load(O::RELAXED) == 0 && {
+                #[cfg(all(test, not(loom)))]
+                CAS_ATTEMPTS.with(|attempts| attempts.set(attempts.get() + 1));
+                raw.ulock()
+                    .compare_exchange_weak(0, WRITER, O::ACQUIRE, O::RELAXED)
+                    .is_ok()
+            }
+        });
+        reentry::claim(self);
+        self.owner.claim();
+        spins
+    }
+
+    /// Acquire an upgradable lock, returning the number of failed attempts before it was acquired.
+    ///
+    /// See [`lock_shared_counted`](RawRwSpinlock::lock_shared_counted) for caveats. The lock is
+    /// released with [`unlock_upgradable`](RawRwLockUpgrade::unlock_upgradable).
+    #[cfg(feature = "upgrade")]
+    #[must_use]
+    pub fn lock_upgradable_counted(&self) -> usize {
+        self.acquire(Acquire::Upgradable, Self::try_lock_upgradable)
+    }
+
+    /// Back off until `try_lock` succeeds, returning the number of failed attempts.
+    ///
+    /// With the `tracing` feature, this runs inside a span describing the acquisition. With the
+    /// `deadlock-detection` feature, this panics if it spins for too long, and with the `log`
+    /// feature it logs a warning.
+    #[inline]
+    fn acquire(&self, kind: Acquire, try_lock: impl Fn(&Self) -> bool) -> usize {
+        #[cfg(any(feature = "tracing", feature = "deadlock-detection", feature = "log"))]
+        let label = kind.label();
+        #[cfg(feature = "tracing")]
+        let span = tracing::trace_span!(
+            "acquire",
+            kind = label,
+            contended = tracing::field::Empty,
+            spins = tracing::field::Empty,
+            wait_ns = tracing::field::Empty,
+        )
+        .entered();
+        #[cfg(feature = "tracing")]
+        let start = (!span.is_disabled()).then(std::time::Instant::now);
+
+        #[cfg(feature = "deadlock-detection")]
+        let mut watchdog = deadlock::Watchdog::new();
+
+        // writers, including upgrading readers, count as waiting from their first failed attempt
+        let writer = kind.is_writer();
+        #[cfg(feature = "metrics")]
+        let mut waiting = None;
+
+        let mut backoff = if writer { B::new_exclusive() } else { B::new() };
+        let mut spins = 0;
+        while !try_lock(self) {
+            #[cfg(feature = "metrics")]
+            if waiting.is_none() && writer {
+                waiting = Some(self.metrics.writer_waiting());
+            }
+            #[cfg(feature = "deadlock-detection")]
+            watchdog.check(label);
+            self.contended();
+            backoff.spin();
+            spins += 1;
+            #[cfg(feature = "log")]
+            self.spin_warning.check(self, label, spins);
         }
+        #[cfg(feature = "metrics")]
+        drop(waiting);
+        self.acquired(spins);
+
+        #[cfg(feature = "tracing")]
+        if let Some(start) = start {
+            let wait_ns = u64::try_from(start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+            span.record("contended", spins != 0);
+            span.record("spins", spins);
+            span.record("wait_ns", wait_ns);
+        }
+
+        spins
     }
 
-    fn try_lock_shared(&self) -> bool {
-        let value = self.ulock().fetch_add(READER, Ordering::Acquire);
+    /// Get the number of readers currently holding the lock.
+    ///
+    /// This is a best-effort snapshot taken with a relaxed load, and may already be stale by the
+    /// time it is returned. It also counts readers that are in the middle of failing to acquire
+    /// the lock, so it should only be used for debugging.
+    #[must_use]
+    pub fn reader_count(&self) -> usize {
+        self.ulock().load(O::RELAXED) / READER
+    }
+
+    /// Spin until nobody holds the lock, without taking it.
+    ///
+    /// This is inherently racy: the lock may be taken again as soon as this returns, so it only
+    /// suits coordination like shutdown, where nobody is expected to take the lock again. The
+    /// final load is an acquire, so whatever was written under the lock before it was last
+    /// released is visible afterwards.
+    pub fn wait_until_unlocked(&self) {
+        while self.ulock().load(O::ACQUIRE) != 0 {
+            spin_loop();
+        }
+    }
+
+    /// Put the lock back in its unlocked state, whatever state it is in.
+    ///
+    /// This is for pools that reuse locks, including ones whose guards were leaked, without
+    /// creating them again. It is a single release store, so whatever was written to the data
+    /// before the reset is visible to the next thread to take the lock. Counters kept by the
+    /// `metrics` feature carry on from where they were.
+    ///
+    /// # Safety
+    ///
+    /// No guard of this lock may be alive, and nobody may hold it through the raw API, since
+    /// all of their locks are forgotten. Nobody may be trying to take the lock either, as the
+    /// reset may land in the middle of their attempt and leave the state corrupted.
+    pub unsafe fn reset(&self) {
+        reentry::release(self);
+        self.owner.release();
+        self.ulock().store(0, O::RELEASE);
+    }
+
+    /// Get the raw state word, laid out as described on [`WRITER`].
+    ///
+    /// This is a snapshot with the same caveats as [`reader_count`](RawRwSpinlock::reader_count).
+    #[must_use]
+    pub fn raw_state(&self) -> usize {
+        self.ulock().load(O::RELAXED)
+    }
 
-        if value & (WRITER | UPGRADED) != 0 {
-            self.ulock().fetch_sub(READER, Ordering::Relaxed);
+    /// Create a lock whose state word is `state`, as returned by
+    /// [`raw_state`](RawRwSpinlock::raw_state), for restoring a lock that was persisted.
+    ///
+    /// This is extremely unsafe. The new lock claims to be held by whatever readers, upgradable
+    /// reader or writer the state records, and nothing but the caller knows who they are. The
+    /// `owner-tracking` feature and the debug-build reentry check don't know who holds it.
+    ///
+    /// # Safety
+    ///
+    /// The state must only be taken from a lock that nobody was accessing concurrently, so that
+    /// it isn't in the middle of an acquisition. Every lock the state records must then be owned
+    /// by code that releases it through the raw API, exactly as if it had taken it from the new
+    /// lock itself.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the state could never be at rest: a writer alongside readers or an upgradable
+    /// reader, or more readers than the reader count can hold.
+    #[must_use]
+    pub unsafe fn from_raw_state(state: usize) -> Self {
+        assert!(
+            if state & WRITER != 0 {
+                state == WRITER
+            } else {
+                state < READER_LIMIT
+            },
+            "invalid lock state {:#x}",
+            state,
+        );
+
+        let lock = <Self as RawRwLock>::INIT;
+        lock.ulock().store(state, O::RELAXED);
+        lock
+    }
+
+    /// Get the state of the lock, decoded from [`raw_state`](RawRwSpinlock::raw_state).
+    #[must_use]
+    pub fn state(&self) -> LockState {
+        LockState::from_raw(self.raw_state())
+    }
+
+    /// Panic in debug builds if anyone but the caller could hold the lock.
+    ///
+    /// This is a sanity check for designs where only one thread is meant to touch the lock. The
+    /// state is a relaxed snapshot, so a stray access that races with the check can be missed.
+    /// In release builds this does nothing.
+    ///
+    /// # Panics
+    ///
+    /// With debug assertions enabled, panics if the state shows a writer, an upgradable reader,
+    /// or more than one reader. The caller may hold a single read lock itself.
+    #[inline]
+    #[track_caller]
+    pub fn assert_unique_access(&self) {
+        #[cfg(debug_assertions)]
+        {
+            let state = self.raw_state();
+            assert!(
+                state & (WRITER | UPGRADED) == 0 && state / READER <= 1,
+                "expected unique access to the lock, but its state is {:?}",
+                LockState::from_raw(state),
+            );
+        }
+    }
+
+    /// Try to acquire a shared lock, spinning until `deadline` passes on the clock `C`.
+    ///
+    /// Returns `false` if the lock could not be acquired in time.
+    #[must_use]
+    pub fn try_lock_shared_deadline<C: Clock>(&self, deadline: C::Instant) -> bool {
+        self.spin_until::<C>(deadline, Self::try_lock_shared)
+    }
+
+    /// Try to acquire an exclusive lock, spinning until `deadline` passes on the clock `C`.
+    ///
+    /// Returns `false` if the lock could not be acquired in time.
+    #[must_use]
+    pub fn try_lock_exclusive_deadline<C: Clock>(&self, deadline: C::Instant) -> bool {
+        self.spin_until::<C>(deadline, Self::try_lock_exclusive)
+    }
+
+    /// Try to acquire an upgradable lock, spinning until `deadline` passes on the clock `C`.
+    ///
+    /// Returns `false` if the lock could not be acquired in time.
+    #[cfg(feature = "upgrade")]
+    #[must_use]
+    pub fn try_lock_upgradable_deadline<C: Clock>(&self, deadline: C::Instant) -> bool {
+        self.spin_until::<C>(deadline, Self::try_lock_upgradable)
+    }
+
+    /// Try to upgrade an upgradable lock, spinning until `deadline` passes on the clock `C`.
+    ///
+    /// Returns `false` if the other readers did not leave in time, in which case the upgradable
+    /// lock is still held.
+    ///
+    /// # Safety
+    ///
+    /// The upgradable lock must be held, as for
+    /// [`try_upgrade`](RawRwLockUpgrade::try_upgrade).
+    #[cfg(feature = "upgrade")]
+    #[must_use]
+    pub unsafe fn try_upgrade_deadline<C: Clock>(&self, deadline: C::Instant) -> bool {
+        self.spin_until::<C>(deadline, |raw| unsafe { raw.try_upgrade() })
+    }
+
+    #[inline]
+    fn spin_until<C: Clock>(&self, deadline: C::Instant, try_lock: impl Fn(&Self) -> bool) -> bool {
+        loop {
+            if try_lock(self) {
+                return true;
+            }
+            if C::now() >= deadline {
+                return false;
+            }
+            spin_loop();
+        }
+    }
+}
+
+impl<B: Backoff, O: OrderingPolicy, G> fmt::Debug for RawRwSpinlock<B, O, G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.ulock().load(O::RELAXED);
+        f.debug_struct("RawRwSpinlock")
+            .field("readers", &(value / READER))
+            .field("writer", &(value & WRITER != 0))
+            .field("upgraded", &(value & UPGRADED != 0))
+            .finish()
+    }
+}
+
+// What `RawRwSpinlock::acquire` is taking, which decides how it backs off and how it is reported.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Acquire {
+    Shared,
+    Recursive,
+    #[cfg(feature = "upgrade")]
+    Upgradable,
+    Exclusive,
+    // an upgradable reader upgrading to a writer
+    #[cfg(feature = "upgrade")]
+    Upgrade,
+}
+
+impl Acquire {
+    // writers, including upgrading readers, use `Backoff::new_exclusive` and count as waiting
+    fn is_writer(self) -> bool {
+        match self {
+            Acquire::Shared | Acquire::Recursive => false,
+            #[cfg(feature = "upgrade")]
+            Acquire::Upgradable => false,
+            Acquire::Exclusive => true,
+            #[cfg(feature = "upgrade")]
+            Acquire::Upgrade => true,
+        }
+    }
+
+    // the name used in spans, log messages and deadlock panics
+    #[cfg(any(feature = "tracing", feature = "deadlock-detection", feature = "log"))]
+    fn label(self) -> &'static str {
+        match self {
+            Acquire::Shared => "shared",
+            Acquire::Recursive => "recursive",
+            #[cfg(feature = "upgrade")]
+            Acquire::Upgradable => "upgradable",
+            Acquire::Exclusive => "exclusive",
+            #[cfg(feature = "upgrade")]
+            Acquire::Upgrade => "upgrade",
+        }
+    }
+}
+
+// new readers are turned away once the state reaches this, so that the speculative increments of
+// readers which are about to back off can never carry the reader count into the flag bits
+const READER_LIMIT: usize = !(usize::MAX >> 1);
+
+// the bits that turn a plain reader away; nothing sets `UPGRADED` without the `upgrade` feature,
+// so plain reads only have to look for a writer
+#[cfg(feature = "upgrade")]
+const READ_BLOCKERS: usize = WRITER | UPGRADED;
+#[cfg(not(feature = "upgrade"))]
+const READ_BLOCKERS: usize = WRITER;
+
+// The reader paths, turning readers away once the state reaches `reader_limit`. The plain lock
+// passes `READER_LIMIT`, and `RawCappedRwSpinlock` its own cap.
+impl<B: Backoff, O: OrderingPolicy, G> RawRwSpinlock<B, O, G> {
+    #[inline]
+    fn try_lock_shared_below(&self, reader_limit: usize) -> bool {
+        let value = self.ulock().fetch_add(READER, O::ACQUIRE);
+
+        if value & READ_BLOCKERS != 0 || value >= reader_limit {
+            // Backing out can be relaxed. It is a read-modify-write of the same atomic as the
+            // increment, so it comes after the increment in the state's modification order, and
+            // the next compare-and-swap a writer makes is guaranteed to see it. We never touched
+            // the data, so there is nothing to release.
+            rollback_fence();
+            self.ulock().fetch_sub(READER, O::RELAXED);
+            false
+        } else {
+            true
+        }
+    }
+
+    #[inline]
+    fn try_lock_shared_recursive_below(&self, reader_limit: usize) -> bool {
+        let value = self.ulock().fetch_add(READER, O::ACQUIRE);
+
+        if value & WRITER != 0 || value >= reader_limit {
+            rollback_fence();
+            self.ulock().fetch_sub(READER, O::RELAXED);
             false
         } else {
             true
         }
     }
+}
+
+unsafe impl<B: Backoff, O: OrderingPolicy, G> RawRwLock for RawRwSpinlock<B, O, G> {
+    const INIT: RawRwSpinlock<B, O, G> = RawRwSpinlock {
+        #[cfg(not(loom))]
+        state: AtomicUsize::new(0),
+        #[cfg(loom)]
+        state: LoomLazy::new(),
+        #[cfg(feature = "metrics")]
+        metrics: Metrics::new(),
+        #[cfg(feature = "log")]
+        spin_warning: SpinWarning::new(),
+        owner: WriteOwner::new(),
+        _backoff: PhantomData,
+        _ordering: PhantomData,
+        _guard_marker: PhantomData,
+    };
+
+    type GuardMarker = G;
+
+    fn lock_shared(&self) {
+        let _ = self.lock_shared_counted();
+    }
+
+    fn try_lock_shared(&self) -> bool {
+        self.try_lock_shared_below(READER_LIMIT)
+    }
 
     fn try_lock_exclusive(&self) -> bool {
-        self.ulock()
-            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
-            .is_ok()
+        // see `lock_exclusive_counted`; the compare-and-swap still decides who gets the lock
+        let locked = self.ulock().load(O::RELAXED) == 0
+            && self
+                .ulock()
+                .compare_exchange(0, WRITER, O::ACQUIRE, O::RELAXED)
+                .is_ok();
+        if locked {
+            reentry::claim(self);
+            self.owner.claim();
+        }
+        locked
     }
 
     fn lock_exclusive(&self) {
-        loop {
-            match self.ulock().compare_exchange_weak(
-                0,
-                WRITER,
-                Ordering::Acquire,
-                Ordering::Relaxed,
-            ) {
-                Ok(_) => return,
-                Err(_) => spin_loop(),
-            }
-        }
+        let _ = self.lock_exclusive_counted();
     }
 
     unsafe fn unlock_shared(&self) {
-        self.ulock().fetch_sub(READER, Ordering::Release);
+        self.ulock().fetch_sub(READER, O::RELEASE);
     }
 
     unsafe fn unlock_exclusive(&self) {
-        self.ulock()
-            .fetch_and(!(WRITER | UPGRADED), Ordering::Release);
+        reentry::release(self);
+        self.owner.release();
+        self.ulock().fetch_and(!(WRITER | UPGRADED), O::RELEASE);
+    }
+    // These are relaxed, non-synchronizing snapshots of the state, so they can be stale by the
+    // time they are returned.
+    fn is_locked(&self) -> bool {
+        self.ulock().load(O::RELAXED) != 0
+    }
+
+    fn is_locked_exclusive(&self) -> bool {
+        self.ulock().load(O::RELAXED) & WRITER != 0
     }
 }
 
-unsafe impl RawRwLockUpgrade for RawRwSpinlock {
-    fn lock_upgradable(&self) {
-        while !self.try_lock_upgradable() {
-            spin_loop()
-        }
+// There is no queue of waiters to hand the lock off to, so a fair unlock is just a regular unlock.
+// Bumping releases the lock and spins once before re-acquiring it, giving any waiting locker a
+// window to get in.
+unsafe impl<B: Backoff, O: OrderingPolicy, G> RawRwLockFair for RawRwSpinlock<B, O, G> {
+    unsafe fn unlock_shared_fair(&self) {
+        self.unlock_shared();
     }
 
-    fn try_lock_upgradable(&self) -> bool {
-        self.ulock().fetch_or(UPGRADED, Ordering::Acquire) & (WRITER | UPGRADED) == 0
+    unsafe fn unlock_exclusive_fair(&self) {
+        self.unlock_exclusive();
     }
 
-    unsafe fn try_upgrade(&self) -> bool {
-        self.ulock()
-            .compare_exchange(UPGRADED, WRITER, Ordering::Acquire, Ordering::Relaxed)
-            .is_ok()
+    unsafe fn bump_shared(&self) {
+        self.unlock_shared();
+        spin_loop();
+        self.lock_shared();
     }
 
-    unsafe fn upgrade(&self) {
+    unsafe fn bump_exclusive(&self) {
+        self.unlock_exclusive();
+        spin_loop();
+        self.lock_exclusive();
+    }
+}
+
+// Recursive reads only back off for an actual writer, not for an upgradable reader. This is meant
+// for callers that already hold a shared guard: a plain read would fail against the `UPGRADED`
+// bit, while the recursive read lets the thread keep going.
+unsafe impl<B: Backoff, O: OrderingPolicy, G> RawRwLockRecursive for RawRwSpinlock<B, O, G> {
+    fn lock_shared_recursive(&self) {
+        self.acquire(Acquire::Recursive, Self::try_lock_shared_recursive);
+    }
+
+    fn try_lock_shared_recursive(&self) -> bool {
+        self.try_lock_shared_recursive_below(READER_LIMIT)
+    }
+}
+
+#[cfg(feature = "upgrade")]
+unsafe impl<B: Backoff, O: OrderingPolicy, G> RawRwLockUpgrade for RawRwSpinlock<B, O, G> {
+    fn lock_upgradable(&self) {
+        let _ = self.lock_upgradable_counted();
+    }
+
+    fn try_lock_upgradable(&self) -> bool {
+        // only set the bit if nobody else holds it, so that a failed attempt never leaves it set
+        let mut value = self.ulock().load(O::RELAXED);
         loop {
+            if value & (WRITER | UPGRADED) != 0 {
+                return false;
+            }
+
             match self.ulock().compare_exchange_weak(
-                UPGRADED,
-                WRITER,
-                Ordering::Acquire,
-                Ordering::Relaxed,
+                value,
+                value | UPGRADED,
+                O::ACQUIRE,
+                O::RELAXED,
             ) {
-                Ok(_) => return,
-                Err(_) => spin_loop(),
+                Ok(_) => {
+                    reentry::claim(self);
+                    return true;
+                }
+                Err(current) => value = current,
             }
         }
     }
 
+    unsafe fn try_upgrade(&self) -> bool {
+        let upgraded = self
+            .ulock()
+            .compare_exchange(UPGRADED, WRITER, O::ACQUIRE, O::RELAXED)
+            .is_ok();
+        if upgraded {
+            self.owner.claim();
+        }
+        upgraded
+    }
+
+    unsafe fn upgrade(&self) {
+        self.acquire(Acquire::Upgrade, |raw| {
+            raw.ulock()
+                .compare_exchange_weak(UPGRADED, WRITER, O::ACQUIRE, O::RELAXED)
+                .is_ok()
+        });
+        self.owner.claim();
+    }
+
     unsafe fn unlock_upgradable(&self) {
-        self.ulock().fetch_sub(UPGRADED, Ordering::AcqRel);
+        reentry::release(self);
+        self.ulock().fetch_sub(UPGRADED, O::ACQ_REL);
     }
 }
 
-unsafe impl RawRwLockDowngrade for RawRwSpinlock {
+#[cfg(feature = "upgrade")]
+unsafe impl<B: Backoff, O: OrderingPolicy, G> RawRwLockDowngrade for RawRwSpinlock<B, O, G> {
     unsafe fn downgrade(&self) {
-        self.ulock().fetch_add(READER, Ordering::Acquire);
+        self.ulock().fetch_add(READER, O::ACQUIRE);
         self.unlock_exclusive();
     }
 }
 
+#[cfg(feature = "upgrade")]
+unsafe impl<B: Backoff, O: OrderingPolicy, G> RawRwLockUpgradeDowngrade for RawRwSpinlock<B, O, G> {
+    unsafe fn downgrade_upgradable(&self) {
+        self.ulock().fetch_add(READER, O::ACQUIRE);
+        self.unlock_upgradable();
+    }
+
+    unsafe fn downgrade_to_upgradable(&self) {
+        // nobody else can set the upgradable bit while we hold the writer bit, so this swaps one
+        // for the other in a single step
+        self.owner.release();
+        self.ulock().fetch_xor(WRITER | UPGRADED, O::RELEASE);
+    }
+}
+
+#[cfg(feature = "std")]
+unsafe impl<B: Backoff, O: OrderingPolicy, G> RawRwLockTimed for RawRwSpinlock<B, O, G> {
+    type Duration = std::time::Duration;
+    type Instant = std::time::Instant;
+
+    fn try_lock_shared_for(&self, timeout: Self::Duration) -> bool {
+        if let Some(deadline) = StdClock::checked_add(StdClock::now(), timeout) {
+            self.try_lock_shared_until(deadline)
+        } else {
+            self.lock_shared();
+            true
+        }
+    }
+
+    fn try_lock_shared_until(&self, timeout: Self::Instant) -> bool {
+        self.try_lock_shared_deadline::<StdClock>(timeout)
+    }
+
+    fn try_lock_exclusive_for(&self, timeout: Self::Duration) -> bool {
+        if let Some(deadline) = StdClock::checked_add(StdClock::now(), timeout) {
+            self.try_lock_exclusive_until(deadline)
+        } else {
+            self.lock_exclusive();
+            true
+        }
+    }
+
+    fn try_lock_exclusive_until(&self, timeout: Self::Instant) -> bool {
+        self.try_lock_exclusive_deadline::<StdClock>(timeout)
+    }
+}
+
+#[cfg(all(feature = "std", feature = "upgrade"))]
+unsafe impl<B: Backoff, O: OrderingPolicy, G> RawRwLockUpgradeTimed for RawRwSpinlock<B, O, G> {
+    fn try_lock_upgradable_for(&self, timeout: Self::Duration) -> bool {
+        if let Some(deadline) = StdClock::checked_add(StdClock::now(), timeout) {
+            self.try_lock_upgradable_until(deadline)
+        } else {
+            self.lock_upgradable();
+            true
+        }
+    }
+
+    fn try_lock_upgradable_until(&self, timeout: Self::Instant) -> bool {
+        self.try_lock_upgradable_deadline::<StdClock>(timeout)
+    }
+
+    unsafe fn try_upgrade_for(&self, timeout: Self::Duration) -> bool {
+        if let Some(deadline) = StdClock::checked_add(StdClock::now(), timeout) {
+            self.try_upgrade_until(deadline)
+        } else {
+            self.upgrade();
+            true
+        }
+    }
+
+    unsafe fn try_upgrade_until(&self, timeout: Self::Instant) -> bool {
+        self.try_upgrade_deadline::<StdClock>(timeout)
+    }
+}
+
 /// A read-write lock that uses a spinlock internally.
 pub type RwLock<T> = LARwLock<RawRwSpinlock, T>;
 /// A read guard for the read-write lock.
@@ -167,19 +1042,64 @@ pub type RwLockReadGuard<'a, T> = LARwLockReadGuard<'a, RawRwSpinlock, T>;
 /// A write guard fo the read-write lock.
 pub type RwLockWriteGuard<'a, T> = LARwLockWriteGuard<'a, RawRwSpinlock, T>;
 /// An upgradable read guard for the read-write lock.
+#[cfg(feature = "upgrade")]
 pub type RwLockUpgradableReadGuard<'a, T> = LARwLockUpgradableReadGuard<'a, RawRwSpinlock, T>;
+/// A read guard that has been mapped to part of the locked data.
+pub type MappedRwLockReadGuard<'a, T> = LAMappedRwLockReadGuard<'a, RawRwSpinlock, T>;
+/// A write guard that has been mapped to part of the locked data.
+pub type MappedRwLockWriteGuard<'a, T> = LAMappedRwLockWriteGuard<'a, RawRwSpinlock, T>;
+/// A read guard that owns an `Arc` of the read-write lock.
+#[cfg(feature = "arc_lock")]
+pub type ArcRwLockReadGuard<T> = LAArcRwLockReadGuard<RawRwSpinlock, T>;
+/// A write guard that owns an `Arc` of the read-write lock.
+#[cfg(feature = "arc_lock")]
+pub type ArcRwLockWriteGuard<T> = LAArcRwLockWriteGuard<RawRwSpinlock, T>;
+/// An upgradable read guard that owns an `Arc` of the read-write lock.
+#[cfg(all(feature = "arc_lock", feature = "upgrade"))]
+pub type ArcRwLockUpgradableReadGuard<T> = LAArcRwLockUpgradableReadGuard<RawRwSpinlock, T>;
 
-#[test]
-fn basics() {
-    let rwlock = RwLock::new(8);
-    assert_eq!(*rwlock.read(), 8);
-    *rwlock.write() = 7;
-    assert_eq!(*rwlock.read(), 7);
+/// Raw spinlock rwlock whose guards are not `Send`, for data that must stay on the thread that
+/// locked it.
+///
+/// The lock itself can still be shared between threads; only its guards are pinned to the thread
+/// that created them, so one can't be moved away to unlock the lock from elsewhere:
+///
+/// ```compile_fail,E0277
+/// let lock = spinny::RwLockLocal::new(0);
+/// let guard = lock.write();
+/// std::thread::scope(|s| {
+///     s.spawn(move || drop(guard));
+/// });
+/// ```
+pub type RawRwSpinlockLocal<B = DefaultBackoff, O = Standard> = RawRwSpinlock<B, O, GuardNoSend>;
+/// A read-write lock whose guards are not `Send`.
+pub type RwLockLocal<T> = LARwLock<RawRwSpinlockLocal, T>;
+
+/// Create a new, unlocked `RwLock` in a constant context.
+///
+/// This works on stable Rust with every `lock_api` version this crate supports, so it can be used
+/// to initialize a `static`:
+///
+/// ```
+/// static CONFIG: spinny::RwLock<i32> = spinny::const_new(0);
+/// *CONFIG.write() = 1;
+/// assert_eq!(*CONFIG.read(), 1);
+/// ```
+#[must_use]
+pub const fn const_new<T>(value: T) -> RwLock<T> {
+    RwLock::const_new(<RawRwSpinlock as RawRwLock>::INIT, value)
 }
 
 #[cfg(test)]
+// the baseline tests predate the crate's pedantic lints, and are kept as they were written
+#[allow(clippy::needless_for_each, clippy::bool_assert_comparison)]
 mod tests {
-    use super::{RwLock, RwLockUpgradableReadGuard};
+    #[cfg(feature = "upgrade")]
+    use super::RwLockUpgradableReadGuard;
+    use super::{
+        AtomicUsize, Backoff, ExponentialBackoff, Ordering, OrderingPolicy, RawRwSpinlock, RwLock,
+        RwLockReadGuard, RwLockWriteGuard, SeqCstAll, SpinOnly, Standard,
+    };
 
     #[cfg(loom)]
     use loom::thread;
@@ -188,6 +1108,53 @@ mod tests {
 
     use std::{sync::Arc, vec::Vec};
 
+    fn basics_kernel() {
+        let rwlock = RwLock::new(8);
+        assert_eq!(*rwlock.read(), 8);
+        *rwlock.write() = 7;
+        assert_eq!(*rwlock.read(), 7);
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn basics() {
+        loom::model(|| basics_kernel());
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn basics() {
+        basics_kernel();
+    }
+
+    // test that a lock whose first use is two threads contending it is created without a race
+    fn fresh_contention_kernel() {
+        let rwlock = Arc::new(RwLock::new(0));
+        let joiners: Vec<_> = (0..2)
+            .map(|_| {
+                let rclone = rwlock.clone();
+                thread::spawn(move || *rclone.write() += 1)
+            })
+            .collect();
+
+        for j in joiners {
+            j.join().unwrap();
+        }
+        assert_eq!(*rwlock.read(), 2);
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn fresh_contention() {
+        loom::model(|| fresh_contention_kernel());
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn fresh_contention() {
+        fresh_contention_kernel();
+    }
+
     // test multiple reads
     fn multiread_kernel() {
         let rwlock = Arc::new(RwLock::new(7));
@@ -243,39 +1210,1522 @@ mod tests {
         multiwrite_kernel();
     }
 
-    // test upgrading
-    fn upgrade_kernel() {
-        let rwlock = Arc::new(RwLock::new((false, 0)));
-        let mut joiners = Vec::new();
-        for i in 0..2 {
-            let rclone = rwlock.clone();
-            joiners.push(thread::spawn(move || {
-                let lock = RwLock::upgradable_read(&rclone);
+    // test that a blocking and a trying writer, both of which check the state before their
+    // compare-and-swap, are never let in at the same time
+    fn exclusive_writers_kernel() {
+        let rwlock = Arc::new(RwLock::new(()));
+        let inside = Arc::new(AtomicUsize::new(0));
 
-                // even numbers just read the lock, determine the first element is false, then return
-                if i & 1 == 0 {
-                    assert_eq!(lock.0, false);
-                } else {
-                    // odd numbers increment the number
-                    let mut lock = RwLockUpgradableReadGuard::upgrade(lock);
-                    lock.1 += 1;
-                }
-            }));
+        let (rclone, iclone) = (rwlock.clone(), inside.clone());
+        let joiner = thread::spawn(move || {
+            let _guard = rclone.write();
+            assert_eq!(iclone.fetch_add(1, Ordering::Relaxed), 0);
+            iclone.fetch_sub(1, Ordering::Relaxed);
+        });
+
+        if let Some(_guard) = rwlock.try_write() {
+            assert_eq!(inside.fetch_add(1, Ordering::Relaxed), 0);
+            inside.fetch_sub(1, Ordering::Relaxed);
         }
 
-        joiners.into_iter().for_each(|j| j.join().unwrap());
-        assert_eq!(rwlock.read().1, 1);
+        joiner.join().unwrap();
+        assert!(!rwlock.is_locked());
     }
 
     #[cfg(loom)]
     #[test]
-    fn upgrade() {
-        loom::model(|| upgrade_kernel());
+    fn exclusive_writers() {
+        loom::model(|| exclusive_writers_kernel());
     }
 
     #[cfg(not(loom))]
     #[test]
-    fn upgrade() {
-        upgrade_kernel();
+    fn exclusive_writers() {
+        for _ in 0..if cfg!(miri) { 5 } else { 100 } {
+            exclusive_writers_kernel();
+        }
+    }
+
+    // test that a writer is only let in once both of the readers ahead of it have left, with
+    // both readers on one thread so that loom only has one thread spinning to explore
+    fn readers_drain_kernel() {
+        let rwlock = Arc::new(RwLock::new(()));
+        let reading = Arc::new(AtomicUsize::new(2));
+        let (first, second) = (rwlock.read(), rwlock.read());
+
+        let (rclone, iclone) = (rwlock.clone(), reading.clone());
+        let writer = thread::spawn(move || {
+            let _guard = rclone.write();
+            assert_eq!(iclone.load(Ordering::Relaxed), 0);
+        });
+
+        reading.fetch_sub(1, Ordering::Relaxed);
+        drop(first);
+        reading.fetch_sub(1, Ordering::Relaxed);
+        drop(second);
+
+        writer.join().unwrap();
+        assert!(!rwlock.is_locked());
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn readers_drain() {
+        loom::model(|| readers_drain_kernel());
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn readers_drain() {
+        for _ in 0..if cfg!(miri) { 5 } else { 100 } {
+            readers_drain_kernel();
+        }
+    }
+
+    // test that a writer waiting on readers spins on loads, and only attempts a compare-and-swap
+    // once they have left
+    #[cfg(not(loom))]
+    #[test]
+    fn readers_drain_cas_attempts() {
+        use super::CAS_ATTEMPTS;
+        use lock_api::RawRwLock;
+        use std::{sync::Barrier, time::Duration};
+
+        let raw = Arc::new(<RawRwSpinlock>::INIT);
+        let barrier = Arc::new(Barrier::new(2));
+        assert!(raw.try_lock_shared());
+        assert!(raw.try_lock_shared());
+
+        let (rclone, bclone) = (raw.clone(), barrier.clone());
+        let writer = thread::spawn(move || {
+            bclone.wait();
+            let spins = rclone.lock_exclusive_counted();
+            unsafe { rclone.unlock_exclusive() };
+            (spins, CAS_ATTEMPTS.with(std::cell::Cell::get))
+        });
+
+        barrier.wait();
+        thread::sleep(Duration::from_millis(if cfg!(miri) { 1 } else { 20 }));
+        unsafe {
+            raw.unlock_shared();
+            raw.unlock_shared();
+        }
+
+        // a weak compare-and-swap may fail spuriously, but not once per spin
+        let (spins, attempts) = writer.join().unwrap();
+        assert!(attempts >= 1);
+        assert!(
+            attempts < 5,
+            "{} compare-and-swaps in {} spins",
+            attempts,
+            spins
+        );
+    }
+
+    // test upgrading
+    #[cfg(feature = "upgrade")]
+    fn upgrade_kernel() {
+        let rwlock = Arc::new(RwLock::new((false, 0)));
+        let mut joiners = Vec::new();
+        for i in 0..2 {
+            let rclone = rwlock.clone();
+            joiners.push(thread::spawn(move || {
+                let lock = RwLock::upgradable_read(&rclone);
+
+                // even numbers just read the lock, determine the first element is false, then return
+                if i & 1 == 0 {
+                    assert_eq!(lock.0, false);
+                } else {
+                    // odd numbers increment the number
+                    let mut lock = RwLockUpgradableReadGuard::upgrade(lock);
+                    lock.1 += 1;
+                }
+            }));
+        }
+
+        joiners.into_iter().for_each(|j| j.join().unwrap());
+        assert_eq!(rwlock.read().1, 1);
+    }
+
+    #[cfg(all(loom, feature = "upgrade"))]
+    #[test]
+    fn upgrade() {
+        loom::model(|| upgrade_kernel());
+    }
+
+    #[cfg(all(not(loom), feature = "upgrade"))]
+    #[test]
+    fn upgrade() {
+        upgrade_kernel();
+    }
+
+    // test that downgrading keeps other writers out and publishes the writer's changes
+    #[cfg(feature = "upgrade")]
+    fn downgrade_kernel() {
+        let rwlock = Arc::new(RwLock::new(0));
+        // mirrors the data with relaxed accesses, so only the lock's orderings make it visible
+        let shadow = Arc::new(AtomicUsize::new(0));
+        let rclone = rwlock.clone();
+        let sclone = shadow.clone();
+        let other = thread::spawn(move || {
+            let lock = rclone.read();
+            if *lock & 1 == 1 {
+                assert_eq!(sclone.load(Ordering::Relaxed), 1);
+            }
+            drop(lock);
+
+            *rclone.write() += 2;
+        });
+
+        let mut lock = rwlock.write();
+        *lock += 1;
+        shadow.store(1, Ordering::Relaxed);
+        let expected = *lock;
+        let lock = RwLockWriteGuard::downgrade(lock);
+        assert_eq!(*lock, expected);
+        drop(lock);
+
+        other.join().unwrap();
+        assert_eq!(*rwlock.read(), 3);
+    }
+
+    #[cfg(all(loom, feature = "upgrade"))]
+    #[test]
+    fn downgrade() {
+        loom::model(|| downgrade_kernel());
+    }
+
+    #[cfg(all(not(loom), feature = "upgrade"))]
+    #[test]
+    fn downgrade() {
+        downgrade_kernel();
+    }
+
+    // test that a writer can get in while a reader keeps bumping its guard
+    fn bump_kernel() {
+        let rwlock = Arc::new(RwLock::new(0));
+        let rclone = rwlock.clone();
+        let reader = thread::spawn(move || {
+            let mut lock = rclone.read();
+            RwLockReadGuard::bump(&mut lock);
+            RwLockReadGuard::bump(&mut lock);
+            assert!(*lock <= 1);
+        });
+
+        *rwlock.write() += 1;
+
+        reader.join().unwrap();
+        assert_eq!(*rwlock.read(), 1);
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn bump() {
+        loom::model(|| bump_kernel());
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn bump() {
+        bump_kernel();
+    }
+
+    // test that a reader waiting on a writer can get in while the writer bumps its guard,
+    // returning whether it did
+    fn bump_write_kernel() -> bool {
+        let rwlock = Arc::new(RwLock::new(0));
+        let mut guard = rwlock.write();
+
+        let rclone = rwlock.clone();
+        let reader = thread::spawn(move || *rclone.read());
+
+        *guard = 1;
+        RwLockWriteGuard::bump(&mut guard);
+        *guard = 2;
+        drop(guard);
+
+        // the reader only ever sees the data between bumps or after the writer is done
+        let seen = reader.join().unwrap();
+        assert!(seen == 1 || seen == 2);
+        seen == 1
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn bump_write() {
+        static SLIPPED_IN: std::sync::atomic::AtomicBool =
+            std::sync::atomic::AtomicBool::new(false);
+
+        loom::model(|| {
+            if bump_write_kernel() {
+                SLIPPED_IN.store(true, Ordering::Relaxed);
+            }
+        });
+        // some interleaving has to let the reader in during the bump
+        assert!(SLIPPED_IN.load(Ordering::Relaxed));
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn bump_write() {
+        for _ in 0..if cfg!(miri) { 5 } else { 100 } {
+            let _ = bump_write_kernel();
+        }
+    }
+
+    // test reads, writes and upgrades under a specific backoff
+    fn backoff_kernel<B: Backoff + 'static>() {
+        let rwlock = Arc::new(lock_api::RwLock::<RawRwSpinlock<B>, _>::new(0));
+        let mut joiners = Vec::new();
+        for i in 0..2 {
+            let rclone = rwlock.clone();
+            joiners.push(thread::spawn(move || {
+                if i & 1 == 0 {
+                    *rclone.write() += 1;
+                } else {
+                    #[cfg(feature = "upgrade")]
+                    let mut lock =
+                        lock_api::RwLockUpgradableReadGuard::upgrade(rclone.upgradable_read());
+                    #[cfg(not(feature = "upgrade"))]
+                    let mut lock = rclone.write();
+                    *lock += 1;
+                }
+            }));
+        }
+
+        for j in joiners {
+            j.join().unwrap();
+        }
+        assert_eq!(*rwlock.read(), 2);
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn backoff() {
+        loom::model(|| backoff_kernel::<SpinOnly>());
+        loom::model(|| backoff_kernel::<ExponentialBackoff>());
+        loom::model(|| backoff_kernel::<super::JitteredBackoff>());
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn backoff() {
+        backoff_kernel::<SpinOnly>();
+        backoff_kernel::<ExponentialBackoff>();
+        backoff_kernel::<super::JitteredBackoff>();
+        #[cfg(feature = "std")]
+        backoff_kernel::<super::YieldingBackoff>();
+        #[cfg(feature = "std")]
+        backoff_kernel::<super::SpinThenYield>();
+    }
+
+    // test that writers contending under a backoff all get in, `rounds` times each
+    fn contending_writers_kernel<B: Backoff + 'static>(writers: usize, rounds: usize) {
+        let rwlock = Arc::new(lock_api::RwLock::<RawRwSpinlock<B>, _>::new(0));
+        let joiners: Vec<_> = (0..writers)
+            .map(|_| {
+                let rclone = rwlock.clone();
+                thread::spawn(move || {
+                    for _ in 0..rounds {
+                        *rclone.write() += 1;
+                    }
+                })
+            })
+            .collect();
+        for j in joiners {
+            j.join().unwrap();
+        }
+        assert_eq!(*rwlock.read(), writers * rounds);
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn jittered_writers() {
+        loom::model(|| contending_writers_kernel::<super::JitteredBackoff>(2, 1));
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn jittered_writers() {
+        contending_writers_kernel::<super::JitteredBackoff>(8, if cfg!(miri) { 5 } else { 1000 });
+    }
+
+    // stress test that jitter gets a storm of writers through faster than the naive loop, which
+    // retries after a single spin. The numbers depend on the machine, so this only runs when asked
+    // for, as with `cargo test --release -- --ignored writer_storm --nocapture`, and prints them.
+    // Each backoff gets its best of three runs.
+    #[cfg(not(loom))]
+    #[test]
+    #[ignore = "a stress test whose numbers depend on the machine"]
+    fn writer_storm() {
+        use super::JitteredBackoff;
+        use std::time::{Duration, Instant};
+
+        const WRITERS: usize = 8;
+        const ROUNDS: usize = 5_000;
+
+        fn time<B: Backoff + 'static>() -> Duration {
+            (0..3)
+                .map(|_| {
+                    let start = Instant::now();
+                    contending_writers_kernel::<B>(WRITERS, ROUNDS);
+                    start.elapsed()
+                })
+                .min()
+                .unwrap()
+        }
+
+        let (naive, jittered) = (time::<SpinOnly>(), time::<JitteredBackoff>());
+        std::println!(
+            "{WRITERS} writers taking the lock {ROUNDS} times each: {naive:?} with SpinOnly, \
+             {jittered:?} with JitteredBackoff"
+        );
+        // on a single core the writers take turns by time slice and never collide
+        if thread::available_parallelism().map_or(1, usize::from) > 1 {
+            assert!(jittered < naive);
+        }
+    }
+
+    // test that a writer's changes are visible to a later reader under a specific ordering policy
+    fn ordering_kernel<O: OrderingPolicy + 'static>() {
+        let rwlock = Arc::new(lock_api::RwLock::<RawRwSpinlock<SpinOnly, O>, _>::new(0));
+        // mirrors the data with relaxed accesses, so only the lock's orderings make it visible
+        let shadow = Arc::new(AtomicUsize::new(0));
+        let rclone = rwlock.clone();
+        let sclone = shadow.clone();
+        let writer = thread::spawn(move || {
+            let mut lock = rclone.write();
+            *lock = 1;
+            sclone.store(1, Ordering::Relaxed);
+        });
+
+        let lock = rwlock.read();
+        if *lock == 1 {
+            assert_eq!(shadow.load(Ordering::Relaxed), 1);
+        }
+        drop(lock);
+
+        writer.join().unwrap();
+        assert_eq!(*rwlock.read(), 1);
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn ordering() {
+        loom::model(|| ordering_kernel::<Standard>());
+        loom::model(|| ordering_kernel::<SeqCstAll>());
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn ordering() {
+        ordering_kernel::<Standard>();
+        ordering_kernel::<SeqCstAll>();
+    }
+
+    // test that readers backing out of a held lock never leave a phantom reader behind
+    fn rollback_kernel() {
+        let rwlock = Arc::new(RwLock::new(0));
+        let mut guard = rwlock.write();
+        *guard = 1;
+
+        let rclone = rwlock.clone();
+        let reader = thread::spawn(move || {
+            for _ in 0..2 {
+                if let Some(lock) = rclone.try_read() {
+                    assert!(*lock >= 1);
+                }
+            }
+        });
+
+        drop(guard);
+        // this spins forever if a reader's failed increment is never undone
+        *rwlock.write() += 1;
+
+        reader.join().unwrap();
+        assert!(!rwlock.is_locked());
+        assert_eq!(*rwlock.read(), 2);
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn rollback() {
+        loom::model(|| rollback_kernel());
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn rollback() {
+        rollback_kernel();
+    }
+
+    // test that an acquisition which has gone past its spin limit and started yielding still
+    // gets the lock once it is released
+    #[cfg(feature = "std")]
+    #[cfg_attr(loom, ignore)]
+    #[test]
+    fn spin_then_yield() {
+        use super::SpinThenYield;
+        use std::time::Duration;
+
+        let rwlock = Arc::new(lock_api::RwLock::<RawRwSpinlock<SpinThenYield<4>>, _>::new(
+            0,
+        ));
+        let guard = rwlock.write();
+
+        let rclone = rwlock.clone();
+        let writer = thread::spawn(move || {
+            *rclone.write() += 1;
+        });
+
+        std::thread::sleep(Duration::from_millis(20));
+        drop(guard);
+        writer.join().unwrap();
+        assert_eq!(*rwlock.read(), 1);
+    }
+
+    // test that a lock with unsendable guards can still be shared and used across threads
+    #[cfg(not(loom))]
+    #[test]
+    fn local_guards() {
+        use super::RwLockLocal;
+
+        fn assert_sync<T: Sync>(_: &T) {}
+
+        let rwlock = RwLockLocal::new(0);
+        assert_sync(&rwlock);
+        thread::scope(|s| {
+            s.spawn(|| *rwlock.write() += 1);
+        });
+        assert_eq!(*rwlock.read(), 1);
+    }
+
+    // test that a lock restored from its raw state carries on where the original left off
+    #[cfg(not(loom))]
+    #[test]
+    fn from_raw_state() {
+        use super::LockState;
+        use lock_api::RawRwLock;
+
+        let raw: RawRwSpinlock = RawRwSpinlock::INIT;
+        let free = unsafe { <RawRwSpinlock>::from_raw_state(raw.raw_state()) };
+        assert!(!free.is_locked());
+        assert!(free.try_lock_exclusive());
+        unsafe { free.unlock_exclusive() };
+
+        assert!(raw.try_lock_shared());
+        assert!(raw.try_lock_shared());
+        let held = unsafe { <RawRwSpinlock>::from_raw_state(raw.raw_state()) };
+        assert_eq!(held.reader_count(), 2);
+        assert!(!held.try_lock_exclusive());
+        assert!(held.try_lock_shared());
+        unsafe {
+            held.unlock_shared();
+            held.unlock_shared();
+        }
+        assert!(!held.try_lock_exclusive());
+        unsafe { held.unlock_shared() };
+        assert!(held.try_lock_exclusive());
+        assert_eq!(
+            unsafe { <RawRwSpinlock>::from_raw_state(held.raw_state()) }.state(),
+            LockState::Write
+        );
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    #[should_panic(expected = "invalid lock state")]
+    fn from_raw_state_invalid() {
+        let _ = unsafe { <RawRwSpinlock>::from_raw_state(super::WRITER | super::READER) };
+    }
+
+    // test that the uniqueness check passes on a free lock and one the caller reads
+    #[cfg(not(loom))]
+    #[test]
+    fn assert_unique_access() {
+        use super::RwLockExt;
+
+        let rwlock = RwLock::new(0);
+        rwlock.assert_unique_access();
+        let guard = rwlock.read();
+        rwlock.assert_unique_access();
+        drop(guard);
+        *rwlock.write() += 1;
+        rwlock.assert_unique_access();
+    }
+
+    // test that the uniqueness check catches a stray reader
+    #[cfg(all(debug_assertions, not(loom)))]
+    #[test]
+    #[should_panic(expected = "expected unique access")]
+    fn assert_unique_access_stray_reader() {
+        use super::RwLockExt;
+
+        let rwlock = RwLock::new(0);
+        let _mine = rwlock.read();
+        let _stray = rwlock.read();
+        rwlock.assert_unique_access();
+    }
+
+    // test that a plain read on a thread holding an upgradable guard panics
+    #[cfg(all(debug_assertions, feature = "std", not(loom), feature = "upgrade"))]
+    #[test]
+    #[should_panic(expected = "use `read_recursive`")]
+    fn reentrant_read() {
+        let rwlock = RwLock::new(0);
+        let _guard = rwlock.upgradable_read();
+        drop(rwlock.read());
+    }
+
+    // test that the check only fires for the thread holding the lock
+    #[cfg(all(debug_assertions, feature = "std", not(loom), feature = "upgrade"))]
+    #[test]
+    fn reentrant_read_other_thread() {
+        let rwlock = RwLock::new(0);
+        let guard = rwlock.upgradable_read();
+        assert_eq!(*rwlock.read_recursive(), 0);
+        thread::scope(|s| {
+            s.spawn(|| assert_eq!(*rwlock.read(), 1));
+            let mut guard = RwLockUpgradableReadGuard::upgrade(guard);
+            *guard += 1;
+        });
+
+        let guard = rwlock.write();
+        drop(guard);
+        assert_eq!(*rwlock.read(), 1);
+    }
+
+    // test that a write lock released on another thread doesn't stop this one reading
+    #[cfg(all(debug_assertions, feature = "std", not(loom)))]
+    #[test]
+    fn reentrant_read_released_elsewhere() {
+        use lock_api::RawRwLock;
+
+        let rwlock = RwLock::new(0);
+        let raw = unsafe { rwlock.raw() };
+        raw.lock_exclusive();
+        thread::scope(|s| {
+            s.spawn(|| unsafe { raw.unlock_exclusive() });
+        });
+        assert_eq!(*rwlock.read(), 0);
+    }
+
+    // test that the try methods give up at once under contention, and succeed when free
+    #[cfg(not(loom))]
+    #[test]
+    fn try_lock() {
+        let rwlock = RwLock::new(0);
+        assert_eq!(*rwlock.try_read().unwrap(), 0);
+        *rwlock.try_write().unwrap() += 1;
+
+        let guard = rwlock.read();
+        assert!(rwlock.try_write().is_none());
+        assert_eq!(*rwlock.try_read().unwrap(), 1);
+        drop(guard);
+
+        let guard = rwlock.write();
+        assert!(rwlock.try_read().is_none());
+        assert!(rwlock.try_write().is_none());
+        drop(guard);
+
+        assert!(rwlock.try_read().is_some());
+        assert!(rwlock.try_write().is_some());
+    }
+
+    // test that try_upgrade racing a reader either upgrades or returns a usable guard
+    #[cfg(feature = "upgrade")]
+    fn try_upgrade_race_kernel() {
+        let rwlock = Arc::new(RwLock::new(0));
+        let rclone = rwlock.clone();
+        let reader = thread::spawn(move || {
+            // a recursive read can get in alongside the upgradable guard, so it can make
+            // try_upgrade fail at any point
+            let guard = rclone.read_recursive();
+            *guard
+        });
+
+        let guard = rwlock.upgradable_read();
+        let mut guard = match RwLockUpgradableReadGuard::try_upgrade(guard) {
+            Ok(guard) => guard,
+            Err(guard) => {
+                // the guard still holds the lock, so no other upgradable reader can get in
+                assert!(rwlock.try_upgradable_read().is_none());
+                assert_eq!(*guard, 0);
+                RwLockUpgradableReadGuard::upgrade(guard)
+            }
+        };
+        *guard += 1;
+        drop(guard);
+
+        let seen = reader.join().unwrap();
+        assert!(seen == 0 || seen == 1);
+        assert_eq!(*rwlock.read(), 1);
+    }
+
+    #[cfg(all(loom, feature = "upgrade"))]
+    #[test]
+    fn try_upgrade_race() {
+        loom::model(|| try_upgrade_race_kernel());
+    }
+
+    #[cfg(all(not(loom), feature = "upgrade"))]
+    #[test]
+    fn try_upgrade_race() {
+        for _ in 0..if cfg!(miri) { 5 } else { 100 } {
+            try_upgrade_race_kernel();
+        }
+    }
+
+    // test every guard transition in turn on one thread, so that Miri checks them all
+    #[cfg(all(not(loom), feature = "upgrade"))]
+    #[test]
+    fn guard_transitions() {
+        use super::{RwLockReadGuard, RwLockUpgradableReadGuard};
+
+        let rwlock = RwLock::new(0);
+
+        let guard = rwlock.upgradable_read();
+        let reader = rwlock.read_recursive();
+        let guard = RwLockUpgradableReadGuard::try_upgrade(guard).unwrap_err();
+        drop(reader);
+        let mut guard = RwLockUpgradableReadGuard::upgrade(guard);
+        *guard += 1;
+        let mut guard = RwLockWriteGuard::downgrade_to_upgradable(guard);
+        RwLockUpgradableReadGuard::with_upgraded(&mut guard, |value| *value += 1);
+        drop(guard);
+
+        let guard = rwlock.upgradable_read();
+        let mut guard = RwLockUpgradableReadGuard::try_upgrade(guard).unwrap();
+        *guard += 1;
+        let guard = RwLockWriteGuard::downgrade(guard);
+        assert_eq!(*guard, 3);
+        let guard = RwLockReadGuard::map(guard, |value| value);
+        drop(guard);
+
+        let guard = rwlock.upgradable_read();
+        let guard = RwLockUpgradableReadGuard::downgrade(guard);
+        let other = rwlock.read();
+        assert_eq!(*guard + *other, 6);
+        drop((guard, other));
+
+        let mut guard = rwlock.write();
+        RwLockWriteGuard::bump(&mut guard);
+        RwLockWriteGuard::unlocked(&mut guard, || assert!(!rwlock.is_locked()));
+        *guard += 1;
+        drop(guard);
+
+        assert!(!rwlock.is_locked());
+        assert_eq!(rwlock.into_inner(), 4);
+    }
+
+    // test that plain reads and writes still exclude each other with the upgradable paths gone
+    #[cfg(all(not(feature = "upgrade"), not(loom)))]
+    #[test]
+    fn without_upgrade() {
+        use super::LockState;
+
+        let rwlock = Arc::new(RwLock::new(0));
+        let raw = unsafe { rwlock.raw() };
+        let guards = [rwlock.read(), rwlock.read()];
+        assert!(rwlock.try_write().is_none());
+        assert_eq!(raw.state(), LockState::Read(2));
+        drop(guards);
+
+        let joiners: Vec<_> = (0..4)
+            .map(|_| {
+                let rclone = rwlock.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        let before = *rclone.read();
+                        let mut guard = rclone.write();
+                        assert!(*guard >= before);
+                        *guard += 1;
+                    }
+                })
+            })
+            .collect();
+        for j in joiners {
+            j.join().unwrap();
+        }
+        assert_eq!(*rwlock.read(), 400);
+        assert_eq!(raw.raw_state(), 0);
+    }
+
+    // test that the write owner is the thread that took the write lock, and only while it holds it
+    #[cfg(all(feature = "owner-tracking", not(loom)))]
+    #[test]
+    fn write_owner() {
+        use super::{RwLockExt, StdThreadId};
+        use lock_api::GetThreadId;
+        use std::sync::Barrier;
+
+        let rwlock = RwLock::new(0);
+        assert_eq!(rwlock.write_owner(), None);
+        let guard = rwlock.write();
+        assert_eq!(rwlock.write_owner(), Some(StdThreadId.nonzero_thread_id()));
+        drop(guard);
+        assert_eq!(rwlock.write_owner(), None);
+
+        let (locked, checked) = (Barrier::new(2), Barrier::new(2));
+        std::thread::scope(|s| {
+            let writer = s.spawn(|| {
+                let _guard = rwlock.write();
+                locked.wait();
+                checked.wait();
+                StdThreadId.nonzero_thread_id()
+            });
+            locked.wait();
+            let owner = rwlock.write_owner();
+            assert_ne!(owner, Some(StdThreadId.nonzero_thread_id()));
+            checked.wait();
+            assert_eq!(owner, Some(writer.join().unwrap()));
+        });
+        assert_eq!(rwlock.write_owner(), None);
+
+        #[cfg(feature = "upgrade")]
+        {
+            let guard = rwlock.upgradable_read();
+            assert_eq!(rwlock.write_owner(), None);
+            let guard = RwLockUpgradableReadGuard::upgrade(guard);
+            assert_eq!(rwlock.write_owner(), Some(StdThreadId.nonzero_thread_id()));
+            let _guard = RwLockWriteGuard::downgrade(guard);
+            assert_eq!(rwlock.write_owner(), None);
+        }
+    }
+
+    // test that the lock is as big as its documented layout, and no bigger with no data in it
+    #[cfg(not(loom))]
+    #[test]
+    fn lock_size() {
+        use super::LOCK_SIZE;
+        use core::mem;
+
+        assert_eq!(mem::size_of::<RwLock<()>>(), LOCK_SIZE);
+        assert_eq!(mem::size_of::<RawRwSpinlock>(), LOCK_SIZE);
+        #[cfg(not(any(feature = "metrics", feature = "log", feature = "owner-tracking")))]
+        {
+            assert_eq!(LOCK_SIZE, mem::size_of::<usize>());
+            assert_eq!(mem::size_of::<RwLock<()>>(), mem::size_of::<usize>());
+        }
+        assert_eq!(mem::align_of::<RwLock<()>>(), mem::align_of::<usize>());
+    }
+
+    // test the state queries against each kind of guard
+    #[cfg(not(loom))]
+    #[test]
+    fn is_locked() {
+        let rwlock = RwLock::new(());
+        assert!(!rwlock.is_locked());
+        assert!(!rwlock.is_locked_exclusive());
+
+        let guard = rwlock.read();
+        assert!(rwlock.is_locked());
+        assert!(!rwlock.is_locked_exclusive());
+        drop(guard);
+
+        #[cfg(feature = "upgrade")]
+        {
+            let guard = rwlock.upgradable_read();
+            assert!(rwlock.is_locked());
+            assert!(!rwlock.is_locked_exclusive());
+            drop(guard);
+        }
+
+        let guard = rwlock.write();
+        assert!(rwlock.is_locked());
+        assert!(rwlock.is_locked_exclusive());
+        drop(guard);
+
+        assert!(!rwlock.is_locked());
+        assert!(!rwlock.is_locked_exclusive());
+    }
+
+    // test that a lock can live in a static
+    #[cfg(not(loom))]
+    #[test]
+    fn const_new() {
+        static LOCK: RwLock<i32> = super::const_new(3);
+
+        assert_eq!(*LOCK.read(), 3);
+        *LOCK.write() += 1;
+        assert_eq!(*LOCK.read(), 4);
+    }
+
+    // test round-tripping a lock through serde
+    #[cfg(all(feature = "serde", not(loom)))]
+    #[test]
+    fn serde() {
+        let rwlock = RwLock::new(std::vec![1u8, 2, 3]);
+        let text = serde_json::to_string(&rwlock).unwrap();
+        assert_eq!(text, "[1,2,3]");
+
+        let rwlock: RwLock<Vec<u8>> = serde_json::from_str(&text).unwrap();
+        assert_eq!(*rwlock.read(), [1, 2, 3]);
+        assert!(!rwlock.is_locked());
+    }
+
+    // test that locks can be defaulted, including through a derive
+    #[cfg(not(loom))]
+    #[test]
+    fn default() {
+        #[derive(Default)]
+        struct Container {
+            count: RwLock<u32>,
+            name: RwLock<std::string::String>,
+        }
+
+        assert_eq!(*RwLock::<u32>::default().read(), 0);
+
+        let container = Container::default();
+        assert_eq!(*container.count.read(), 0);
+        assert!(container.name.read().is_empty());
+        assert!(!container.count.is_locked());
+    }
+
+    // test that an owning guard can outlive the scope it was created in
+    #[cfg(all(feature = "arc_lock", not(loom)))]
+    #[test]
+    fn arc_lock() {
+        #[cfg(feature = "upgrade")]
+        use super::ArcRwLockUpgradableReadGuard;
+        use super::{ArcRwLockReadGuard, ArcRwLockWriteGuard};
+
+        struct Holder {
+            guard: ArcRwLockReadGuard<i32>,
+        }
+
+        fn make_holder() -> Holder {
+            let rwlock = Arc::new(RwLock::new(1));
+            Holder {
+                guard: rwlock.read_arc(),
+            }
+        }
+
+        let holder = make_holder();
+        assert_eq!(*holder.guard, 1);
+        let rwlock = ArcRwLockReadGuard::into_arc(holder.guard);
+
+        let mut guard: ArcRwLockWriteGuard<i32> = rwlock.write_arc();
+        *guard = 2;
+        drop(guard);
+
+        #[cfg(feature = "upgrade")]
+        {
+            let guard: ArcRwLockUpgradableReadGuard<i32> = rwlock.upgradable_read_arc();
+            assert_eq!(*ArcRwLockUpgradableReadGuard::upgrade(guard), 2);
+        }
+    }
+
+    // test that readers are refused instead of overflowing into the flag bits
+    #[cfg(not(loom))]
+    #[test]
+    fn reader_overflow() {
+        use super::{RawRwLockRecursive, READER, READER_LIMIT};
+
+        let rwlock = RwLock::new(());
+        let raw = unsafe { rwlock.raw() };
+        raw.set_state(READER_LIMIT - READER);
+
+        let guard = rwlock.read();
+        assert!(rwlock.try_read().is_none());
+        assert!(!raw.try_lock_shared_recursive());
+        assert_eq!(raw.reader_count(), READER_LIMIT / READER);
+        assert!(!rwlock.is_locked_exclusive());
+        #[cfg(feature = "upgrade")]
+        assert!(rwlock.try_upgradable_read().is_some());
+
+        drop(guard);
+        assert!(rwlock.try_read().is_some());
+        raw.set_state(0);
+    }
+
+    // test that a failed upgradable read never leaves the upgradable bit behind
+    #[cfg(feature = "upgrade")]
+    fn upgradable_race_kernel() {
+        use super::{Ordering, UPGRADED};
+
+        let rwlock = Arc::new(RwLock::new(0));
+        let wclone = rwlock.clone();
+        let writer = thread::spawn(move || {
+            *wclone.write() += 1;
+        });
+
+        if let Some(lock) = rwlock.try_upgradable_read() {
+            assert!(*lock <= 1);
+        }
+        // we were the only upgradable reader, so the bit must be clear whether or not we got in
+        let raw = unsafe { rwlock.raw() };
+        assert_eq!(raw.ulock().load(Ordering::Relaxed) & UPGRADED, 0);
+
+        writer.join().unwrap();
+        assert!(!rwlock.is_locked());
+        assert!(rwlock.try_upgradable_read().is_some());
+    }
+
+    #[cfg(all(loom, feature = "upgrade"))]
+    #[test]
+    fn upgradable_race() {
+        loom::model(|| upgradable_race_kernel());
+    }
+
+    #[cfg(all(not(loom), feature = "upgrade"))]
+    #[test]
+    fn upgradable_race() {
+        upgradable_race_kernel();
+    }
+
+    // test that other threads can get in while a write guard is temporarily unlocked
+    #[cfg(not(loom))]
+    #[test]
+    fn unlocked() {
+        use super::RwLockWriteGuard;
+
+        let rwlock = Arc::new(RwLock::new(0));
+        let mut lock = rwlock.write();
+        *lock = 1;
+
+        let rclone = rwlock.clone();
+        let seen = RwLockWriteGuard::unlocked(&mut lock, move || {
+            thread::spawn(move || *rclone.read()).join().unwrap()
+        });
+        assert_eq!(seen, 1);
+        assert!(rwlock.is_locked_exclusive());
+        *lock += 1;
+
+        drop(lock);
+        assert_eq!(*rwlock.read(), 2);
+    }
+
+    // test upgrading in place, and that an unwinding closure still drops back to upgradable
+    #[cfg(all(not(loom), feature = "upgrade"))]
+    #[test]
+    fn with_upgraded() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let rwlock = RwLock::new(0);
+        let mut lock = rwlock.upgradable_read();
+
+        lock.with_upgraded(|value| *value += 1);
+        assert_eq!(*lock, 1);
+        assert_eq!(*rwlock.read_recursive(), 1);
+        assert!(!rwlock.is_locked_exclusive());
+
+        assert_eq!(
+            lock.with_upgraded(|value| {
+                *value += 1;
+                *value
+            }),
+            2
+        );
+        assert_eq!(*rwlock.read_recursive(), 2);
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            lock.with_upgraded(|_| panic!("closure failed"));
+        }));
+        assert!(result.is_err());
+        assert!(!rwlock.is_locked_exclusive());
+        assert!(rwlock.try_upgradable_read().is_none());
+        assert_eq!(*lock, 2);
+
+        drop(lock);
+        assert!(!rwlock.is_locked());
+    }
+
+    // test that downgrading an upgradable reader swaps the upgradable bit for a reader
+    #[cfg(all(not(loom), feature = "upgrade"))]
+    #[test]
+    fn downgrade_upgradable() {
+        use super::RwLockExt;
+
+        let rwlock = RwLock::new(1);
+        let lock = RwLockUpgradableReadGuard::downgrade(rwlock.upgradable_read());
+        assert_eq!(*lock, 1);
+        assert_eq!(rwlock.reader_count(), 1);
+
+        // a second upgradable reader gets in, but writers are still kept out
+        let upgradable = rwlock.try_upgradable_read().unwrap();
+        assert!(rwlock.try_write().is_none());
+        drop(upgradable);
+        assert!(rwlock.try_write().is_none());
+
+        drop(lock);
+        assert!(rwlock.try_write().is_some());
+    }
+
+    // test downgrading a writer to an upgradable reader
+    #[cfg(all(not(loom), feature = "upgrade"))]
+    #[test]
+    fn downgrade_to_upgradable() {
+        use super::RwLockWriteGuard;
+
+        let rwlock = RwLock::new(1);
+        let mut lock = rwlock.write();
+        *lock = 2;
+
+        let lock = RwLockWriteGuard::downgrade_to_upgradable(lock);
+        assert_eq!(*lock, 2);
+        assert!(!rwlock.is_locked_exclusive());
+        assert!(rwlock.try_write().is_none());
+        assert!(rwlock.try_upgradable_read().is_none());
+
+        // new plain readers wait for upgradable readers, but readers that can recurse get in
+        assert!(rwlock.try_read().is_none());
+        let reader = rwlock.read_recursive();
+        assert_eq!(*reader, 2);
+        drop(reader);
+
+        // fully downgrading lets plain readers in, while still keeping writers out
+        let lock = RwLockUpgradableReadGuard::downgrade(lock);
+        assert_eq!(*rwlock.try_read().unwrap(), 2);
+        assert!(rwlock.try_write().is_none());
+        assert!(rwlock.try_upgradable_read().is_some());
+
+        drop(lock);
+        assert!(!rwlock.is_locked());
+    }
+
+    // test that spinning against a held lock is counted
+    #[cfg(all(feature = "metrics", not(loom)))]
+    #[test]
+    fn contention_count() {
+        use super::RwLockExt;
+
+        let rwlock = Arc::new(RwLock::new(0));
+        let guard = rwlock.write();
+        assert_eq!(rwlock.contention_count(), 0);
+
+        let rclone = rwlock.clone();
+        let reader = thread::spawn(move || *rclone.read());
+        while rwlock.contention_count() == 0 {
+            thread::yield_now();
+        }
+
+        drop(guard);
+        assert_eq!(reader.join().unwrap(), 0);
+        assert!(rwlock.contention_count() > 0);
+    }
+
+    // test that a writer held up by a reader is seen waiting by another reader
+    #[cfg(all(feature = "metrics", not(loom)))]
+    #[test]
+    fn waiting_writers() {
+        use super::RwLockExt;
+
+        let rwlock = RwLock::new(0);
+        let guard = rwlock.read();
+        assert_eq!(rwlock.waiting_writers(), 0);
+        thread::scope(|s| {
+            let writer = s.spawn(|| *rwlock.write() += 1);
+            let observer = s.spawn(|| {
+                let _guard = rwlock.read();
+                while rwlock.waiting_writers() == 0 {
+                    thread::yield_now();
+                }
+                rwlock.waiting_writers()
+            });
+            assert!(observer.join().unwrap() >= 1);
+            drop(guard);
+            writer.join().unwrap();
+        });
+        assert_eq!(rwlock.waiting_writers(), 0);
+        assert_eq!(rwlock.stats().waiting_writers, 0);
+        assert_eq!(*rwlock.read(), 1);
+    }
+
+    // test that contended acquisitions show up in the stats
+    #[cfg(all(feature = "metrics", not(loom)))]
+    #[test]
+    fn stats() {
+        use super::{LockState, RwLockExt};
+
+        let rwlock = RwLock::new(0);
+        let stats = rwlock.stats();
+        assert_eq!((stats.acquisitions, stats.total_spins), (0, 0));
+        assert_eq!(stats.state, LockState::Free);
+
+        for _ in 0..3 {
+            let guard = rwlock.write();
+            thread::scope(|s| {
+                s.spawn(|| *rwlock.write() += 1);
+                let spins = rwlock.contention_count();
+                while rwlock.contention_count() == spins {
+                    thread::yield_now();
+                }
+                drop(guard);
+            });
+        }
+
+        let _guard = rwlock.read();
+        let stats = rwlock.stats();
+        assert_eq!(stats.acquisitions, 7);
+        assert!(stats.total_spins >= 3);
+        assert!(stats.max_spins >= 1);
+        assert!(stats.max_spins <= stats.total_spins);
+        assert_eq!(stats.state, LockState::Read(1));
+    }
+
+    // test mapping guards into part of the locked data
+    #[cfg(not(loom))]
+    #[test]
+    fn map() {
+        use super::{MappedRwLockReadGuard, MappedRwLockWriteGuard, RwLockWriteGuard};
+
+        let rwlock = RwLock::new((1, 2));
+
+        let mut mapped: MappedRwLockWriteGuard<'_, i32> =
+            RwLockWriteGuard::map(rwlock.write(), |pair| &mut pair.1);
+        *mapped += 1;
+        assert!(rwlock.try_read().is_none());
+        drop(mapped);
+
+        let mapped: MappedRwLockReadGuard<'_, i32> =
+            RwLockReadGuard::map(rwlock.read(), |pair| &pair.1);
+        assert_eq!(*mapped, 3);
+        assert!(rwlock.try_write().is_none());
+        drop(mapped);
+
+        assert_eq!(*rwlock.read(), (1, 3));
+    }
+
+    // test fallible mapping, which gives the guard back on failure
+    #[cfg(not(loom))]
+    #[test]
+    fn try_map() {
+        use super::RwLockWriteGuard;
+
+        let rwlock = RwLock::new((Some(1), None::<i32>));
+
+        let mapped = RwLockReadGuard::try_map(rwlock.read(), |pair| pair.0.as_ref())
+            .unwrap_or_else(|_| panic!("first field is set"));
+        assert_eq!(*mapped, 1);
+        drop(mapped);
+
+        let Err(guard) = RwLockReadGuard::try_map(rwlock.read(), |pair| pair.1.as_ref()) else {
+            panic!("second field is unset");
+        };
+        assert_eq!(guard.0, Some(1));
+        drop(guard);
+
+        let mut mapped = RwLockWriteGuard::try_map(rwlock.write(), |pair| pair.0.as_mut())
+            .unwrap_or_else(|_| panic!("first field is set"));
+        *mapped += 1;
+        drop(mapped);
+
+        let Err(mut guard) = RwLockWriteGuard::try_map(rwlock.write(), |pair| pair.1.as_mut())
+        else {
+            panic!("second field is unset");
+        };
+        guard.1 = Some(3);
+        drop(guard);
+
+        assert_eq!(*rwlock.read(), (Some(2), Some(3)));
+    }
+
+    // test that an uncontended acquisition never reports spinning
+    #[cfg(not(loom))]
+    #[test]
+    fn counted() {
+        use lock_api::RawRwLock;
+        #[cfg(feature = "upgrade")]
+        use lock_api::RawRwLockUpgrade;
+
+        let raw = <RawRwSpinlock>::INIT;
+
+        assert_eq!(raw.lock_exclusive_counted(), 0);
+        unsafe { raw.unlock_exclusive() };
+
+        #[cfg(feature = "upgrade")]
+        {
+            assert_eq!(raw.lock_upgradable_counted(), 0);
+            unsafe { raw.unlock_upgradable() };
+        }
+
+        assert_eq!(raw.lock_shared_counted(), 0);
+        assert_eq!(raw.lock_shared_counted(), 0);
+        unsafe {
+            raw.unlock_shared();
+            raw.unlock_shared();
+        }
+        assert!(!raw.is_locked());
+    }
+
+    // test reading through the data pointer while a guard keeps the lock
+    #[cfg(not(loom))]
+    #[test]
+    fn data_ptr() {
+        let rwlock = RwLock::new(5);
+        let mut lock = rwlock.write();
+        *lock += 1;
+
+        let ptr = rwlock.data_ptr();
+        // SAFETY: we hold the write lock, and don't touch the guard while using the pointer
+        assert_eq!(unsafe { *ptr }, 6);
+        unsafe { *ptr = 7 };
+        assert_eq!(*lock, 7);
+    }
+
+    // test that mutating through get_mut is seen by later guards
+    #[cfg(not(loom))]
+    #[test]
+    fn get_mut() {
+        let mut rwlock = RwLock::new(5);
+        *rwlock.get_mut() += 1;
+        assert!(!rwlock.is_locked());
+        assert_eq!(*rwlock.read(), 6);
+    }
+
+    // test taking the data out of a lock that has been used
+    fn into_inner_kernel() {
+        use std::string::String;
+
+        let rwlock = RwLock::new(String::from("spin"));
+        rwlock.write().push_str("ny");
+        assert_eq!(rwlock.into_inner(), "spinny");
+    }
+
+    // under loom, this also covers a lock whose state was lazily initialized
+    #[cfg(loom)]
+    #[test]
+    fn into_inner() {
+        loom::model(into_inner_kernel);
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn into_inner() {
+        into_inner_kernel();
+    }
+
+    // test recovering from leaked guards
+    #[cfg(not(loom))]
+    #[test]
+    fn force_unlock() {
+        let rwlock = RwLock::new(0);
+
+        core::mem::forget(rwlock.write());
+        assert!(rwlock.try_read().is_none());
+        unsafe { rwlock.force_unlock_write() };
+        assert!(!rwlock.is_locked());
+
+        core::mem::forget(rwlock.read());
+        assert!(rwlock.try_write().is_none());
+        unsafe { rwlock.force_unlock_read() };
+        assert!(rwlock.try_write().is_some());
+    }
+
+    // test that a contended acquisition reports its spins to a subscriber
+    #[cfg(all(feature = "tracing", not(loom)))]
+    #[test]
+    fn tracing_span() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::time::Duration;
+        use tracing::{
+            field::{Field, Visit},
+            span, Event, Metadata, Subscriber,
+        };
+
+        // keeps the largest spin count recorded on any span
+        struct MaxSpins(Arc<AtomicU64>);
+
+        impl Visit for &MaxSpins {
+            fn record_u64(&mut self, field: &Field, value: u64) {
+                if field.name() == "spins" {
+                    self.0.fetch_max(value, Ordering::Relaxed);
+                }
+            }
+
+            fn record_debug(&mut self, _field: &Field, _value: &dyn core::fmt::Debug) {}
+        }
+
+        impl Subscriber for MaxSpins {
+            fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, _attrs: &span::Attributes<'_>) -> span::Id {
+                span::Id::from_u64(1)
+            }
+
+            fn record(&self, _span: &span::Id, values: &span::Record<'_>) {
+                values.record(&mut &*self);
+            }
+
+            fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+            fn event(&self, _event: &Event<'_>) {}
+
+            fn enter(&self, _span: &span::Id) {}
+
+            fn exit(&self, _span: &span::Id) {}
+        }
+
+        let rwlock = Arc::new(RwLock::new(0));
+        let spins = Arc::new(AtomicU64::new(0));
+        let guard = rwlock.write();
+
+        let rclone = rwlock.clone();
+        let sclone = spins.clone();
+        let writer = thread::spawn(move || {
+            tracing::subscriber::with_default(MaxSpins(sclone), || *rclone.write() += 1);
+        });
+
+        thread::sleep(Duration::from_millis(20));
+        drop(guard);
+        writer.join().unwrap();
+        assert!(spins.load(Ordering::Relaxed) > 0);
+    }
+
+    // test that locking the same lock twice on one thread is caught
+    #[cfg(all(feature = "deadlock-detection", not(loom)))]
+    // spinning for half a second takes far too long under Miri
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    #[should_panic(expected = "possible deadlock")]
+    fn deadlock_detection() {
+        use std::time::Duration;
+
+        super::set_deadlock_timeout(Duration::from_millis(500));
+        let rwlock = RwLock::new(0);
+        let _guard = rwlock.write();
+        let _again = rwlock.write();
+    }
+
+    // test recursive reads while an upgradable reader is present
+    #[cfg(all(not(loom), feature = "upgrade"))]
+    #[test]
+    fn read_recursive() {
+        let rwlock = RwLock::new(5);
+        let upgradable = rwlock.upgradable_read();
+        assert!(rwlock.try_read().is_none());
+
+        let outer = rwlock.read_recursive();
+        let inner = rwlock.read_recursive();
+        assert_eq!(*outer, 5);
+        assert_eq!(*inner, 5);
+        assert_eq!(*upgradable, 5);
+
+        drop(inner);
+        drop(outer);
+        let mut lock = RwLockUpgradableReadGuard::upgrade(upgradable);
+        *lock = 6;
+        drop(lock);
+        assert_eq!(*rwlock.read(), 6);
+    }
+
+    // test that a recursive try gets in alongside an upgradable reader, and only a writer stops it
+    #[cfg(all(not(loom), feature = "upgrade"))]
+    #[test]
+    fn try_read_recursive() {
+        use super::RwLockExt;
+
+        let rwlock = RwLock::new(5);
+        let upgradable = rwlock.upgradable_read();
+        assert!(rwlock.try_read().is_none());
+        let reader = rwlock.try_read_recursive().unwrap();
+        assert_eq!(*reader, 5);
+        assert_eq!(rwlock.reader_count(), 1);
+        drop((reader, upgradable));
+
+        let guard = rwlock.write();
+        assert!(rwlock.try_read_recursive().is_none());
+        assert_eq!(rwlock.reader_count(), 0);
+        drop(guard);
+        assert!(rwlock.try_read_recursive().is_some());
+    }
+
+    // test deadline spinning against a clock that ticks forward every time it is read
+    #[cfg(not(loom))]
+    #[test]
+    fn deadline_with_custom_clock() {
+        use super::{Clock, RawRwSpinlock};
+        use lock_api::RawRwLock;
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        static TICKS: AtomicU64 = AtomicU64::new(0);
+
+        struct TickClock;
+
+        impl Clock for TickClock {
+            type Instant = u64;
+            type Duration = u64;
+
+            fn now() -> u64 {
+                TICKS.fetch_add(1, Ordering::Relaxed)
+            }
+
+            fn checked_add(instant: u64, duration: u64) -> Option<u64> {
+                instant.checked_add(duration)
+            }
+        }
+
+        let raw = <RawRwSpinlock>::INIT;
+        let deadline = TickClock::checked_add(TickClock::now(), 10).unwrap();
+        assert!(raw.try_lock_exclusive_deadline::<TickClock>(deadline));
+        assert!(!raw.try_lock_shared_deadline::<TickClock>(deadline + 10));
+        assert!(TickClock::now() >= deadline + 10);
+
+        unsafe { raw.unlock_exclusive() };
+        assert!(raw.try_lock_shared_deadline::<TickClock>(deadline));
+    }
+
+    #[cfg(all(feature = "std", not(loom)))]
+    #[test]
+    fn try_write_for_times_out() {
+        use std::time::{Duration, Instant};
+
+        let rwlock = RwLock::new(0);
+        let guard = rwlock.write();
+
+        let start = Instant::now();
+        assert!(rwlock.try_write_for(Duration::from_millis(50)).is_none());
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(50));
+        assert!(elapsed < Duration::from_secs(5));
+        assert!(rwlock.try_read_for(Duration::from_millis(10)).is_none());
+
+        drop(guard);
+        assert!(rwlock.try_write_for(Duration::from_millis(50)).is_some());
+    }
+
+    // test that a timed upgrade gives up while another reader stays, keeping the upgradable lock
+    #[cfg(all(feature = "std", not(loom), feature = "upgrade"))]
+    #[test]
+    fn try_upgrade_for_times_out() {
+        use super::RwLockUpgradableReadGuard;
+        use std::{sync::mpsc, thread, time::Duration};
+
+        let rwlock = RwLock::new(0);
+        let (entered, wait_entered) = mpsc::channel();
+        let (done, wait_done) = mpsc::channel();
+
+        thread::scope(|s| {
+            let reader = &rwlock;
+            s.spawn(move || {
+                let _guard = reader.read();
+                entered.send(()).unwrap();
+                wait_done.recv().unwrap();
+            });
+            wait_entered.recv().unwrap();
+
+            let guard = rwlock.upgradable_read();
+            // only one upgradable reader can exist at once, so a second one times out too
+            assert!(rwlock
+                .try_upgradable_read_for(Duration::from_millis(10))
+                .is_none());
+            let guard =
+                RwLockUpgradableReadGuard::try_upgrade_for(guard, Duration::from_millis(50))
+                    .unwrap_err();
+            assert!(rwlock.is_locked());
+            assert!(!rwlock.is_locked_exclusive());
+            assert!(rwlock.try_upgradable_read().is_none());
+            assert_eq!(*guard, 0);
+
+            done.send(()).unwrap();
+            let mut guard =
+                RwLockUpgradableReadGuard::try_upgrade_for(guard, Duration::from_secs(5)).unwrap();
+            *guard += 1;
+        });
+
+        assert_eq!(*rwlock.read(), 1);
     }
 }
@@ -0,0 +1,198 @@
+// MIT/Apache2 License
+
+//! A `Vec` behind a lock, with an iterator that holds the read lock itself.
+
+use super::{RwLock, RwLockReadGuard};
+use core::{fmt, iter::FusedIterator, ops::Deref};
+use std::vec::Vec;
+
+/// A `Vec` behind an [`RwLock`], whose [`iter`](LockedVec::iter) can be returned from a function.
+///
+/// Iterating through a read guard borrows from the guard, so the iterator can't outlive the
+/// function that took the guard. The iterator returned by `iter` owns the guard instead, and
+/// releases it as soon as the last element has been yielded, even if the iterator itself is kept
+/// around.
+///
+/// An iterator can't hand out references into the data that outlive it, since the lock protecting
+/// them would be released when it is dropped, so `iter` yields clones. For references, lock the
+/// `RwLock` this dereferences to and iterate through the guard as usual.
+///
+/// ```
+/// use spinny::LockedVec;
+///
+/// fn evens(numbers: &LockedVec<u32>) -> impl Iterator<Item = u32> + '_ {
+///     numbers.iter().filter(|n| n % 2 == 0)
+/// }
+///
+/// let numbers = LockedVec::new(vec![1, 2, 3, 4]);
+/// assert_eq!(evens(&numbers).collect::<Vec<_>>(), [2, 4]);
+/// numbers.write().push(6);
+/// assert_eq!(evens(&numbers).count(), 3);
+/// ```
+#[derive(Default)]
+pub struct LockedVec<T> {
+    lock: RwLock<Vec<T>>,
+}
+
+impl<T> LockedVec<T> {
+    /// Create a new `LockedVec` holding `vec`.
+    #[inline]
+    #[must_use]
+    pub const fn new(vec: Vec<T>) -> Self {
+        LockedVec {
+            lock: RwLock::new(vec),
+        }
+    }
+
+    /// Consume the `LockedVec` and return the `Vec` inside it.
+    #[inline]
+    #[must_use]
+    pub fn into_inner(self) -> Vec<T> {
+        self.lock.into_inner()
+    }
+
+    /// Iterate over clones of the elements, holding a read lock until the last one is yielded.
+    ///
+    /// This spins to take the read lock, just like `read`.
+    #[inline]
+    pub fn iter(&self) -> LockedVecIter<'_, T>
+    where
+        T: Clone,
+    {
+        LockedVecIter {
+            guard: Some(self.lock.read()),
+            index: 0,
+        }
+    }
+}
+
+impl<T> Deref for LockedVec<T> {
+    type Target = RwLock<Vec<T>>;
+
+    #[inline]
+    fn deref(&self) -> &RwLock<Vec<T>> {
+        &self.lock
+    }
+}
+
+impl<'a, T: Clone> IntoIterator for &'a LockedVec<T> {
+    type Item = T;
+    type IntoIter = LockedVecIter<'a, T>;
+
+    #[inline]
+    fn into_iter(self) -> LockedVecIter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> From<Vec<T>> for LockedVec<T> {
+    #[inline]
+    fn from(vec: Vec<T>) -> Self {
+        LockedVec::new(vec)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for LockedVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LockedVec")
+            .field("lock", &self.lock)
+            .finish()
+    }
+}
+
+/// An iterator over clones of the elements of a [`LockedVec`], returned by
+/// [`LockedVec::iter`].
+///
+/// It holds a read lock on the `LockedVec` until it has yielded every element or is dropped,
+/// whichever comes first.
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct LockedVecIter<'a, T> {
+    // `None` once every element has been yielded and the lock released
+    guard: Option<RwLockReadGuard<'a, Vec<T>>>,
+    index: usize,
+}
+
+impl<T: Clone> Iterator for LockedVecIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let guard = self.guard.as_ref()?;
+        let item = guard.get(self.index).cloned();
+        self.index += 1;
+        if self.index >= guard.len() {
+            // release the lock along with the last element, rather than on the call after it
+            self.guard = None;
+        }
+        item
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self
+            .guard
+            .as_ref()
+            .map_or(0, |guard| guard.len() - self.index);
+        (remaining, Some(remaining))
+    }
+}
+
+impl<T: Clone> ExactSizeIterator for LockedVecIter<'_, T> {}
+
+impl<T: Clone> FusedIterator for LockedVecIter<'_, T> {}
+
+impl<T: fmt::Debug> fmt::Debug for LockedVecIter<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let remaining = self
+            .guard
+            .as_ref()
+            .map_or(&[][..], |guard| &guard[self.index..]);
+        f.debug_tuple("LockedVecIter").field(&remaining).finish()
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::LockedVec;
+    use std::{thread, vec, vec::Vec};
+
+    // test that a writer waiting on the iterator gets in once it is used up
+    #[test]
+    fn sum_while_writer_waits() {
+        let locked = LockedVec::new((1..=100).collect::<Vec<u32>>());
+
+        let mut iter = locked.iter();
+        assert_eq!(iter.len(), 100);
+        thread::scope(|s| {
+            let writer = s.spawn(|| locked.write().push(1000));
+            let sum: u32 = iter.by_ref().sum();
+            assert_eq!(sum, 5050);
+            writer.join().unwrap();
+        });
+
+        // the iterator is still alive, but has already released the lock
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.len(), 0);
+        assert!(!locked.is_locked());
+        assert_eq!(locked.iter().sum::<u32>(), 6050);
+    }
+
+    #[test]
+    fn drop_early() {
+        let locked = LockedVec::from(vec![1, 2, 3]);
+        assert_eq!((&locked).into_iter().collect::<Vec<_>>(), [1, 2, 3]);
+        let mut iter = locked.iter();
+        assert_eq!(iter.next(), Some(1));
+        assert!(locked.try_write().is_none());
+        drop(iter);
+        assert!(locked.try_write().is_some());
+
+        let mut iter = locked.iter();
+        assert_eq!(iter.nth(2), Some(3));
+        // released with the last element, not on the next call
+        assert!(locked.try_write().is_some());
+        assert_eq!(iter.next(), None);
+        drop(iter);
+        assert_eq!(locked.into_inner(), [1, 2, 3]);
+    }
+}
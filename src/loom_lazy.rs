@@ -0,0 +1,94 @@
+// MIT/Apache2 License
+
+//! Lazily created state for the locks under loom, whose atomics can't be created in a `const`.
+//!
+//! The locks' `INIT` constants can't hold a loom atomic, so under loom each lock creates its
+//! atomic on first use, inside a `OnceCell`. Loom can't see the synchronization inside the
+//! `OnceCell`, so a thread that finds the atomic already created by another thread would look to
+//! loom like it is racing the creation. This models that synchronization with loom atomics, the
+//! same way `loom::lazy_static!` models the real `lazy_static`: creating the state is a release,
+//! and every other thread acquires it before first touching the state.
+
+use loom::{
+    lazy_static,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread::{self, ThreadId},
+};
+use once_cell::sync::OnceCell;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering as StdOrdering},
+        Mutex,
+    },
+    vec::Vec,
+};
+
+lazy_static! {
+    // One for every lock, since a loom atomic can't live in a lock until it has been created.
+    // Sharing it only adds edges from the creating thread's work before the creation, which the
+    // locks never rely on.
+    static ref CREATED: AtomicUsize = AtomicUsize::new(0);
+}
+
+struct Created<T> {
+    value: T,
+    creator: ThreadId,
+    // set by the creator once its release has happened
+    published: AtomicBool,
+}
+
+/// A value created on first use, whose creation loom sees as synchronized with every other
+/// thread that uses it.
+pub(crate) struct LoomLazy<T> {
+    cell: OnceCell<Created<T>>,
+    // the threads that have already acquired the creation
+    synced: Mutex<Vec<ThreadId>>,
+}
+
+impl<T> LoomLazy<T> {
+    pub(crate) const fn new() -> Self {
+        LoomLazy {
+            cell: OnceCell::new(),
+            synced: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Get the value, creating it with `init` if this is the first use.
+    ///
+    /// `init` mustn't perform loom operations: every loom thread runs on the same OS thread, so
+    /// one that was switched away from inside the `OnceCell` would block the rest for real.
+    pub(crate) fn get_or_init(&self, init: impl FnOnce() -> T) -> &T {
+        let current = thread::current().id();
+        let mut creating = false;
+        let created = self.cell.get_or_init(|| {
+            creating = true;
+            Created {
+                value: init(),
+                creator: current,
+                published: AtomicBool::new(false),
+            }
+        });
+
+        if creating {
+            CREATED.fetch_add(1, Ordering::Release);
+            created.published.store(true, StdOrdering::Relaxed);
+        } else if created.creator != current && !self.has_synced(current) {
+            // loom may have switched away from the creator between creating the value and
+            // releasing it
+            while !created.published.load(StdOrdering::Relaxed) {
+                thread::yield_now();
+            }
+
+            // a read-modify-write always reads the latest value, so this reads from the release
+            CREATED.fetch_add(0, Ordering::Acquire);
+            self.synced.lock().unwrap().push(current);
+        }
+
+        &created.value
+    }
+
+    // the lock on the list is never held across a loom operation, for the reason given above
+    fn has_synced(&self, thread: ThreadId) -> bool {
+        self.synced.lock().unwrap().contains(&thread)
+    }
+}
@@ -0,0 +1,108 @@
+// MIT/Apache2 License
+
+//! Contention counters, enabled by the `metrics` feature.
+
+// These are plain atomics even under loom: they don't take part in synchronization, and the
+// counters need to be constructible in `INIT`.
+use super::{
+    atomic::{CounterUsize as AtomicUsize, Ordering},
+    LockState,
+};
+
+/// Per-lock contention counters.
+pub(crate) struct Metrics {
+    spins: AtomicUsize,
+    acquisitions: AtomicUsize,
+    max_spins: AtomicUsize,
+    waiting_writers: AtomicUsize,
+}
+
+impl Metrics {
+    pub(crate) const fn new() -> Self {
+        Metrics {
+            spins: AtomicUsize::new(0),
+            acquisitions: AtomicUsize::new(0),
+            max_spins: AtomicUsize::new(0),
+            waiting_writers: AtomicUsize::new(0),
+        }
+    }
+
+    /// Record a failed acquisition attempt that is about to spin.
+    #[inline]
+    pub(crate) fn spin(&self) {
+        self.spins.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a blocking acquisition that succeeded after `spins` failed attempts.
+    #[inline]
+    pub(crate) fn acquired(&self, spins: usize) {
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        if spins != 0 {
+            self.max_spins.fetch_max(spins, Ordering::Relaxed);
+        }
+    }
+
+    /// Record a writer that has started spinning, until the returned guard is dropped.
+    #[inline]
+    pub(crate) fn writer_waiting(&self) -> WaitingWriter<'_> {
+        self.waiting_writers.fetch_add(1, Ordering::Relaxed);
+        WaitingWriter {
+            waiting_writers: &self.waiting_writers,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn waiting_writers(&self) -> usize {
+        self.waiting_writers.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub(crate) fn spins(&self) -> u64 {
+        self.spins.load(Ordering::Relaxed) as u64
+    }
+
+    pub(crate) fn stats(&self, state: LockState) -> LockStats {
+        LockStats {
+            acquisitions: self.acquisitions.load(Ordering::Relaxed) as u64,
+            total_spins: self.spins(),
+            max_spins: self.max_spins.load(Ordering::Relaxed) as u64,
+            waiting_writers: self.waiting_writers(),
+            state,
+        }
+    }
+}
+
+/// A summary of a lock's contention over its lifetime, returned by
+/// [`RawRwSpinlock::stats`](crate::RawRwSpinlock::stats).
+///
+/// Each counter is read separately with a relaxed load while other threads may be updating them,
+/// so they are approximate and may not agree exactly with each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct LockStats {
+    /// The number of blocking acquisitions, including upgrades. Try and timed acquisitions
+    /// aren't counted.
+    pub acquisitions: u64,
+    /// The number of failed attempts across every acquisition; the same as
+    /// [`contention_count`](crate::RawRwSpinlock::contention_count).
+    pub total_spins: u64,
+    /// The most failed attempts any single blocking acquisition has made.
+    pub max_spins: u64,
+    /// The number of writers spinning on the lock when the snapshot was taken; see
+    /// [`waiting_writers`](crate::RawRwSpinlock::waiting_writers).
+    pub waiting_writers: usize,
+    /// The state of the lock when the snapshot was taken.
+    pub state: LockState,
+}
+
+/// Counts a writer as waiting for as long as it lives, including when the wait ends in a panic.
+pub(crate) struct WaitingWriter<'a> {
+    waiting_writers: &'a AtomicUsize,
+}
+
+impl Drop for WaitingWriter<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        self.waiting_writers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
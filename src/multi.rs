@@ -0,0 +1,144 @@
+// MIT/Apache2 License
+
+//! Write-locking several locks at once without risking a lock-order deadlock.
+
+use lock_api::{RawRwLock, RwLock as LARwLock, RwLockWriteGuard as LARwLockWriteGuard};
+
+/// The address that decides the order locks are taken in.
+#[inline]
+fn addr<L: ?Sized>(lock: &L) -> usize {
+    let lock: *const L = lock;
+    lock.cast::<()>() as usize
+}
+
+/// Write-lock two locks, always taking them in the same global order.
+///
+/// Two threads that lock the same two locks with this can never deadlock on each other,
+/// whichever order they pass them in. The guards are returned in the order the locks were
+/// passed.
+///
+/// # Panics
+///
+/// Panics if both arguments are the same lock, since locking it twice would deadlock.
+pub fn lock_two<'a, R: RawRwLock, T: ?Sized, U: ?Sized>(
+    first: &'a LARwLock<R, T>,
+    second: &'a LARwLock<R, U>,
+) -> (LARwLockWriteGuard<'a, R, T>, LARwLockWriteGuard<'a, R, U>) {
+    let (first_addr, second_addr) = (addr(first), addr(second));
+    assert_ne!(first_addr, second_addr, "the same lock was passed twice");
+
+    if first_addr < second_addr {
+        let first = first.write();
+        (first, second.write())
+    } else {
+        let second = second.write();
+        (first.write(), second)
+    }
+}
+
+/// Write-lock every lock in `locks`, always taking them in the same global order.
+///
+/// This is [`lock_two`] for any fixed number of locks. The guards are returned in the order the
+/// locks were passed.
+///
+/// # Panics
+///
+/// Panics if the same lock appears more than once.
+pub fn lock_all<R: RawRwLock, T: ?Sized, const N: usize>(
+    locks: [&LARwLock<R, T>; N],
+) -> [LARwLockWriteGuard<'_, R, T>; N] {
+    let mut order = [0; N];
+    for (i, slot) in order.iter_mut().enumerate() {
+        *slot = i;
+    }
+    order.sort_unstable_by_key(|&i| addr(locks[i]));
+    for pair in order.windows(2) {
+        assert_ne!(
+            addr(locks[pair[0]]),
+            addr(locks[pair[1]]),
+            "the same lock was passed twice"
+        );
+    }
+
+    let mut guards = locks.map(|_| None);
+    for i in order {
+        guards[i] = Some(locks[i].write());
+    }
+    guards.map(|guard| guard.expect("every lock was taken"))
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(not(loom))]
+    use super::lock_all;
+    use super::lock_two;
+    use crate::RwLock;
+
+    #[cfg(loom)]
+    use loom::thread;
+    #[cfg(not(loom))]
+    use std::thread;
+
+    use std::{sync::Arc, vec::Vec};
+
+    #[cfg(not(loom))]
+    #[test]
+    fn order() {
+        let locks = [RwLock::new(0), RwLock::new(1), RwLock::new(2)];
+        let [c, a, b] = lock_all([&locks[2], &locks[0], &locks[1]]);
+        assert_eq!((*a, *b, *c), (0, 1, 2));
+        assert!(locks.iter().all(RwLock::is_locked_exclusive));
+        drop((a, b, c));
+
+        let (b, a) = lock_two(&locks[1], &locks[0]);
+        assert_eq!((*a, *b), (0, 1));
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    #[should_panic(expected = "the same lock was passed twice")]
+    fn same_lock() {
+        let lock = RwLock::new(0);
+        let _guards = lock_two(&lock, &lock);
+    }
+
+    // test that locking the same two locks in opposite orders can't deadlock
+    fn opposite_kernel() {
+        let locks = Arc::new((RwLock::new(0), RwLock::new(0)));
+        let mut joiners = Vec::new();
+        for i in 0..2 {
+            let lclone = locks.clone();
+            joiners.push(thread::spawn(move || {
+                let (first, second) = if i == 0 {
+                    lock_two(&lclone.0, &lclone.1)
+                } else {
+                    let (second, first) = lock_two(&lclone.1, &lclone.0);
+                    (first, second)
+                };
+                let (mut first, mut second) = (first, second);
+                *first += 1;
+                *second += 1;
+            }));
+        }
+
+        for j in joiners {
+            j.join().unwrap();
+        }
+        assert_eq!((*locks.0.read(), *locks.1.read()), (2, 2));
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn opposite() {
+        loom::model(|| opposite_kernel());
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn opposite() {
+        // Miri is slow enough to interleave the threads plenty in a few rounds
+        for _ in 0..if cfg!(miri) { 5 } else { 100 } {
+            opposite_kernel();
+        }
+    }
+}
@@ -0,0 +1,132 @@
+// MIT/Apache2 License
+
+//! A spin-based mutex, for when reader/writer distinction isn't needed.
+
+use super::{spin_loop, AtomicBool, Ordering};
+use lock_api::{GuardSend, Mutex as LAMutex, MutexGuard as LAMutexGuard, RawMutex};
+
+#[cfg(loom)]
+use crate::loom_lazy::LoomLazy;
+
+#[cfg(not(loom))]
+/// Raw spinlock mutex, wrapped in the `lock_api` `Mutex` struct.
+pub struct RawSpinMutex(AtomicBool);
+
+#[cfg(loom)]
+/// Raw spinlock mutex, wrapped in the `lock_api` `Mutex` struct.
+pub struct RawSpinMutex(LoomLazy<AtomicBool>);
+
+#[cfg(not(loom))]
+impl RawSpinMutex {
+    #[inline]
+    fn ulock(&self) -> &AtomicBool {
+        &self.0
+    }
+}
+
+#[cfg(loom)]
+impl RawSpinMutex {
+    #[inline]
+    fn ulock(&self) -> &AtomicBool {
+        self.0.get_or_init(|| AtomicBool::new(false))
+    }
+}
+
+unsafe impl RawMutex for RawSpinMutex {
+    #[cfg(not(loom))]
+    const INIT: RawSpinMutex = RawSpinMutex(AtomicBool::new(false));
+    #[cfg(loom)]
+    const INIT: RawSpinMutex = RawSpinMutex(LoomLazy::new());
+
+    type GuardMarker = GuardSend;
+
+    fn lock(&self) {
+        loop {
+            match self.ulock().compare_exchange_weak(
+                false,
+                true,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return,
+                Err(_) => spin_loop(),
+            }
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        self.ulock()
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    unsafe fn unlock(&self) {
+        self.ulock().store(false, Ordering::Release);
+    }
+
+    fn is_locked(&self) -> bool {
+        self.ulock().load(Ordering::Relaxed)
+    }
+}
+
+/// A mutual exclusion lock that uses a spinlock internally.
+pub type Mutex<T> = LAMutex<RawSpinMutex, T>;
+/// A guard for the mutex.
+pub type MutexGuard<'a, T> = LAMutexGuard<'a, RawSpinMutex, T>;
+
+#[cfg(test)]
+mod tests {
+    use super::Mutex;
+
+    #[cfg(loom)]
+    use loom::thread;
+    #[cfg(not(loom))]
+    use std::thread;
+
+    use std::{sync::Arc, vec::Vec};
+
+    #[cfg(not(loom))]
+    #[test]
+    fn basics() {
+        let mutex = Mutex::new(8);
+        assert_eq!(*mutex.lock(), 8);
+        *mutex.lock() = 7;
+        assert_eq!(*mutex.lock(), 7);
+
+        let guard = mutex.lock();
+        assert!(mutex.is_locked());
+        assert!(mutex.try_lock().is_none());
+        drop(guard);
+        assert!(!mutex.is_locked());
+    }
+
+    // test multiple lockers
+    fn multilock_kernel() {
+        let mutex = Arc::new(Mutex::new(0));
+        let mut joiners = Vec::new();
+        for _ in 0..2 {
+            let mclone = mutex.clone();
+            joiners.push(thread::spawn(move || {
+                let mut lock = mclone.lock();
+                *lock += 1;
+            }));
+        }
+
+        for j in joiners {
+            j.join().unwrap();
+        }
+        assert_eq!(*mutex.lock(), 2);
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn multilock() {
+        loom::model(|| multilock_kernel());
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn multilock() {
+        multilock_kernel();
+    }
+}
@@ -0,0 +1,206 @@
+// MIT/Apache2 License
+
+//! Trait impls shared by the locks whose state is narrower than `RawRwSpinlock`'s.
+
+/// Implement `Debug` and the `lock_api` traits for `$lock<B>`, whose state is a `$atomic` with
+/// the same flags as `RawRwSpinlock`'s.
+///
+/// The lock provides `add_reader`, which adds a reader unless one of the flags it is given is set
+/// or the count is full, and the body of `downgrade_upgradable`, since those depend on how much
+/// room the count has. None of these locks queue their waiters, so the fair unlocks are the plain
+/// ones.
+macro_rules! impl_narrow_lock {
+    (
+        $lock:ident, $atomic:ident;
+        unsafe fn downgrade_upgradable(&$this:ident) $downgrade_upgradable:block
+    ) => {
+        #[cfg(not(loom))]
+        impl<B: Backoff> $lock<B> {
+            #[inline]
+            fn ulock(&self) -> &$atomic {
+                &self.state
+            }
+        }
+
+        #[cfg(loom)]
+        impl<B: Backoff> $lock<B> {
+            #[inline]
+            fn ulock(&self) -> &$atomic {
+                self.state.get_or_init(|| $atomic::new(0))
+            }
+        }
+
+        impl<B: Backoff> fmt::Debug for $lock<B> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let value = self.ulock().load(Ordering::Relaxed);
+                f.debug_struct(stringify!($lock))
+                    .field("readers", &(value / READER))
+                    .field("writer", &(value & WRITER != 0))
+                    .field("upgraded", &(value & UPGRADED != 0))
+                    .finish()
+            }
+        }
+
+        unsafe impl<B: Backoff> RawRwLock for $lock<B> {
+            const INIT: $lock<B> = $lock {
+                #[cfg(not(loom))]
+                state: $atomic::new(0),
+                #[cfg(loom)]
+                state: LoomLazy::new(),
+                _backoff: PhantomData,
+            };
+
+            type GuardMarker = GuardSend;
+
+            fn lock_shared(&self) {
+                let mut backoff = B::new();
+                while !self.try_lock_shared() {
+                    backoff.spin();
+                }
+            }
+
+            fn try_lock_shared(&self) -> bool {
+                self.add_reader(WRITER | UPGRADED)
+            }
+
+            fn try_lock_exclusive(&self) -> bool {
+                self.ulock()
+                    .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            }
+
+            fn lock_exclusive(&self) {
+                let mut backoff = B::new_exclusive();
+                while self
+                    .ulock()
+                    .compare_exchange_weak(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+                    .is_err()
+                {
+                    backoff.spin();
+                }
+            }
+
+            unsafe fn unlock_shared(&self) {
+                self.ulock().fetch_sub(READER, Ordering::Release);
+            }
+
+            unsafe fn unlock_exclusive(&self) {
+                self.ulock()
+                    .fetch_and(!(WRITER | UPGRADED), Ordering::Release);
+            }
+
+            fn is_locked(&self) -> bool {
+                self.ulock().load(Ordering::Relaxed) != 0
+            }
+
+            fn is_locked_exclusive(&self) -> bool {
+                self.ulock().load(Ordering::Relaxed) & WRITER != 0
+            }
+        }
+
+        unsafe impl<B: Backoff> RawRwLockFair for $lock<B> {
+            unsafe fn unlock_shared_fair(&self) {
+                self.unlock_shared();
+            }
+
+            unsafe fn unlock_exclusive_fair(&self) {
+                self.unlock_exclusive();
+            }
+
+            unsafe fn bump_shared(&self) {
+                self.unlock_shared();
+                spin_loop();
+                self.lock_shared();
+            }
+
+            unsafe fn bump_exclusive(&self) {
+                self.unlock_exclusive();
+                spin_loop();
+                self.lock_exclusive();
+            }
+        }
+
+        unsafe impl<B: Backoff> RawRwLockRecursive for $lock<B> {
+            fn lock_shared_recursive(&self) {
+                let mut backoff = B::new();
+                while !self.try_lock_shared_recursive() {
+                    backoff.spin();
+                }
+            }
+
+            fn try_lock_shared_recursive(&self) -> bool {
+                self.add_reader(WRITER)
+            }
+        }
+
+        #[cfg(feature = "upgrade")]
+        unsafe impl<B: Backoff> RawRwLockUpgrade for $lock<B> {
+            fn lock_upgradable(&self) {
+                let mut backoff = B::new();
+                while !self.try_lock_upgradable() {
+                    backoff.spin();
+                }
+            }
+
+            fn try_lock_upgradable(&self) -> bool {
+                let mut value = self.ulock().load(Ordering::Relaxed);
+                loop {
+                    if value & (WRITER | UPGRADED) != 0 {
+                        return false;
+                    }
+
+                    match self.ulock().compare_exchange_weak(
+                        value,
+                        value | UPGRADED,
+                        Ordering::Acquire,
+                        Ordering::Relaxed,
+                    ) {
+                        Ok(_) => return true,
+                        Err(current) => value = current,
+                    }
+                }
+            }
+
+            unsafe fn try_upgrade(&self) -> bool {
+                self.ulock()
+                    .compare_exchange(UPGRADED, WRITER, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            }
+
+            unsafe fn upgrade(&self) {
+                let mut backoff = B::new_exclusive();
+                while self
+                    .ulock()
+                    .compare_exchange_weak(UPGRADED, WRITER, Ordering::Acquire, Ordering::Relaxed)
+                    .is_err()
+                {
+                    backoff.spin();
+                }
+            }
+
+            unsafe fn unlock_upgradable(&self) {
+                self.ulock().fetch_sub(UPGRADED, Ordering::AcqRel);
+            }
+        }
+
+        #[cfg(feature = "upgrade")]
+        unsafe impl<B: Backoff> RawRwLockDowngrade for $lock<B> {
+            unsafe fn downgrade(&self) {
+                // nobody else can hold the lock, so there is always room for one reader
+                self.ulock().fetch_add(READER, Ordering::Acquire);
+                self.unlock_exclusive();
+            }
+        }
+
+        #[cfg(feature = "upgrade")]
+        unsafe impl<B: Backoff> RawRwLockUpgradeDowngrade for $lock<B> {
+            unsafe fn downgrade_upgradable(&$this) $downgrade_upgradable
+
+            unsafe fn downgrade_to_upgradable(&self) {
+                self.ulock().fetch_xor(WRITER | UPGRADED, Ordering::Release);
+            }
+        }
+    };
+}
+
+pub(crate) use impl_narrow_lock;
@@ -0,0 +1,370 @@
+// MIT/Apache2 License
+
+//! A spin-based primitive for one-time initialization.
+
+use super::{spin_loop, AtomicUsize, Ordering};
+use core::{cell::UnsafeCell, fmt, mem::MaybeUninit};
+
+#[cfg(loom)]
+use crate::loom_lazy::LoomLazy;
+
+const INCOMPLETE: usize = 0;
+const RUNNING: usize = 1;
+const COMPLETE: usize = 2;
+const PANICKED: usize = 3;
+
+/// Raw one-time initialization state, without any data attached.
+///
+/// The first caller of [`call_once`](RawOnce::call_once) runs its closure. Everyone else spins
+/// until that closure has finished.
+pub struct RawOnce {
+    #[cfg(not(loom))]
+    state: AtomicUsize,
+    #[cfg(loom)]
+    state: LoomLazy<AtomicUsize>,
+}
+
+#[cfg(not(loom))]
+impl RawOnce {
+    #[inline]
+    fn ulock(&self) -> &AtomicUsize {
+        &self.state
+    }
+}
+
+#[cfg(loom)]
+impl RawOnce {
+    #[inline]
+    fn ulock(&self) -> &AtomicUsize {
+        self.state.get_or_init(|| AtomicUsize::new(INCOMPLETE))
+    }
+}
+
+impl RawOnce {
+    /// Create a `RawOnce` that has not run yet.
+    #[inline]
+    #[must_use]
+    pub const fn new() -> Self {
+        RawOnce {
+            #[cfg(not(loom))]
+            state: AtomicUsize::new(INCOMPLETE),
+            #[cfg(loom)]
+            state: LoomLazy::new(),
+        }
+    }
+
+    /// Run `f` if no call to `call_once` has run yet, or wait for the one that is running.
+    ///
+    /// When this returns, a closure passed to `call_once` has run to completion.
+    ///
+    /// # Panics
+    ///
+    /// If a closure passed to `call_once` panics, the `RawOnce` is poisoned, and this and every
+    /// later call to `call_once` panics.
+    pub fn call_once<F: FnOnce()>(&self, f: F) {
+        let mut f = Some(f);
+        self.run(PANICKED, || (f.take().unwrap())());
+    }
+
+    /// Like `call_once`, but a panicking initializer leaves the `RawOnce` as if it never ran, so
+    /// that the next caller, or a thread that was waiting, runs `f` again.
+    pub(crate) fn call_once_retrying<F: FnMut()>(&self, f: F) {
+        self.run(INCOMPLETE, f);
+    }
+
+    fn run<F: FnMut()>(&self, on_unwind: usize, mut f: F) {
+        loop {
+            match self.ulock().compare_exchange(
+                INCOMPLETE,
+                RUNNING,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    // if `f` unwinds, the guard resets the state instead of leaving the waiters
+                    // spinning forever
+                    let poison = Poison {
+                        state: self.ulock(),
+                        on_unwind,
+                    };
+                    f();
+                    core::mem::forget(poison);
+                    self.ulock().store(COMPLETE, Ordering::Release);
+                    return;
+                }
+                Err(RUNNING) => {
+                    while self.ulock().load(Ordering::Acquire) == RUNNING {
+                        spin_loop();
+                    }
+                }
+                Err(COMPLETE) => return,
+                Err(state) => {
+                    debug_assert_eq!(state, PANICKED);
+                    panic!("Once instance has previously been poisoned");
+                }
+            }
+        }
+    }
+
+    /// Whether a closure passed to `call_once` has run to completion.
+    #[inline]
+    #[must_use]
+    pub fn is_completed(&self) -> bool {
+        self.ulock().load(Ordering::Acquire) == COMPLETE
+    }
+
+    /// Whether a closure passed to `call_once` panicked.
+    #[inline]
+    #[must_use]
+    pub fn is_poisoned(&self) -> bool {
+        self.ulock().load(Ordering::Acquire) == PANICKED
+    }
+}
+
+impl Default for RawOnce {
+    #[inline]
+    fn default() -> Self {
+        RawOnce::new()
+    }
+}
+
+impl fmt::Debug for RawOnce {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self.ulock().load(Ordering::Relaxed) {
+            INCOMPLETE => "incomplete",
+            RUNNING => "running",
+            COMPLETE => "complete",
+            _ => "poisoned",
+        };
+        f.debug_struct("RawOnce").field("state", &state).finish()
+    }
+}
+
+/// Resets the `RawOnce` when dropped during an unwind out of the initializer.
+struct Poison<'a> {
+    state: &'a AtomicUsize,
+    on_unwind: usize,
+}
+
+impl Drop for Poison<'_> {
+    fn drop(&mut self) {
+        self.state.store(self.on_unwind, Ordering::Release);
+    }
+}
+
+/// A value that is initialized at most once, by whichever thread gets to it first.
+///
+/// The value is stored inline, next to a single `AtomicUsize` of state, so this needs no
+/// allocator and suits `no_std`. The initializer writes the value in place, and it is dropped
+/// along with the `Once` if it was ever initialized.
+pub struct Once<T> {
+    raw: RawOnce,
+    data: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send> Send for Once<T> {}
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+
+impl<T> Once<T> {
+    /// Create a new, uninitialized `Once`.
+    #[inline]
+    #[must_use]
+    pub const fn new() -> Self {
+        Once {
+            raw: RawOnce::new(),
+            data: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Initialize the value with `f` if it hasn't been initialized yet, and return it.
+    ///
+    /// If another thread is running its initializer, this spins until that finishes.
+    ///
+    /// # Panics
+    ///
+    /// If an initializer panics, the `Once` is poisoned, and this and every later call to
+    /// `call_once` panics.
+    pub fn call_once<F: FnOnce() -> T>(&self, f: F) -> &T {
+        self.raw.call_once(|| {
+            let value = f();
+            // SAFETY: only the thread running the initializer gets here, and no one reads the
+            // data until the state is COMPLETE
+            unsafe { (*self.data.get()).as_mut_ptr().write(value) };
+        });
+
+        // SAFETY: `call_once` only returns normally once the data is initialized
+        unsafe { &*(*self.data.get()).as_ptr() }
+    }
+
+    /// Like `call_once`, but a panicking initializer leaves the `Once` uninitialized rather than
+    /// poisoned, so that `f` is run again by the next caller.
+    pub(crate) fn call_once_retrying<F: Fn() -> T>(&self, f: F) -> &T {
+        self.raw.call_once_retrying(|| {
+            let value = f();
+            // SAFETY: as in `call_once`
+            unsafe { (*self.data.get()).as_mut_ptr().write(value) };
+        });
+
+        // SAFETY: `call_once_retrying` only returns normally once the data is initialized
+        unsafe { &*(*self.data.get()).as_ptr() }
+    }
+
+    /// Get the value, if it has been initialized.
+    #[inline]
+    #[must_use]
+    pub fn get(&self) -> Option<&T> {
+        if self.raw.is_completed() {
+            // SAFETY: the data is initialized once the state is COMPLETE, and never written again
+            Some(unsafe { &*(*self.data.get()).as_ptr() })
+        } else {
+            None
+        }
+    }
+
+    /// Whether an initializer panicked.
+    #[inline]
+    #[must_use]
+    pub fn is_poisoned(&self) -> bool {
+        self.raw.is_poisoned()
+    }
+}
+
+impl<T> Default for Once<T> {
+    #[inline]
+    fn default() -> Self {
+        Once::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Once<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.get() {
+            Some(value) => f.debug_struct("Once").field("data", value).finish(),
+            None => f
+                .debug_struct("Once")
+                .field("data", &format_args!("<uninit>"))
+                .finish(),
+        }
+    }
+}
+
+impl<T> Drop for Once<T> {
+    fn drop(&mut self) {
+        if self.raw.is_completed() {
+            // SAFETY: the data is initialized, and we have exclusive access to it
+            unsafe { (*self.data.get()).as_mut_ptr().drop_in_place() };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Once;
+
+    #[cfg(loom)]
+    use loom::thread;
+    #[cfg(not(loom))]
+    use std::thread;
+
+    use super::{AtomicUsize, Ordering};
+    use std::{sync::Arc, vec::Vec};
+
+    #[cfg(not(loom))]
+    #[test]
+    fn basics() {
+        let once = Once::new();
+        assert!(once.get().is_none());
+        assert_eq!(*once.call_once(|| 3), 3);
+        assert_eq!(*once.call_once(|| 4), 3);
+        assert_eq!(once.get(), Some(&3));
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn poisoned() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let once = Once::<i32>::new();
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            once.call_once(|| panic!("initializer failed"));
+        }));
+        assert!(result.is_err());
+        assert!(once.is_poisoned());
+        assert!(once.get().is_none());
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            once.call_once(|| 1);
+        }));
+        assert!(result.is_err());
+    }
+
+    // test that an inline value is initialized once and dropped once, with the `Once`
+    #[cfg(not(loom))]
+    #[test]
+    fn inline_drop() {
+        use core::mem;
+
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct Buffer([u8; 32]);
+        impl Drop for Buffer {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        assert_eq!(
+            mem::size_of::<Once<[u8; 32]>>(),
+            32 + mem::size_of::<AtomicUsize>()
+        );
+
+        let once = Once::new();
+        assert!(once.get().is_none());
+        assert_eq!(once.call_once(|| Buffer([7; 32])).0, [7; 32]);
+        once.call_once(|| Buffer([0; 32]));
+        // the second initializer never ran, so its value was never created or dropped
+        assert_eq!(DROPS.load(Ordering::Relaxed), 0);
+        assert_eq!(once.get().unwrap().0[31], 7);
+
+        drop(once);
+        assert_eq!(DROPS.load(Ordering::Relaxed), 1);
+        drop(Once::<Buffer>::new());
+        assert_eq!(DROPS.load(Ordering::Relaxed), 1);
+    }
+
+    // test that racing initializers only run once
+    fn race_kernel() {
+        let once = Arc::new(Once::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut joiners = Vec::new();
+        for i in 0..2 {
+            let oclone = once.clone();
+            let cclone = calls.clone();
+            joiners.push(thread::spawn(move || {
+                let value = *oclone.call_once(|| {
+                    cclone.fetch_add(1, Ordering::Relaxed);
+                    i
+                });
+                assert!(value < 2);
+            }));
+        }
+
+        for j in joiners {
+            j.join().unwrap();
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert!(once.get().is_some());
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn race() {
+        loom::model(|| race_kernel());
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn race() {
+        race_kernel();
+    }
+}
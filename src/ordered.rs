@@ -0,0 +1,252 @@
+// MIT/Apache2 License
+
+//! Locks with levels that must be taken in ascending order, enabled by the `lock-ordering`
+//! feature.
+
+use super::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use core::{
+    fmt,
+    ops::{Deref, DerefMut},
+};
+
+#[cfg(debug_assertions)]
+use std::{cell::RefCell, vec::Vec};
+
+#[cfg(debug_assertions)]
+std::thread_local! {
+    // the levels of the locks this thread holds, in the order they were taken
+    static HELD: RefCell<Vec<u32>> = const { RefCell::new(Vec::new()) };
+}
+
+#[cfg(debug_assertions)]
+fn check(level: u32) {
+    HELD.with(|held| {
+        if let Some(&highest) = held.borrow().iter().max() {
+            assert!(
+                level > highest,
+                "lock ordering violated: tried to take a lock at level {} while holding one at \
+                 level {}; locks must be taken in strictly ascending level order",
+                level,
+                highest
+            );
+        }
+    });
+}
+
+#[cfg(debug_assertions)]
+fn push(level: u32) {
+    HELD.with(|held| held.borrow_mut().push(level));
+}
+
+#[cfg(debug_assertions)]
+fn pop(level: u32) {
+    HELD.with(|held| {
+        // guards aren't always dropped in the order they were taken
+        let mut held = held.borrow_mut();
+        if let Some(index) = held.iter().rposition(|&held| held == level) {
+            held.remove(index);
+        }
+    });
+}
+
+/// An [`RwLock`] with a level, which debug builds check locks are taken in order of.
+///
+/// A thread that holds a lock may only block on locks with a strictly higher level. If every
+/// thread keeps to that, no two threads can each hold a lock the other is waiting for, so they
+/// can't deadlock. In debug builds, each thread keeps a stack of the levels it holds, and a
+/// blocking acquisition at a level equal to or below the highest one held panics instead of
+/// spinning. Try acquisitions can't deadlock, so they aren't checked, but they still count as
+/// held once they succeed.
+///
+/// In release builds none of this is checked, and this is just an `RwLock` and its level.
+///
+/// ```
+/// use spinny::OrderedRwLock;
+///
+/// static CONFIG: OrderedRwLock<u32> = OrderedRwLock::new(1, 0);
+/// static CACHE: OrderedRwLock<u32> = OrderedRwLock::new(2, 0);
+///
+/// let config = CONFIG.read();
+/// *CACHE.write() = *config + 1;
+/// ```
+pub struct OrderedRwLock<T: ?Sized> {
+    level: u32,
+    lock: RwLock<T>,
+}
+
+impl<T> OrderedRwLock<T> {
+    /// Create a new lock at `level` holding `value`.
+    #[inline]
+    #[must_use]
+    pub const fn new(level: u32, value: T) -> Self {
+        OrderedRwLock {
+            level,
+            lock: RwLock::new(value),
+        }
+    }
+
+    /// Consume the lock and return the data inside it.
+    #[inline]
+    #[must_use]
+    pub fn into_inner(self) -> T {
+        self.lock.into_inner()
+    }
+}
+
+impl<T: ?Sized> OrderedRwLock<T> {
+    /// Get the level of this lock.
+    #[inline]
+    #[must_use]
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    #[inline]
+    #[cfg_attr(not(debug_assertions), allow(clippy::unused_self))]
+    fn held<G>(&self, guard: G) -> LevelGuard<G> {
+        #[cfg(debug_assertions)]
+        push(self.level);
+        LevelGuard {
+            guard,
+            #[cfg(debug_assertions)]
+            level: self.level,
+        }
+    }
+
+    /// Lock for reading, spinning until the lock is available.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, panics if this thread holds a lock at this level or above.
+    #[inline]
+    pub fn read(&self) -> LevelGuard<RwLockReadGuard<'_, T>> {
+        #[cfg(debug_assertions)]
+        check(self.level);
+        self.held(self.lock.read())
+    }
+
+    /// Try to lock for reading without spinning.
+    #[inline]
+    pub fn try_read(&self) -> Option<LevelGuard<RwLockReadGuard<'_, T>>> {
+        self.lock.try_read().map(|guard| self.held(guard))
+    }
+
+    /// Lock for writing, spinning until the lock is available.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, panics if this thread holds a lock at this level or above.
+    #[inline]
+    pub fn write(&self) -> LevelGuard<RwLockWriteGuard<'_, T>> {
+        #[cfg(debug_assertions)]
+        check(self.level);
+        self.held(self.lock.write())
+    }
+
+    /// Try to lock for writing without spinning.
+    #[inline]
+    pub fn try_write(&self) -> Option<LevelGuard<RwLockWriteGuard<'_, T>>> {
+        self.lock.try_write().map(|guard| self.held(guard))
+    }
+
+    /// Get a mutable reference to the data, which needs no locking.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.lock.get_mut()
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for OrderedRwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OrderedRwLock")
+            .field("level", &self.level)
+            .field("lock", &&self.lock)
+            .finish()
+    }
+}
+
+/// A guard for an [`OrderedRwLock`], which stops counting its lock as held when it is dropped.
+#[must_use = "if unused the OrderedRwLock will immediately unlock"]
+pub struct LevelGuard<G> {
+    guard: G,
+    #[cfg(debug_assertions)]
+    level: u32,
+}
+
+impl<G: Deref> Deref for LevelGuard<G> {
+    type Target = G::Target;
+
+    #[inline]
+    fn deref(&self) -> &G::Target {
+        &self.guard
+    }
+}
+
+impl<G: DerefMut> DerefMut for LevelGuard<G> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut G::Target {
+        &mut self.guard
+    }
+}
+
+#[cfg(debug_assertions)]
+impl<G> Drop for LevelGuard<G> {
+    #[inline]
+    fn drop(&mut self) {
+        pop(self.level);
+    }
+}
+
+impl<G: Deref> fmt::Debug for LevelGuard<G>
+where
+    G::Target: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::OrderedRwLock;
+
+    #[test]
+    fn ascending() {
+        let (low, middle, high) = (
+            OrderedRwLock::new(1, 0),
+            OrderedRwLock::new(2, 0),
+            OrderedRwLock::new(3, 0),
+        );
+
+        let low_guard = low.read();
+        let mut middle_guard = middle.write();
+        *high.write() = *low_guard + 1;
+        *middle_guard = 2;
+        drop((low_guard, middle_guard));
+
+        // once the higher locks are released, a lower one can be taken again
+        assert_eq!(*low.write(), 0);
+        let high_guard = high.try_read().unwrap();
+        assert_eq!(*high_guard, 1);
+        drop(high_guard);
+        assert_eq!(*middle.read(), 2);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "lock ordering violated")]
+    fn descending() {
+        let (low, high) = (OrderedRwLock::new(1, ()), OrderedRwLock::new(2, ()));
+        let _high = high.read();
+        let _low = low.read();
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "lock ordering violated")]
+    fn same_level() {
+        let (first, second) = (OrderedRwLock::new(1, ()), OrderedRwLock::new(1, ()));
+        let _first = first.write();
+        let _second = second.read();
+    }
+}
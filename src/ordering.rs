@@ -0,0 +1,46 @@
+// MIT/Apache2 License
+
+//! Memory orderings for the lock state.
+
+use core::sync::atomic::Ordering;
+
+/// The memory orderings a [`RawRwSpinlock`](crate::RawRwSpinlock) uses on its state.
+///
+/// Each constant names the role an access plays, and supplies the ordering used for every access
+/// in that role.
+pub trait OrderingPolicy {
+    /// Taking the lock: the `fetch_add` or compare-and-swap that acquires it.
+    const ACQUIRE: Ordering;
+    /// Releasing the lock.
+    const RELEASE: Ordering;
+    /// Accesses that both release and acquire, such as giving up an upgradable lock.
+    const ACQ_REL: Ordering;
+    /// Accesses that don't synchronize: failed compare-and-swaps, backing out of a failed
+    /// acquisition, and snapshots of the state.
+    const RELAXED: Ordering;
+}
+
+/// The orderings the lock needs and no stronger. This is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Standard;
+
+impl OrderingPolicy for Standard {
+    const ACQUIRE: Ordering = Ordering::Acquire;
+    const RELEASE: Ordering = Ordering::Release;
+    const ACQ_REL: Ordering = Ordering::AcqRel;
+    const RELAXED: Ordering = Ordering::Relaxed;
+}
+
+/// `SeqCst` for every access.
+///
+/// This is a correctness-debugging aid: if a bug goes away under this policy, the orderings are
+/// the place to look. It is slower than [`Standard`] and no more correct.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SeqCstAll;
+
+impl OrderingPolicy for SeqCstAll {
+    const ACQUIRE: Ordering = Ordering::SeqCst;
+    const RELEASE: Ordering = Ordering::SeqCst;
+    const ACQ_REL: Ordering = Ordering::SeqCst;
+    const RELAXED: Ordering = Ordering::SeqCst;
+}
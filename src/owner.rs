@@ -0,0 +1,56 @@
+// MIT/Apache2 License
+
+//! Recording which thread holds a lock's write lock, enabled by the `owner-tracking` feature.
+//!
+//! Without the feature, [`WriteOwner`] is empty and every method on it does nothing, so it costs
+//! neither space nor time.
+
+// A plain `core` atomic even under loom: it doesn't take part in synchronization, and has to be
+// constructible in `INIT`.
+#[cfg(feature = "owner-tracking")]
+use core::{
+    num::NonZeroUsize,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Remembers which thread holds a lock's write lock.
+pub(crate) struct WriteOwner {
+    // zero while no thread holds the write lock
+    #[cfg(feature = "owner-tracking")]
+    owner: AtomicUsize,
+}
+
+#[cfg_attr(not(feature = "owner-tracking"), allow(clippy::unused_self))]
+impl WriteOwner {
+    pub(crate) const fn new() -> Self {
+        WriteOwner {
+            #[cfg(feature = "owner-tracking")]
+            owner: AtomicUsize::new(0),
+        }
+    }
+
+    /// Called by a thread that has just taken the write lock, including by upgrading.
+    #[inline]
+    pub(crate) fn claim(&self) {
+        #[cfg(feature = "owner-tracking")]
+        {
+            use lock_api::GetThreadId;
+
+            let id = crate::StdThreadId.nonzero_thread_id().get();
+            self.owner.store(id, Ordering::Relaxed);
+        }
+    }
+
+    /// Called by the holder just before it gives up the write lock, including by downgrading.
+    #[inline]
+    pub(crate) fn release(&self) {
+        #[cfg(feature = "owner-tracking")]
+        self.owner.store(0, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "owner-tracking")]
+    #[inline]
+    pub(crate) fn get(&self) -> Option<NonZeroUsize> {
+        NonZeroUsize::new(self.owner.load(Ordering::Relaxed))
+    }
+}
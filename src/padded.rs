@@ -0,0 +1,114 @@
+// MIT/Apache2 License
+
+//! Padding to keep adjacent locks off each other's cache lines.
+
+use super::RwLock;
+use core::{
+    fmt,
+    ops::{Deref, DerefMut},
+};
+
+/// Pads and aligns a value to the length of a cache line.
+///
+/// Locks that are stored next to each other, as in an array of per-shard locks, can share a
+/// cache line, so that contention on one slows down the others. Wrapping each one in a
+/// `CachePadded`, as in `Vec<CachePadded<RwLock<T>>>`, gives every lock a line of its own.
+/// [`PaddedRwLock`] is shorthand for the common case.
+///
+/// The alignment is 128 bytes on targets that prefetch cache lines in pairs, and 64 bytes
+/// elsewhere.
+#[cfg_attr(
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "powerpc64"
+    ),
+    repr(align(128))
+)]
+#[cfg_attr(
+    not(any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "powerpc64"
+    )),
+    repr(align(64))
+)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct CachePadded<T> {
+    value: T,
+}
+
+/// A read-write lock on a cache line of its own.
+pub type PaddedRwLock<T> = CachePadded<RwLock<T>>;
+
+impl<T> CachePadded<T> {
+    /// Pad `value` to the length of a cache line.
+    #[inline]
+    pub const fn new(value: T) -> Self {
+        CachePadded { value }
+    }
+
+    /// Get the value back out of the padding.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for CachePadded<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T> From<T> for CachePadded<T> {
+    #[inline]
+    fn from(value: T) -> Self {
+        CachePadded::new(value)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for CachePadded<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachePadded")
+            .field("value", &self.value)
+            .finish()
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::{CachePadded, PaddedRwLock, RwLock};
+    use core::mem;
+
+    #[test]
+    fn alignment() {
+        let align = mem::align_of::<PaddedRwLock<u8>>();
+        assert!(align == 64 || align == 128);
+        assert_eq!(mem::align_of::<CachePadded<u8>>(), align);
+        assert_eq!(mem::size_of::<CachePadded<u8>>(), align);
+        // each lock in an array gets a line to itself
+        assert_eq!(mem::size_of::<[PaddedRwLock<u8>; 2]>(), 2 * align);
+    }
+
+    #[test]
+    fn deref() {
+        let mut padded = CachePadded::new(1);
+        *padded += 1;
+        assert_eq!(*padded, 2);
+
+        let lock: PaddedRwLock<_> = CachePadded::new(RwLock::new(3));
+        *lock.write() += 1;
+        assert_eq!(lock.into_inner().into_inner(), 4);
+    }
+}
@@ -0,0 +1,317 @@
+// MIT/Apache2 License
+
+//! A lock that remembers whether a writer panicked, like `std::sync::RwLock`.
+
+use super::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use core::{
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, Ordering},
+};
+use std::thread;
+
+/// An [`RwLock`] that is poisoned when a thread panics while holding its write guard.
+///
+/// A panic halfway through an update can leave the data in a state that breaks its invariants.
+/// Once that has happened, every locking method returns a [`PoisonError`] instead of the guard,
+/// so the next thread to lock it finds out. The error still holds the guard, and
+/// [`clear_poison`](PoisonRwLock::clear_poison) lifts the poison once the data has been
+/// repaired. Panicking under a read guard leaves the lock as it was, since readers can't have
+/// changed anything.
+///
+/// This costs a flag and a call to `std::thread::panicking` on every write unlock, which is why it
+/// is a separate type rather than part of `RwLock`.
+///
+/// ```
+/// use spinny::PoisonRwLock;
+/// use std::{sync::Arc, thread};
+///
+/// let lock = Arc::new(PoisonRwLock::new(0));
+/// let lock2 = lock.clone();
+/// let result = thread::spawn(move || {
+///     let _guard = lock2.write().unwrap();
+///     panic!("oops");
+/// })
+/// .join();
+/// assert!(result.is_err());
+///
+/// assert!(lock.is_poisoned());
+/// let guard = lock.write().unwrap_err().into_inner();
+/// assert_eq!(*guard, 0);
+/// ```
+pub struct PoisonRwLock<T: ?Sized> {
+    // relaxed accesses are enough: the flag is only ever read or written under the lock, except
+    // by `is_poisoned` and `clear_poison`, which are snapshots anyway
+    poisoned: AtomicBool,
+    lock: RwLock<T>,
+}
+
+/// The result of locking a [`PoisonRwLock`].
+pub type PoisonResult<G> = Result<G, PoisonError<G>>;
+
+impl<T> PoisonRwLock<T> {
+    /// Create a new, unpoisoned `PoisonRwLock` holding `value`.
+    #[inline]
+    #[must_use]
+    pub const fn new(value: T) -> Self {
+        PoisonRwLock {
+            poisoned: AtomicBool::new(false),
+            lock: RwLock::new(value),
+        }
+    }
+
+    /// Consume the lock and return the data inside it.
+    ///
+    /// # Errors
+    ///
+    /// Returns the data inside a [`PoisonError`] if the lock is poisoned.
+    #[inline]
+    pub fn into_inner(self) -> PoisonResult<T> {
+        let poisoned = self.poisoned.load(Ordering::Relaxed);
+        poison_result(poisoned, self.lock.into_inner())
+    }
+}
+
+#[inline]
+fn poison_result<G>(poisoned: bool, guard: G) -> PoisonResult<G> {
+    if poisoned {
+        Err(PoisonError::new(guard))
+    } else {
+        Ok(guard)
+    }
+}
+
+impl<T: ?Sized> PoisonRwLock<T> {
+    #[inline]
+    fn wrap_read<'a>(&self, guard: RwLockReadGuard<'a, T>) -> PoisonResult<RwLockReadGuard<'a, T>> {
+        poison_result(self.is_poisoned(), guard)
+    }
+
+    #[inline]
+    fn wrap_write<'a>(
+        &'a self,
+        guard: RwLockWriteGuard<'a, T>,
+    ) -> PoisonResult<PoisonWriteGuard<'a, T>> {
+        let guard = PoisonWriteGuard {
+            guard,
+            poisoned: &self.poisoned,
+            panicking: thread::panicking(),
+        };
+        poison_result(self.is_poisoned(), guard)
+    }
+
+    /// Lock for reading, spinning until the lock is available.
+    ///
+    /// # Errors
+    ///
+    /// Returns the guard inside a [`PoisonError`] if the lock is poisoned.
+    #[inline]
+    pub fn read(&self) -> PoisonResult<RwLockReadGuard<'_, T>> {
+        self.wrap_read(self.lock.read())
+    }
+
+    /// Try to lock for reading without spinning.
+    ///
+    /// Returns `None` if the lock is held for writing.
+    ///
+    /// # Errors
+    ///
+    /// An acquired guard is returned inside a [`PoisonError`] if the lock is poisoned.
+    #[inline]
+    pub fn try_read(&self) -> Option<PoisonResult<RwLockReadGuard<'_, T>>> {
+        self.lock.try_read().map(|guard| self.wrap_read(guard))
+    }
+
+    /// Lock for writing, spinning until the lock is available.
+    ///
+    /// # Errors
+    ///
+    /// Returns the guard inside a [`PoisonError`] if the lock is poisoned.
+    #[inline]
+    pub fn write(&self) -> PoisonResult<PoisonWriteGuard<'_, T>> {
+        self.wrap_write(self.lock.write())
+    }
+
+    /// Try to lock for writing without spinning.
+    ///
+    /// Returns `None` if the lock is held.
+    ///
+    /// # Errors
+    ///
+    /// An acquired guard is returned inside a [`PoisonError`] if the lock is poisoned.
+    #[inline]
+    pub fn try_write(&self) -> Option<PoisonResult<PoisonWriteGuard<'_, T>>> {
+        self.lock.try_write().map(|guard| self.wrap_write(guard))
+    }
+
+    /// Get a mutable reference to the data, which needs no locking.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reference inside a [`PoisonError`] if the lock is poisoned.
+    #[inline]
+    pub fn get_mut(&mut self) -> PoisonResult<&mut T> {
+        poison_result(*self.poisoned.get_mut(), self.lock.get_mut())
+    }
+
+    /// Tell whether a thread has panicked while holding the write guard.
+    ///
+    /// Another thread may poison the lock or clear the poison at any time, so this is only a
+    /// snapshot.
+    #[inline]
+    #[must_use]
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Relaxed)
+    }
+
+    /// Lift the poison, so that locking succeeds again.
+    ///
+    /// Call this once the data has been checked or repaired, usually through the guard held by a
+    /// [`PoisonError`].
+    #[inline]
+    pub fn clear_poison(&self) {
+        self.poisoned.store(false, Ordering::Relaxed);
+    }
+}
+
+impl<T: Default> Default for PoisonRwLock<T> {
+    #[inline]
+    fn default() -> Self {
+        PoisonRwLock::new(T::default())
+    }
+}
+
+impl<T> From<T> for PoisonRwLock<T> {
+    #[inline]
+    fn from(value: T) -> Self {
+        PoisonRwLock::new(value)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for PoisonRwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoisonRwLock")
+            .field("lock", &&self.lock)
+            .field("poisoned", &self.is_poisoned())
+            .finish()
+    }
+}
+
+/// A write guard for a [`PoisonRwLock`], which poisons the lock if it is dropped while its
+/// thread panics.
+#[must_use = "if unused the PoisonRwLock will immediately unlock"]
+pub struct PoisonWriteGuard<'a, T: ?Sized> {
+    guard: RwLockWriteGuard<'a, T>,
+    poisoned: &'a AtomicBool,
+    // a guard taken while already unwinding doesn't poison the lock, just as in `std`
+    panicking: bool,
+}
+
+impl<T: ?Sized> Deref for PoisonWriteGuard<'_, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for PoisonWriteGuard<'_, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for PoisonWriteGuard<'_, T> {
+    #[inline]
+    fn drop(&mut self) {
+        // set before the write lock is released by the field's own drop
+        if !self.panicking && thread::panicking() {
+            self.poisoned.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for PoisonWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for PoisonWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::PoisonRwLock;
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        string::{String, ToString},
+        thread,
+    };
+
+    // test that a panic under a write guard poisons the lock for every later locker
+    #[test]
+    fn panic_under_write() {
+        let lock = PoisonRwLock::new(String::from("before"));
+        thread::scope(|s| {
+            let result = s
+                .spawn(|| {
+                    let mut guard = lock.write().unwrap();
+                    guard.push_str(", during");
+                    panic!("writer panicked");
+                })
+                .join();
+            assert!(result.is_err());
+        });
+
+        assert!(lock.is_poisoned());
+        let err = lock.write().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "a thread panicked while holding the write lock"
+        );
+        let mut guard = err.into_inner();
+        assert_eq!(*guard, "before, during");
+        *guard = String::from("repaired");
+        drop(guard);
+        assert!(lock.read().is_err());
+        assert!(lock.try_write().unwrap().is_err());
+
+        lock.clear_poison();
+        assert_eq!(*lock.read().unwrap(), "repaired");
+        assert_eq!(lock.into_inner().unwrap(), "repaired");
+    }
+
+    // test that panicking under a read guard, or taking a write guard while already unwinding,
+    // leaves the lock unpoisoned
+    #[test]
+    fn no_poison() {
+        struct WriteOnDrop<'a>(&'a PoisonRwLock<i32>);
+        impl Drop for WriteOnDrop<'_> {
+            fn drop(&mut self) {
+                *self.0.write().unwrap() += 1;
+            }
+        }
+
+        let lock = PoisonRwLock::new(0);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _guard = lock.read().unwrap();
+            panic!("reader panicked");
+        }));
+        assert!(result.is_err());
+        assert!(!lock.is_poisoned());
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _write = WriteOnDrop(&lock);
+            panic!("unwinding");
+        }));
+        assert!(result.is_err());
+        assert!(!lock.is_poisoned());
+        assert_eq!(*lock.write().unwrap(), 1);
+    }
+}
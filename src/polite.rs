@@ -0,0 +1,299 @@
+// MIT/Apache2 License
+
+//! A variant of the spinlock rwlock whose readers step back after a writer, to give the next
+//! writer a chance.
+
+use super::{spin_loop, Backoff, DefaultBackoff, RawRwSpinlock};
+use core::fmt;
+use lock_api::{GuardSend, RawRwLock, RawRwLockRecursive, RwLock as LARwLock};
+#[cfg(feature = "upgrade")]
+use lock_api::{RawRwLockDowngrade, RawRwLockUpgrade, RawRwLockUpgradeDowngrade};
+
+/// Raw spinlock rwlock whose readers hold back after a writer, wrapped in the `lock_api` `RwLock`
+/// struct.
+///
+/// With [`RawRwSpinlock`](crate::RawRwSpinlock), readers and writers that are waiting for a
+/// writer all race for the lock as soon as it is released, and readers usually win, since any
+/// number of them can get in at once. Here, a reader that finds a writer holding the lock waits
+/// for it to leave and then spins `READER_YIELD` more times before trying again, which gives any
+/// other waiting writer a head start on the free window.
+///
+/// This is lighter than [`RawWriterPreferringRwSpinlock`](crate::RawWriterPreferringRwSpinlock):
+/// the state is the same as `RawRwSpinlock`'s, readers are never turned away outright, and the
+/// lock costs nothing until a writer has held it. It only improves a writer's odds, so a steady
+/// stream of readers that never see a writer can still keep one waiting. Recursive and upgradable
+/// reads don't hold back.
+pub struct RawPoliteRwSpinlock<B: Backoff = DefaultBackoff, const READER_YIELD: u32 = 16> {
+    inner: RawRwSpinlock<B>,
+}
+
+impl<B: Backoff, const READER_YIELD: u32> fmt::Debug for RawPoliteRwSpinlock<B, READER_YIELD> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawPoliteRwSpinlock")
+            .field("lock", &self.inner)
+            .field("reader_yield", &READER_YIELD)
+            .finish()
+    }
+}
+
+unsafe impl<B: Backoff, const READER_YIELD: u32> RawRwLock
+    for RawPoliteRwSpinlock<B, READER_YIELD>
+{
+    const INIT: RawPoliteRwSpinlock<B, READER_YIELD> = RawPoliteRwSpinlock {
+        inner: RawRwSpinlock::INIT,
+    };
+
+    type GuardMarker = GuardSend;
+
+    fn lock_shared(&self) {
+        self.inner.check_reentry();
+
+        let mut backoff = B::new();
+        while !self.inner.try_lock_shared() {
+            if self.inner.is_locked_exclusive() {
+                // wait out the writer, then hold back so another writer can get in first
+                while self.inner.is_locked_exclusive() {
+                    backoff.spin();
+                }
+                for _ in 0..READER_YIELD {
+                    spin_loop();
+                }
+            } else {
+                backoff.spin();
+            }
+        }
+    }
+
+    fn try_lock_shared(&self) -> bool {
+        self.inner.try_lock_shared()
+    }
+
+    fn try_lock_exclusive(&self) -> bool {
+        self.inner.try_lock_exclusive()
+    }
+
+    fn lock_exclusive(&self) {
+        self.inner.lock_exclusive();
+    }
+
+    unsafe fn unlock_shared(&self) {
+        self.inner.unlock_shared();
+    }
+
+    unsafe fn unlock_exclusive(&self) {
+        self.inner.unlock_exclusive();
+    }
+
+    fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    fn is_locked_exclusive(&self) -> bool {
+        self.inner.is_locked_exclusive()
+    }
+}
+
+unsafe impl<B: Backoff, const READER_YIELD: u32> RawRwLockRecursive
+    for RawPoliteRwSpinlock<B, READER_YIELD>
+{
+    fn lock_shared_recursive(&self) {
+        self.inner.lock_shared_recursive();
+    }
+
+    fn try_lock_shared_recursive(&self) -> bool {
+        self.inner.try_lock_shared_recursive()
+    }
+}
+
+#[cfg(feature = "upgrade")]
+unsafe impl<B: Backoff, const READER_YIELD: u32> RawRwLockUpgrade
+    for RawPoliteRwSpinlock<B, READER_YIELD>
+{
+    fn lock_upgradable(&self) {
+        self.inner.lock_upgradable();
+    }
+
+    fn try_lock_upgradable(&self) -> bool {
+        self.inner.try_lock_upgradable()
+    }
+
+    unsafe fn unlock_upgradable(&self) {
+        self.inner.unlock_upgradable();
+    }
+
+    unsafe fn upgrade(&self) {
+        self.inner.upgrade();
+    }
+
+    unsafe fn try_upgrade(&self) -> bool {
+        self.inner.try_upgrade()
+    }
+}
+
+#[cfg(feature = "upgrade")]
+unsafe impl<B: Backoff, const READER_YIELD: u32> RawRwLockDowngrade
+    for RawPoliteRwSpinlock<B, READER_YIELD>
+{
+    unsafe fn downgrade(&self) {
+        self.inner.downgrade();
+    }
+}
+
+#[cfg(feature = "upgrade")]
+unsafe impl<B: Backoff, const READER_YIELD: u32> RawRwLockUpgradeDowngrade
+    for RawPoliteRwSpinlock<B, READER_YIELD>
+{
+    unsafe fn downgrade_upgradable(&self) {
+        self.inner.downgrade_upgradable();
+    }
+
+    unsafe fn downgrade_to_upgradable(&self) {
+        self.inner.downgrade_to_upgradable();
+    }
+}
+
+/// A read-write lock that uses a spinlock internally, whose readers hold back after a writer.
+pub type PoliteRwLock<T> = LARwLock<RawPoliteRwSpinlock, T>;
+
+#[cfg(test)]
+mod tests {
+    use super::RawPoliteRwSpinlock;
+    #[cfg(any(loom, feature = "test-spin-deterministic"))]
+    use lock_api::RwLock;
+    #[cfg(not(loom))]
+    use {crate::test_util::writer_among_readers, lock_api::RawRwLock};
+
+    // test that a reader waiting out a write holds back long enough for the writer to take the
+    // lock again before it gets in
+    #[cfg(loom)]
+    fn held_back_kernel<const READER_YIELD: u32>() {
+        use crate::{
+            atomic::{AtomicBool, Ordering},
+            SpinOnly,
+        };
+        use loom::thread;
+        use std::sync::Arc;
+
+        let rwlock = Arc::new(RwLock::<RawPoliteRwSpinlock<SpinOnly, READER_YIELD>, _>::new(0));
+        let started = Arc::new(AtomicBool::new(false));
+        let mut guard = rwlock.write();
+        let reader = {
+            let lclone = rwlock.clone();
+            let sclone = started.clone();
+            thread::spawn(move || {
+                sclone.store(true, Ordering::Relaxed);
+                let value = *lclone.read();
+                value
+            })
+        };
+
+        // let the reader start waiting on the first write
+        while !started.load(Ordering::Relaxed) {
+            thread::yield_now();
+        }
+        thread::yield_now();
+        *guard += 1;
+        drop(guard);
+        *rwlock.write() += 1;
+
+        // no read completed between the two writes
+        assert_eq!(reader.join().unwrap(), 2);
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn single_writer() {
+        // every spin is a yield under loom, so even one spin of hold-back lets the writer in
+        // first; without any, the reader can take the free window
+        let mut builder = loom::model::Builder::new();
+        builder.preemption_bound = Some(2);
+        builder.check(held_back_kernel::<2>);
+    }
+
+    // test that a writer gets in, exclusively, among a bounded stream of reads
+    #[cfg(not(loom))]
+    #[test]
+    fn single_writer() {
+        for _ in 0..if cfg!(miri) { 2 } else { 100 } {
+            let inside = writer_among_readers::<RawPoliteRwSpinlock>(
+                2,
+                if cfg!(miri) { 20 } else { 1000 },
+                4,
+                |raw, _| raw.is_locked_exclusive(),
+            );
+            assert_eq!(inside, 0);
+        }
+    }
+
+    // test that a reader spins exactly `READER_YIELD` times after a write before trying again,
+    // leaving the lock free for a writer all along
+    #[cfg(all(not(loom), feature = "test-spin-deterministic"))]
+    #[test]
+    fn reader_yield_steps() {
+        use crate::{clear_thread_spin_step, set_thread_spin_step, SpinOnly};
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        use std::thread;
+
+        const READER_YIELD: u32 = 4;
+
+        // the reader reports each step it reaches, then waits for the test to let it carry on
+        static ARRIVED: AtomicUsize = AtomicUsize::new(0);
+        static GRANTED: AtomicUsize = AtomicUsize::new(0);
+
+        fn step() {
+            let step = ARRIVED.fetch_add(1, Ordering::SeqCst) + 1;
+            while GRANTED.load(Ordering::SeqCst) < step {
+                thread::yield_now();
+            }
+        }
+
+        let rwlock = RwLock::<RawPoliteRwSpinlock<SpinOnly, READER_YIELD>, _>::new(0);
+        let mut guard = Some(rwlock.write());
+        thread::scope(|s| {
+            let reader = s.spawn(|| {
+                set_thread_spin_step(step);
+                let value = *rwlock.read();
+                clear_thread_spin_step();
+                value
+            });
+
+            // the first step is the reader waiting on the write, and the rest are it holding
+            // back after the write is released
+            let steps = 1 + READER_YIELD as usize;
+            for step in 1..=steps {
+                while ARRIVED.load(Ordering::SeqCst) < step {
+                    thread::yield_now();
+                }
+                if step == 1 {
+                    *guard.take().unwrap() += 1;
+                } else {
+                    assert!(!rwlock.is_locked());
+                }
+                // a writer arriving in the last step still gets in first
+                if step == steps {
+                    *rwlock.try_write().unwrap() += 1;
+                }
+                GRANTED.store(step, Ordering::SeqCst);
+            }
+
+            assert_eq!(reader.join().unwrap(), 2);
+        });
+        assert_eq!(ARRIVED.load(Ordering::SeqCst), 1 + READER_YIELD as usize);
+    }
+
+    #[cfg(all(not(loom), feature = "upgrade"))]
+    #[test]
+    fn basics() {
+        let rwlock = super::PoliteRwLock::new(0);
+        let guard = rwlock.upgradable_read();
+        let reader = rwlock.read_recursive();
+        assert!(rwlock.try_read().is_none());
+        drop(reader);
+        let mut guard = lock_api::RwLockUpgradableReadGuard::upgrade(guard);
+        *guard += 1;
+        let guard = lock_api::RwLockWriteGuard::downgrade(guard);
+        assert_eq!(*rwlock.read(), 1);
+        drop(guard);
+        assert!(rwlock.try_write().is_some());
+    }
+}
@@ -0,0 +1,196 @@
+// MIT/Apache2 License
+
+//! Guards that own an `Rc` of their lock, enabled by the `alloc` feature.
+
+use super::RawRwSpinlock;
+use alloc::rc::Rc;
+use core::{
+    fmt,
+    ops::{Deref, DerefMut},
+};
+use lock_api::{RawRwLock, RwLock as LARwLock};
+
+/// A read guard that owns an `Rc` of its lock, returned by
+/// [`RwLockExt::read_rc`](crate::RwLockExt::read_rc).
+///
+/// This is the single-threaded counterpart of `ArcRwLockReadGuard`, for `Rc<RwLock<T>>`s that
+/// don't need an atomic reference count. Holding the `Rc` makes it neither `Send` nor `Sync`,
+/// whatever the lock's [`GuardMarker`](RawRwLock::GuardMarker) says, so it never leaves the
+/// thread that created it:
+///
+/// ```compile_fail,E0277
+/// use spinny::{RwLock, RwLockExt};
+/// use std::rc::Rc;
+///
+/// let lock = Rc::new(RwLock::new(0));
+/// let guard = lock.read_rc();
+/// std::thread::spawn(move || drop(guard));
+/// ```
+#[must_use = "if unused the RwLock will immediately unlock"]
+pub struct RcRwLockReadGuard<T: ?Sized, R: RawRwLock = RawRwSpinlock> {
+    rwlock: Rc<LARwLock<R, T>>,
+}
+
+impl<T: ?Sized, R: RawRwLock> RcRwLockReadGuard<T, R> {
+    /// # Safety
+    ///
+    /// The caller must hold a read lock on `rwlock`, which the guard takes over.
+    pub(crate) unsafe fn new(rwlock: Rc<LARwLock<R, T>>) -> Self {
+        RcRwLockReadGuard { rwlock }
+    }
+
+    /// Get the lock this guard holds.
+    #[inline]
+    #[must_use]
+    pub fn rwlock(s: &Self) -> &Rc<LARwLock<R, T>> {
+        &s.rwlock
+    }
+
+    /// Release the read lock and return the `Rc` the guard held.
+    #[inline]
+    #[must_use]
+    pub fn into_rc(s: Self) -> Rc<LARwLock<R, T>> {
+        let rwlock = s.rwlock.clone();
+        drop(s);
+        rwlock
+    }
+}
+
+impl<T: ?Sized, R: RawRwLock> Deref for RcRwLockReadGuard<T, R> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        // SAFETY: the guard holds a read lock
+        unsafe { &*self.rwlock.data_ptr() }
+    }
+}
+
+impl<T: ?Sized, R: RawRwLock> Drop for RcRwLockReadGuard<T, R> {
+    #[inline]
+    fn drop(&mut self) {
+        // SAFETY: the guard holds a read lock, and gives it up here
+        unsafe { self.rwlock.force_unlock_read() };
+    }
+}
+
+impl<T: ?Sized + fmt::Debug, R: RawRwLock> fmt::Debug for RcRwLockReadGuard<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display, R: RawRwLock> fmt::Display for RcRwLockReadGuard<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+/// A write guard that owns an `Rc` of its lock, returned by
+/// [`RwLockExt::write_rc`](crate::RwLockExt::write_rc).
+///
+/// See [`RcRwLockReadGuard`]; this is not `Send` or `Sync` either.
+#[must_use = "if unused the RwLock will immediately unlock"]
+pub struct RcRwLockWriteGuard<T: ?Sized, R: RawRwLock = RawRwSpinlock> {
+    rwlock: Rc<LARwLock<R, T>>,
+}
+
+impl<T: ?Sized, R: RawRwLock> RcRwLockWriteGuard<T, R> {
+    /// # Safety
+    ///
+    /// The caller must hold the write lock on `rwlock`, which the guard takes over.
+    pub(crate) unsafe fn new(rwlock: Rc<LARwLock<R, T>>) -> Self {
+        RcRwLockWriteGuard { rwlock }
+    }
+
+    /// Get the lock this guard holds.
+    #[inline]
+    #[must_use]
+    pub fn rwlock(s: &Self) -> &Rc<LARwLock<R, T>> {
+        &s.rwlock
+    }
+
+    /// Release the write lock and return the `Rc` the guard held.
+    #[inline]
+    #[must_use]
+    pub fn into_rc(s: Self) -> Rc<LARwLock<R, T>> {
+        let rwlock = s.rwlock.clone();
+        drop(s);
+        rwlock
+    }
+}
+
+impl<T: ?Sized, R: RawRwLock> Deref for RcRwLockWriteGuard<T, R> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        // SAFETY: the guard holds the write lock
+        unsafe { &*self.rwlock.data_ptr() }
+    }
+}
+
+impl<T: ?Sized, R: RawRwLock> DerefMut for RcRwLockWriteGuard<T, R> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the guard holds the write lock
+        unsafe { &mut *self.rwlock.data_ptr() }
+    }
+}
+
+impl<T: ?Sized, R: RawRwLock> Drop for RcRwLockWriteGuard<T, R> {
+    #[inline]
+    fn drop(&mut self) {
+        // SAFETY: the guard holds the write lock, and gives it up here
+        unsafe { self.rwlock.force_unlock_write() };
+    }
+}
+
+impl<T: ?Sized + fmt::Debug, R: RawRwLock> fmt::Debug for RcRwLockWriteGuard<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display, R: RawRwLock> fmt::Display for RcRwLockWriteGuard<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::{RcRwLockReadGuard, RcRwLockWriteGuard};
+    use crate::{RwLock, RwLockExt};
+    use alloc::rc::Rc;
+
+    // test that an owning guard can be stored in a struct and outlive the scope it was made in
+    #[test]
+    fn stored_in_struct() {
+        struct Holder {
+            guard: RcRwLockReadGuard<i32>,
+        }
+
+        fn make_holder() -> Holder {
+            let rwlock = Rc::new(RwLock::new(1));
+            Holder {
+                guard: rwlock.read_rc(),
+            }
+        }
+
+        let holder = make_holder();
+        assert_eq!(*holder.guard, 1);
+        let rwlock = RcRwLockReadGuard::rwlock(&holder.guard).clone();
+        assert!(rwlock.try_write().is_none());
+        assert!(rwlock.try_write_rc().is_none());
+        let reader = rwlock.try_read_rc().unwrap();
+        drop((holder, reader));
+
+        let mut guard: RcRwLockWriteGuard<i32> = rwlock.write_rc();
+        *guard += 1;
+        assert!(rwlock.try_read_rc().is_none());
+        assert!(Rc::ptr_eq(&RcRwLockWriteGuard::into_rc(guard), &rwlock));
+        assert_eq!(Rc::strong_count(&rwlock), 1);
+        assert_eq!(*rwlock.read(), 2);
+    }
+}
@@ -0,0 +1,112 @@
+// MIT/Apache2 License
+
+//! A spin-based mutex that the thread holding it can lock again.
+
+use super::RawSpinMutex;
+use lock_api::{ReentrantMutex as LAReentrantMutex, ReentrantMutexGuard as LAReentrantMutexGuard};
+
+#[cfg(feature = "std")]
+use core::num::NonZeroUsize;
+#[cfg(feature = "std")]
+use lock_api::GetThreadId;
+
+/// Identifies `std` threads, for [`ReentrantMutex`].
+///
+/// Each thread is identified by the address of a thread-local, which is unique among the
+/// threads that are alive at once.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct StdThreadId;
+
+#[cfg(feature = "std")]
+unsafe impl GetThreadId for StdThreadId {
+    const INIT: StdThreadId = StdThreadId;
+
+    fn nonzero_thread_id(&self) -> NonZeroUsize {
+        std::thread_local!(static KEY: u8 = const { 0 });
+        KEY.with(|key| {
+            let key: *const u8 = key;
+            NonZeroUsize::new(key as usize).expect("thread-locals are never at address zero")
+        })
+    }
+}
+
+/// A mutex that the thread holding it can lock again, built on [`RawSpinMutex`].
+///
+/// The lock counts how many times its owner has locked it, and is released once every guard is
+/// gone. Guards only give shared access, so put a `RefCell` inside for mutation.
+///
+/// `G` tells threads apart. With the `std` feature it defaults to [`StdThreadId`]; `no_std` code
+/// supplies its own by implementing [`GetThreadId`](crate::GetThreadId).
+#[cfg(feature = "std")]
+pub type ReentrantMutex<T, G = StdThreadId> = LAReentrantMutex<RawSpinMutex, G, T>;
+/// A mutex that the thread holding it can lock again, built on [`RawSpinMutex`].
+///
+/// The lock counts how many times its owner has locked it, and is released once every guard is
+/// gone. Guards only give shared access, so put a `RefCell` inside for mutation.
+///
+/// `G` tells threads apart, and is supplied by implementing [`GetThreadId`](crate::GetThreadId).
+/// The `std` feature provides one for `std` threads.
+#[cfg(not(feature = "std"))]
+pub type ReentrantMutex<T, G> = LAReentrantMutex<RawSpinMutex, G, T>;
+
+/// A guard for the reentrant mutex.
+#[cfg(feature = "std")]
+pub type ReentrantMutexGuard<'a, T, G = StdThreadId> =
+    LAReentrantMutexGuard<'a, RawSpinMutex, G, T>;
+/// A guard for the reentrant mutex.
+#[cfg(not(feature = "std"))]
+pub type ReentrantMutexGuard<'a, T, G> = LAReentrantMutexGuard<'a, RawSpinMutex, G, T>;
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::ReentrantMutex;
+    use core::{cell::RefCell, num::NonZeroUsize};
+    use lock_api::GetThreadId;
+
+    // a provider for a program with a single thread, as a `no_std` user might write
+    struct OnlyThread;
+
+    unsafe impl GetThreadId for OnlyThread {
+        const INIT: OnlyThread = OnlyThread;
+
+        fn nonzero_thread_id(&self) -> NonZeroUsize {
+            NonZeroUsize::new(1).unwrap()
+        }
+    }
+
+    #[test]
+    fn relock() {
+        let mutex = ReentrantMutex::<_, OnlyThread>::new(RefCell::new(0));
+        let first = mutex.lock();
+        let second = mutex.lock();
+        let third = mutex.try_lock().unwrap();
+        *third.borrow_mut() += 1;
+        assert_eq!(*first.borrow(), 1);
+        assert_eq!(*second.borrow(), 1);
+
+        drop((first, third));
+        assert!(mutex.is_locked());
+        drop(second);
+        assert!(!mutex.is_locked());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn std_threads() {
+        use std::thread;
+
+        let mutex: ReentrantMutex<_> = ReentrantMutex::new(RefCell::new(0));
+        let guards = [mutex.lock(), mutex.lock(), mutex.lock()];
+        *guards[2].borrow_mut() += 1;
+        assert_eq!(*guards[0].borrow(), 1);
+
+        thread::scope(|s| {
+            s.spawn(|| assert!(mutex.try_lock().is_none()));
+        });
+        drop(guards);
+        thread::scope(|s| {
+            s.spawn(|| assert!(mutex.try_lock().is_some()));
+        });
+    }
+}
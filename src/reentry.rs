@@ -0,0 +1,83 @@
+// MIT/Apache2 License
+
+//! Catching a thread that takes a plain read of a lock it already holds exclusively, which would
+//! spin forever.
+//!
+//! This is only checked in debug builds with the `std` feature, since it needs to tell threads
+//! apart. Otherwise every function here does nothing. Loom runs all of its threads on one OS
+//! thread, so it is off under loom too.
+//!
+//! Each thread keeps a list of the locks it holds an upgradable or write lock on, rather than
+//! each lock remembering its holder, so that the check doesn't change the size of a lock. A guard
+//! that is sent to another thread and released there leaves its lock on the first thread's list.
+//! That entry is dropped the next time the first thread reads the lock while nobody holds it
+//! exclusively; until then, a plain read of it from the first thread panics if it is still held
+//! exclusively.
+
+#[cfg(all(debug_assertions, feature = "std", not(loom)))]
+use std::{cell::RefCell, vec::Vec};
+
+#[cfg(all(debug_assertions, feature = "std", not(loom)))]
+std::thread_local! {
+    // the addresses of the locks this thread holds an upgradable or write lock on
+    static HELD: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+#[cfg(all(debug_assertions, feature = "std", not(loom)))]
+fn address<L>(lock: &L) -> usize {
+    let lock: *const L = lock;
+    lock as usize
+}
+
+/// Called by a thread that has just taken the write or upgradable lock.
+#[inline]
+#[cfg_attr(
+    not(all(debug_assertions, feature = "std", not(loom))),
+    allow(unused_variables)
+)]
+pub(crate) fn claim<L>(lock: &L) {
+    #[cfg(all(debug_assertions, feature = "std", not(loom)))]
+    HELD.with(|held| held.borrow_mut().push(address(lock)));
+}
+
+/// Called by the holder just before it gives up the write or upgradable lock.
+#[inline]
+#[cfg_attr(
+    not(all(debug_assertions, feature = "std", not(loom))),
+    allow(unused_variables)
+)]
+pub(crate) fn release<L>(lock: &L) {
+    #[cfg(all(debug_assertions, feature = "std", not(loom)))]
+    HELD.with(|held| {
+        let mut held = held.borrow_mut();
+        if let Some(index) = held.iter().rposition(|&held| held == address(lock)) {
+            held.swap_remove(index);
+        }
+    });
+}
+
+/// Called before a plain read spins; panics if this thread holds the lock exclusively.
+///
+/// `exclusive` tells whether anyone holds the lock exclusively right now, and is only called if
+/// this thread has the lock on its list.
+#[inline]
+#[cfg_attr(
+    not(all(debug_assertions, feature = "std", not(loom))),
+    allow(unused_variables)
+)]
+pub(crate) fn check_read<L>(lock: &L, exclusive: impl FnOnce() -> bool) {
+    #[cfg(all(debug_assertions, feature = "std", not(loom)))]
+    HELD.with(|held| {
+        let mut held = held.borrow_mut();
+        if let Some(index) = held.iter().position(|&held| held == address(lock)) {
+            // the guard may have been released on another thread
+            assert!(
+                !exclusive(),
+                "this thread tried to read a spinny lock that it holds an upgradable or write \
+                 lock on, which would never succeed; use `read_recursive` alongside an \
+                 upgradable guard, or read through the write guard"
+            );
+            held.swap_remove(index);
+        }
+    });
+}
@@ -0,0 +1,150 @@
+// MIT/Apache2 License
+
+//! A spin-based counting semaphore.
+
+use super::{spin_loop, AtomicUsize, Ordering};
+use core::fmt;
+
+/// A counting semaphore, which lets up to a fixed number of threads in at once.
+///
+/// Threads waiting for a permit spin, in the same way the locks in this crate do.
+pub struct Semaphore {
+    /// The number of permits that are currently available.
+    available: AtomicUsize,
+    /// The number of permits the semaphore was created with.
+    permits: usize,
+}
+
+impl Semaphore {
+    /// Create a semaphore with `permits` permits available.
+    #[inline]
+    #[must_use]
+    pub fn new(permits: usize) -> Self {
+        Semaphore {
+            available: AtomicUsize::new(permits),
+            permits,
+        }
+    }
+
+    /// Spin until a permit is available, and take it.
+    pub fn acquire(&self) {
+        while !self.try_acquire() {
+            spin_loop();
+        }
+    }
+
+    /// Take a permit if one is available.
+    pub fn try_acquire(&self) -> bool {
+        let mut available = self.available.load(Ordering::Relaxed);
+        loop {
+            // never take the count below zero
+            if available == 0 {
+                return false;
+            }
+
+            match self.available.compare_exchange_weak(
+                available,
+                available - 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(actual) => available = actual,
+            }
+        }
+    }
+
+    /// Give back a permit taken by [`acquire`](Semaphore::acquire) or
+    /// [`try_acquire`](Semaphore::try_acquire).
+    ///
+    /// Releasing a permit that was never acquired is a logic error. It is caught by a debug
+    /// assertion, and in release builds it raises the number of available permits past the
+    /// number the semaphore was created with.
+    pub fn release(&self) {
+        let available = self.available.fetch_add(1, Ordering::Release);
+        debug_assert!(
+            available < self.permits,
+            "released more permits than were acquired"
+        );
+    }
+
+    /// The number of permits that are currently available.
+    #[inline]
+    #[must_use]
+    pub fn available_permits(&self) -> usize {
+        self.available.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Semaphore")
+            .field("available", &self.available_permits())
+            .field("permits", &self.permits)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Semaphore;
+
+    #[cfg(loom)]
+    use loom::thread;
+    #[cfg(not(loom))]
+    use std::thread;
+
+    use super::{AtomicUsize, Ordering};
+    use std::{sync::Arc, vec::Vec};
+
+    #[cfg(not(loom))]
+    #[test]
+    fn basics() {
+        let semaphore = Semaphore::new(2);
+        assert!(semaphore.try_acquire());
+        semaphore.acquire();
+        assert!(!semaphore.try_acquire());
+        assert_eq!(semaphore.available_permits(), 0);
+
+        semaphore.release();
+        assert!(semaphore.try_acquire());
+        semaphore.release();
+        semaphore.release();
+        assert_eq!(semaphore.available_permits(), 2);
+    }
+
+    // test that no more than `permits` threads are ever inside at once
+    fn bounded_kernel(threads: usize, permits: usize) {
+        let semaphore = Arc::new(Semaphore::new(permits));
+        let inside = Arc::new(AtomicUsize::new(0));
+        let mut joiners = Vec::new();
+        for _ in 0..threads {
+            let sclone = semaphore.clone();
+            let iclone = inside.clone();
+            joiners.push(thread::spawn(move || {
+                sclone.acquire();
+                let count = iclone.fetch_add(1, Ordering::SeqCst) + 1;
+                assert!(count <= permits);
+                iclone.fetch_sub(1, Ordering::SeqCst);
+                sclone.release();
+            }));
+        }
+
+        for j in joiners {
+            j.join().unwrap();
+        }
+        assert_eq!(semaphore.available_permits(), permits);
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn bounded() {
+        loom::model(|| bounded_kernel(2, 1));
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn bounded() {
+        bounded_kernel(8, 3);
+    }
+}
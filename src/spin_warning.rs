@@ -0,0 +1,119 @@
+// MIT/Apache2 License
+
+//! Logging a warning when an acquisition spins for unusually long, enabled by the `log` feature.
+
+// Plain `core` atomics even under loom: the threshold has to live in a `static`, and the flag
+// doesn't take part in synchronization.
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+const DEFAULT_THRESHOLD: usize = 100_000;
+
+static THRESHOLD: AtomicUsize = AtomicUsize::new(DEFAULT_THRESHOLD);
+
+/// Set how many failed attempts an acquisition of a `RawRwSpinlock` may make before a warning is
+/// logged.
+///
+/// This applies to every lock in the program, and defaults to 100,000.
+pub fn set_long_spin_threshold(spins: usize) {
+    THRESHOLD.store(spins, Ordering::Relaxed);
+}
+
+/// Get how many failed attempts an acquisition of a `RawRwSpinlock` may make before a warning is
+/// logged.
+#[must_use]
+pub fn long_spin_threshold() -> usize {
+    THRESHOLD.load(Ordering::Relaxed)
+}
+
+/// Remembers whether a lock has already warned about a long spin.
+pub(crate) struct SpinWarning {
+    warned: AtomicBool,
+}
+
+impl SpinWarning {
+    pub(crate) const fn new() -> Self {
+        SpinWarning {
+            warned: AtomicBool::new(false),
+        }
+    }
+
+    /// Called on every failed attempt; logs a warning the first time any acquisition of `lock`
+    /// reaches the threshold.
+    #[inline]
+    pub(crate) fn check<L>(&self, lock: &L, kind: &str, spins: usize) {
+        if spins == long_spin_threshold() && !self.warned.swap(true, Ordering::Relaxed) {
+            let lock: *const L = lock;
+            log::warn!(
+                "a {kind} acquisition of the spinny lock at {lock:p} has spun {spins} times; \
+                 further long spins on this lock won't be logged"
+            );
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::set_long_spin_threshold;
+    use crate::RwLock;
+    use log::{Level, Log, Metadata, Record};
+    use std::{
+        format,
+        string::String,
+        sync::{Mutex, Once},
+        thread,
+        time::Duration,
+        vec::Vec,
+    };
+
+    static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    struct Capture;
+
+    impl Log for Capture {
+        fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+            metadata.level() <= Level::Warn
+        }
+
+        fn log(&self, record: &Record<'_>) {
+            if self.enabled(record.metadata()) {
+                WARNINGS.lock().unwrap().push(format!("{}", record.args()));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    // count the warnings about one lock, since other tests may contend on their own
+    fn warnings_about<T>(rwlock: &RwLock<T>) -> usize {
+        let addr = format!("{:p}", unsafe { rwlock.raw() });
+        WARNINGS
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|warning| warning.contains(&addr))
+            .count()
+    }
+
+    #[test]
+    fn warns_once() {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            log::set_logger(&Capture).unwrap();
+            log::set_max_level(log::LevelFilter::Warn);
+        });
+        set_long_spin_threshold(100);
+
+        let rwlock = RwLock::new(0);
+        for _ in 0..2 {
+            let guard = rwlock.write();
+            thread::scope(|s| {
+                s.spawn(|| drop(rwlock.read()));
+                // keep the reader spinning well past the threshold
+                thread::sleep(Duration::from_millis(50));
+                drop(guard);
+            });
+        }
+
+        assert_eq!(warnings_about(&rwlock), 1);
+    }
+}
@@ -0,0 +1,116 @@
+// MIT/Apache2 License
+
+//! The layout of a lock's state word, for tools that inspect it.
+
+/// Set while a writer holds the lock.
+///
+/// The state of a [`RawRwSpinlock`](crate::RawRwSpinlock) is a single `usize`. Bit 0 is this
+/// flag, bit 1 is [`UPGRADED`], and the bits from [`READER_SHIFT`] up hold the number of
+/// readers, in units of [`READER`]. This layout is part of the public API and won't change
+/// without a major version bump. [`RawRwSpinlock32`](crate::RawRwSpinlock32) uses the same
+/// layout in a `u32`.
+pub const WRITER: usize = 1 << 0;
+
+/// Set while an upgradable reader holds the lock. See [`WRITER`] for the layout.
+pub const UPGRADED: usize = 1 << 1;
+
+/// One reader. The state holds the reader count multiplied by this. See [`WRITER`] for the
+/// layout.
+pub const READER: usize = 1 << READER_SHIFT;
+
+/// The position of the reader count in the state word, so that `state >> READER_SHIFT` is the
+/// number of readers. See [`WRITER`] for the layout.
+pub const READER_SHIFT: u32 = 2;
+
+// Changing the layout breaks tools that decode it, so these make a change a deliberate one.
+const _: () = assert!(WRITER == 1 && UPGRADED == 2 && READER == 4);
+const _: () = assert!(WRITER & UPGRADED == 0);
+const _: () = assert!(READER > (WRITER | UPGRADED));
+const _: () = assert!((WRITER | UPGRADED) >> READER_SHIFT == 0);
+
+/// A lock's state, decoded from the raw state word.
+///
+/// Reader counts include readers that are in the middle of failing to acquire the lock, which
+/// briefly add themselves to the count before backing out. For the same reason a writer may be
+/// seen alongside readers; that decodes as [`LockState::Write`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockState {
+    /// Nobody holds the lock.
+    Free,
+    /// This many readers hold the lock.
+    Read(usize),
+    /// An upgradable reader holds the lock, alongside this many plain readers.
+    Upgradable(usize),
+    /// A writer holds the lock.
+    Write,
+}
+
+impl LockState {
+    /// Decode a raw state word.
+    #[must_use]
+    pub fn from_raw(state: usize) -> Self {
+        let readers = state >> READER_SHIFT;
+        if state & WRITER != 0 {
+            LockState::Write
+        } else if state & UPGRADED != 0 {
+            LockState::Upgradable(readers)
+        } else if readers != 0 {
+            LockState::Read(readers)
+        } else {
+            LockState::Free
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::{LockState, READER, READER_SHIFT, UPGRADED, WRITER};
+    use crate::RwLock;
+    #[cfg(feature = "upgrade")]
+    use lock_api::RawRwLockUpgrade;
+
+    #[test]
+    fn from_raw() {
+        assert_eq!(LockState::from_raw(0), LockState::Free);
+        assert_eq!(LockState::from_raw(READER), LockState::Read(1));
+        assert_eq!(LockState::from_raw(5 * READER), LockState::Read(5));
+        assert_eq!(LockState::from_raw(UPGRADED), LockState::Upgradable(0));
+        assert_eq!(
+            LockState::from_raw(UPGRADED | (3 * READER)),
+            LockState::Upgradable(3)
+        );
+        assert_eq!(LockState::from_raw(WRITER), LockState::Write);
+        assert_eq!(LockState::from_raw(WRITER | (2 * READER)), LockState::Write);
+    }
+
+    #[test]
+    fn reader_shift() {
+        let state = (6 << READER_SHIFT) | UPGRADED;
+        assert_eq!(state >> READER_SHIFT, 6);
+        assert_eq!(state & (READER - 1), UPGRADED);
+        assert_eq!(LockState::from_raw(state), LockState::Upgradable(6));
+    }
+
+    #[test]
+    fn live() {
+        let rwlock = RwLock::new(());
+        let raw = unsafe { rwlock.raw() };
+        assert_eq!(raw.state(), LockState::Free);
+
+        let guards = [rwlock.read(), rwlock.read()];
+        assert_eq!(raw.state(), LockState::Read(2));
+        assert_eq!(raw.raw_state(), 2 * READER);
+
+        #[cfg(feature = "upgrade")]
+        {
+            assert!(raw.try_lock_upgradable());
+            assert_eq!(raw.state(), LockState::Upgradable(2));
+            unsafe { raw.unlock_upgradable() };
+        }
+        drop(guards);
+
+        let _guard = rwlock.write();
+        assert_eq!(raw.state(), LockState::Write);
+        assert_eq!(raw.raw_state(), WRITER);
+    }
+}
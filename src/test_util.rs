@@ -0,0 +1,107 @@
+// MIT/Apache2 License
+
+//! Test helpers shared by more than one lock.
+
+use crate::atomic::{AtomicBool, AtomicUsize, Ordering};
+use lock_api::{RawRwLock, RwLock};
+#[cfg(all(not(loom), feature = "upgrade"))]
+use lock_api::{RawRwLockDowngrade, RawRwLockRecursive, RawRwLockUpgrade};
+use std::{sync::Arc, vec::Vec};
+
+#[cfg(loom)]
+use loom::thread;
+#[cfg(not(loom))]
+use std::thread;
+
+/// Run `readers` threads that each take `rounds` reads alongside one thread that takes `writes`
+/// writes, checking that the writer always has the lock to itself.
+///
+/// Returns the number of reads that `waiting` counted against the writer. It is called with the
+/// lock while each read is still held, and with whether the writer was between two of its writes
+/// at that point.
+pub(crate) fn writer_among_readers<R: RawRwLock + Send + Sync + 'static>(
+    readers: usize,
+    rounds: usize,
+    writes: usize,
+    waiting: fn(&R, bool) -> bool,
+) -> usize {
+    let rwlock = Arc::new(RwLock::<R, _>::new(0));
+    // written with relaxed stores, so only the lock orders them against the readers' loads
+    let exclusive = Arc::new(AtomicBool::new(false));
+    let between = Arc::new(AtomicBool::new(false));
+    let reads = Arc::new(AtomicUsize::new(0));
+    let counted = Arc::new(AtomicUsize::new(0));
+
+    let mut joiners = Vec::new();
+    for _ in 0..readers {
+        let lclone = rwlock.clone();
+        let eclone = exclusive.clone();
+        let bclone = between.clone();
+        let rclone = reads.clone();
+        let cclone = counted.clone();
+        joiners.push(thread::spawn(move || {
+            for _ in 0..rounds {
+                let _guard = lclone.read();
+                assert!(!eclone.load(Ordering::Relaxed));
+                if waiting(unsafe { lclone.raw() }, bclone.load(Ordering::Relaxed)) {
+                    cclone.fetch_add(1, Ordering::Relaxed);
+                }
+                rclone.fetch_add(1, Ordering::Relaxed);
+            }
+        }));
+    }
+    {
+        let lclone = rwlock.clone();
+        let eclone = exclusive.clone();
+        let bclone = between.clone();
+        joiners.push(thread::spawn(move || {
+            for i in 0..writes {
+                let mut guard = lclone.write();
+                bclone.store(false, Ordering::Relaxed);
+                eclone.store(true, Ordering::Relaxed);
+                *guard += 1;
+                eclone.store(false, Ordering::Relaxed);
+                if i + 1 < writes {
+                    bclone.store(true, Ordering::Relaxed);
+                }
+            }
+        }));
+    }
+
+    for j in joiners {
+        j.join().unwrap();
+    }
+    assert_eq!(*rwlock.read(), writes);
+    assert_eq!(reads.load(Ordering::Relaxed), readers * rounds);
+    counted.load(Ordering::Relaxed)
+}
+
+/// Run the same sequence of operations against a raw lock, recording every result.
+#[cfg(all(not(loom), feature = "upgrade"))]
+pub(crate) fn script<R: RawRwLockUpgrade + RawRwLockDowngrade + RawRwLockRecursive>() -> Vec<bool> {
+    let raw = R::INIT;
+    let mut results = Vec::new();
+    unsafe {
+        results.push(raw.try_lock_shared());
+        results.push(raw.try_lock_shared());
+        results.push(raw.try_lock_exclusive());
+        results.push(raw.try_lock_upgradable());
+        results.push(raw.try_upgrade());
+        raw.unlock_shared();
+        raw.unlock_shared();
+        results.push(raw.try_upgrade());
+        results.push(raw.try_lock_shared());
+        results.push(raw.try_lock_shared_recursive());
+        raw.downgrade();
+        results.push(raw.try_lock_shared());
+        results.push(raw.try_lock_upgradable());
+        results.push(raw.try_lock_exclusive());
+        raw.unlock_shared();
+        raw.unlock_shared();
+        raw.unlock_upgradable();
+        results.push(raw.is_locked());
+        results.push(raw.try_lock_exclusive());
+        results.push(raw.is_locked_exclusive());
+    }
+    results
+}
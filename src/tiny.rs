@@ -0,0 +1,166 @@
+// MIT/Apache2 License
+
+//! A variant of the spinlock rwlock whose state fits in a single byte.
+
+use super::{
+    atomic::AtomicU8, narrow::impl_narrow_lock, spin_loop, Backoff, DefaultBackoff, Ordering,
+};
+use core::{fmt, marker::PhantomData};
+use lock_api::{GuardSend, RawRwLock, RawRwLockFair, RawRwLockRecursive, RwLock as LARwLock};
+#[cfg(feature = "upgrade")]
+use lock_api::{RawRwLockDowngrade, RawRwLockUpgrade, RawRwLockUpgradeDowngrade};
+
+#[cfg(loom)]
+use crate::loom_lazy::LoomLazy;
+
+// the same flags as `RawRwSpinlock`, with a five-bit reader count above them and the top bit
+// left clear
+#[allow(clippy::cast_possible_truncation)]
+const READER: u8 = super::READER as u8;
+#[allow(clippy::cast_possible_truncation)]
+const UPGRADED: u8 = super::UPGRADED as u8;
+#[allow(clippy::cast_possible_truncation)]
+const WRITER: u8 = super::WRITER as u8;
+const MAX_READERS: u8 = 31;
+const READER_MASK: u8 = MAX_READERS * READER;
+
+/// Raw spinlock rwlock with an 8-bit state, wrapped in the `lock_api` `RwLock` struct.
+///
+/// **At most 31 readers can hold this lock at once.** Past that, `try_read` returns `None` and
+/// `read` spins until a reader leaves, exactly as if a writer held the lock.
+///
+/// Otherwise this behaves like [`RawRwSpinlock`](crate::RawRwSpinlock), with the same guards,
+/// but takes a single byte, for tightly packed structures that only ever see a handful of
+/// readers. The `metrics` counters and the timed methods are only available on
+/// `RawRwSpinlock`.
+///
+/// A byte leaves no room for readers to speculatively increment the count and back out, as the
+/// wider locks do, so readers here compare-and-swap the count instead.
+pub struct RawRwSpinlockU8<B: Backoff = DefaultBackoff> {
+    #[cfg(not(loom))]
+    state: AtomicU8,
+    #[cfg(loom)]
+    state: LoomLazy<AtomicU8>,
+    _backoff: PhantomData<fn() -> B>,
+}
+
+impl<B: Backoff> RawRwSpinlockU8<B> {
+    /// Add a reader unless one of the `refuse` flags is set or the count is full.
+    #[inline]
+    fn add_reader(&self, refuse: u8) -> bool {
+        let mut value = self.ulock().load(Ordering::Relaxed);
+        loop {
+            if value & refuse != 0 || value & READER_MASK == READER_MASK {
+                return false;
+            }
+
+            match self.ulock().compare_exchange_weak(
+                value,
+                value + READER,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => value = current,
+            }
+        }
+    }
+}
+
+impl_narrow_lock! {
+    RawRwSpinlockU8, AtomicU8;
+
+    unsafe fn downgrade_upgradable(&self) {
+        // recursive readers may have filled the count alongside us, in which case we wait for one
+        // of them to leave
+        let mut backoff = B::new();
+        while !self.try_lock_shared_recursive() {
+            backoff.spin();
+        }
+        self.unlock_upgradable();
+    }
+}
+
+/// A read-write lock that uses a spinlock with an 8-bit state internally.
+///
+/// At most 31 readers can hold it at once; see [`RawRwSpinlockU8`].
+pub type RwLockU8<T> = LARwLock<RawRwSpinlockU8, T>;
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::{RawRwSpinlockU8, RwLockU8, MAX_READERS};
+    #[cfg(feature = "upgrade")]
+    use crate::{test_util::script, RawRwSpinlock};
+    use std::vec::Vec;
+
+    #[cfg(feature = "upgrade")]
+    #[test]
+    fn parity() {
+        assert_eq!(script::<RawRwSpinlockU8>(), script::<RawRwSpinlock>());
+    }
+
+    #[test]
+    fn size() {
+        assert_eq!(core::mem::size_of::<RawRwSpinlockU8>(), 1);
+    }
+
+    #[test]
+    fn saturation() {
+        let rwlock = RwLockU8::new(5);
+        let mut guards: Vec<_> = (0..MAX_READERS).map(|_| rwlock.read()).collect();
+        assert!(rwlock.try_read().is_none());
+        assert!(rwlock.try_read_recursive().is_none());
+        assert!(rwlock.try_write().is_none());
+
+        // the count is full, not corrupted: one reader leaving lets exactly one more in
+        drop(guards.pop());
+        guards.push(rwlock.try_read().unwrap());
+        assert!(rwlock.try_read().is_none());
+        assert!(guards.iter().all(|guard| **guard == 5));
+
+        drop(guards);
+        assert!(!rwlock.is_locked());
+        *rwlock.write() += 1;
+        assert_eq!(*rwlock.read(), 6);
+    }
+
+    #[cfg(feature = "upgrade")]
+    #[test]
+    fn saturated_upgradable() {
+        use lock_api::RwLockUpgradableReadGuard;
+
+        let rwlock = RwLockU8::new(0);
+        let upgradable = rwlock.upgradable_read();
+        let mut guards: Vec<_> = (0..MAX_READERS).map(|_| rwlock.read_recursive()).collect();
+        assert!(rwlock.try_read_recursive().is_none());
+
+        // downgrading needs a free reader slot, so make one first
+        drop(guards.pop());
+        let reader = RwLockUpgradableReadGuard::downgrade(upgradable);
+        assert!(rwlock.try_read_recursive().is_none());
+        drop((reader, guards));
+        assert!(!rwlock.is_locked());
+    }
+
+    #[cfg(feature = "upgrade")]
+    #[test]
+    fn cycles() {
+        use lock_api::{RwLockUpgradableReadGuard, RwLockWriteGuard};
+
+        let rwlock = RwLockU8::new(0);
+        for _ in 0..3 {
+            let guard = rwlock.upgradable_read();
+            let reader = rwlock.read_recursive();
+            let guard = RwLockUpgradableReadGuard::try_upgrade(guard).unwrap_err();
+            drop(reader);
+            let mut guard = RwLockUpgradableReadGuard::upgrade(guard);
+            *guard += 1;
+            let guard = RwLockWriteGuard::downgrade_to_upgradable(guard);
+            let guard = RwLockUpgradableReadGuard::downgrade(guard);
+            assert!(rwlock.try_write().is_none());
+            drop(guard);
+            *rwlock.write() += 1;
+        }
+        assert_eq!(rwlock.into_inner(), 6);
+    }
+}
@@ -0,0 +1,376 @@
+// MIT/Apache2 License
+
+//! A variant of the spinlock rwlock that stops admitting readers while a writer waits.
+
+use super::{
+    atomic::{rollback_fence, AtomicUsize},
+    spin_loop, Backoff, DefaultBackoff, Ordering,
+};
+use core::{fmt, marker::PhantomData};
+use lock_api::{GuardSend, RawRwLock, RawRwLockFair, RawRwLockRecursive, RwLock as LARwLock};
+#[cfg(feature = "upgrade")]
+use lock_api::{RawRwLockDowngrade, RawRwLockUpgrade, RawRwLockUpgradeDowngrade};
+
+#[cfg(loom)]
+use crate::loom_lazy::LoomLazy;
+
+const WRITER: usize = 1 << 0;
+const UPGRADED: usize = 1 << 1;
+const WRITER_WAITING: usize = 1 << 2;
+const READER: usize = 1 << 3;
+const READER_LIMIT: usize = !(usize::MAX >> 1);
+
+/// Raw spinlock rwlock that prefers writers, wrapped in the `lock_api` `RwLock` struct.
+///
+/// With [`RawRwSpinlock`](crate::RawRwSpinlock), a steady stream of readers can keep a writer
+/// waiting forever, since each new reader joins the ones already holding the lock. Here, a
+/// writer that fails to take the lock sets a flag that turns new readers away, so that the
+/// readers already holding the lock drain and the writer gets in next. The flag is cleared when a
+/// writer takes the lock, and set again by any other writer that is still waiting.
+///
+/// Recursive reads ignore the flag, so a thread that already holds a read lock can take another
+/// one without deadlocking against the waiting writer. An upgradable reader that is upgrading
+/// isn't held up by the flag either. Only blocking write locks set the flag; `try_write` never
+/// does.
+///
+/// The state uses a different layout from `RawRwSpinlock`'s, so [`LockState`](crate::LockState)
+/// doesn't apply to it.
+pub struct RawWriterPreferringRwSpinlock<B: Backoff = DefaultBackoff> {
+    #[cfg(not(loom))]
+    state: AtomicUsize,
+    #[cfg(loom)]
+    state: LoomLazy<AtomicUsize>,
+    _backoff: PhantomData<fn() -> B>,
+}
+
+#[cfg(not(loom))]
+impl<B: Backoff> RawWriterPreferringRwSpinlock<B> {
+    #[inline]
+    fn ulock(&self) -> &AtomicUsize {
+        &self.state
+    }
+}
+
+#[cfg(loom)]
+impl<B: Backoff> RawWriterPreferringRwSpinlock<B> {
+    #[inline]
+    fn ulock(&self) -> &AtomicUsize {
+        self.state.get_or_init(|| AtomicUsize::new(0))
+    }
+}
+
+impl<B: Backoff> RawWriterPreferringRwSpinlock<B> {
+    /// Whether a writer is waiting for the lock, so that new readers are being turned away.
+    ///
+    /// This is a snapshot taken with a relaxed load, for debugging.
+    #[must_use]
+    pub fn writer_waiting(&self) -> bool {
+        self.ulock().load(Ordering::Relaxed) & WRITER_WAITING != 0
+    }
+
+    #[inline]
+    fn add_reader(&self, refuse: usize) -> bool {
+        // check before touching the count, so that readers turned away by a waiting writer can't
+        // keep its compare-and-swap failing with their speculative increments
+        if self.ulock().load(Ordering::Relaxed) & refuse != 0 {
+            return false;
+        }
+
+        let value = self.ulock().fetch_add(READER, Ordering::Acquire);
+
+        if value & refuse != 0 || value >= READER_LIMIT {
+            rollback_fence();
+            self.ulock().fetch_sub(READER, Ordering::Relaxed);
+            false
+        } else {
+            true
+        }
+    }
+}
+
+impl<B: Backoff> fmt::Debug for RawWriterPreferringRwSpinlock<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.ulock().load(Ordering::Relaxed);
+        f.debug_struct("RawWriterPreferringRwSpinlock")
+            .field("readers", &(value / READER))
+            .field("writer", &(value & WRITER != 0))
+            .field("upgraded", &(value & UPGRADED != 0))
+            .field("writer_waiting", &(value & WRITER_WAITING != 0))
+            .finish()
+    }
+}
+
+unsafe impl<B: Backoff> RawRwLock for RawWriterPreferringRwSpinlock<B> {
+    const INIT: RawWriterPreferringRwSpinlock<B> = RawWriterPreferringRwSpinlock {
+        #[cfg(not(loom))]
+        state: AtomicUsize::new(0),
+        #[cfg(loom)]
+        state: LoomLazy::new(),
+        _backoff: PhantomData,
+    };
+
+    type GuardMarker = GuardSend;
+
+    fn lock_shared(&self) {
+        let mut backoff = B::new();
+        while !self.try_lock_shared() {
+            backoff.spin();
+        }
+    }
+
+    fn try_lock_shared(&self) -> bool {
+        self.add_reader(WRITER | UPGRADED | WRITER_WAITING)
+    }
+
+    fn try_lock_exclusive(&self) -> bool {
+        // take the lock from a waiting writer too; it sets the flag again if it's still waiting
+        let value = self.ulock().load(Ordering::Relaxed);
+        value & !WRITER_WAITING == 0
+            && self
+                .ulock()
+                .compare_exchange(value, WRITER, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+    }
+
+    fn lock_exclusive(&self) {
+        let mut backoff = B::new_exclusive();
+        let mut value = self.ulock().load(Ordering::Relaxed);
+        loop {
+            if value & !WRITER_WAITING == 0 {
+                // taking the lock clears the flag, whoever set it
+                match self.ulock().compare_exchange_weak(
+                    value,
+                    WRITER,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return,
+                    Err(current) => value = current,
+                }
+                continue;
+            }
+
+            if value & WRITER_WAITING == 0 {
+                self.ulock().fetch_or(WRITER_WAITING, Ordering::Relaxed);
+            }
+            backoff.spin();
+            value = self.ulock().load(Ordering::Relaxed);
+        }
+    }
+
+    unsafe fn unlock_shared(&self) {
+        self.ulock().fetch_sub(READER, Ordering::Release);
+    }
+
+    unsafe fn unlock_exclusive(&self) {
+        // leave the flag alone, since another writer may have set it while we held the lock
+        self.ulock()
+            .fetch_and(!(WRITER | UPGRADED), Ordering::Release);
+    }
+
+    fn is_locked(&self) -> bool {
+        self.ulock().load(Ordering::Relaxed) & !WRITER_WAITING != 0
+    }
+
+    fn is_locked_exclusive(&self) -> bool {
+        self.ulock().load(Ordering::Relaxed) & WRITER != 0
+    }
+}
+
+unsafe impl<B: Backoff> RawRwLockFair for RawWriterPreferringRwSpinlock<B> {
+    unsafe fn unlock_shared_fair(&self) {
+        self.unlock_shared();
+    }
+
+    unsafe fn unlock_exclusive_fair(&self) {
+        self.unlock_exclusive();
+    }
+
+    unsafe fn bump_shared(&self) {
+        self.unlock_shared();
+        spin_loop();
+        self.lock_shared();
+    }
+
+    unsafe fn bump_exclusive(&self) {
+        self.unlock_exclusive();
+        spin_loop();
+        self.lock_exclusive();
+    }
+}
+
+unsafe impl<B: Backoff> RawRwLockRecursive for RawWriterPreferringRwSpinlock<B> {
+    fn lock_shared_recursive(&self) {
+        let mut backoff = B::new();
+        while !self.try_lock_shared_recursive() {
+            backoff.spin();
+        }
+    }
+
+    fn try_lock_shared_recursive(&self) -> bool {
+        // a waiting writer would be waiting on us, so don't wait on it
+        self.add_reader(WRITER)
+    }
+}
+
+#[cfg(feature = "upgrade")]
+unsafe impl<B: Backoff> RawRwLockUpgrade for RawWriterPreferringRwSpinlock<B> {
+    fn lock_upgradable(&self) {
+        let mut backoff = B::new();
+        while !self.try_lock_upgradable() {
+            backoff.spin();
+        }
+    }
+
+    fn try_lock_upgradable(&self) -> bool {
+        let mut value = self.ulock().load(Ordering::Relaxed);
+        loop {
+            if value & (WRITER | UPGRADED | WRITER_WAITING) != 0 {
+                return false;
+            }
+
+            match self.ulock().compare_exchange_weak(
+                value,
+                value | UPGRADED,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => value = current,
+            }
+        }
+    }
+
+    unsafe fn try_upgrade(&self) -> bool {
+        // a waiting writer is waiting on us, so upgrade past its flag, and keep it for later
+        let value = self.ulock().load(Ordering::Relaxed);
+        value & !WRITER_WAITING == UPGRADED
+            && self
+                .ulock()
+                .compare_exchange(
+                    value,
+                    (value & WRITER_WAITING) | WRITER,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+    }
+
+    unsafe fn upgrade(&self) {
+        let mut backoff = B::new_exclusive();
+        while !self.try_upgrade() {
+            backoff.spin();
+        }
+    }
+
+    unsafe fn unlock_upgradable(&self) {
+        self.ulock().fetch_sub(UPGRADED, Ordering::AcqRel);
+    }
+}
+
+#[cfg(feature = "upgrade")]
+unsafe impl<B: Backoff> RawRwLockDowngrade for RawWriterPreferringRwSpinlock<B> {
+    unsafe fn downgrade(&self) {
+        self.ulock().fetch_add(READER, Ordering::Acquire);
+        self.unlock_exclusive();
+    }
+}
+
+#[cfg(feature = "upgrade")]
+unsafe impl<B: Backoff> RawRwLockUpgradeDowngrade for RawWriterPreferringRwSpinlock<B> {
+    unsafe fn downgrade_upgradable(&self) {
+        self.ulock().fetch_add(READER, Ordering::Acquire);
+        self.unlock_upgradable();
+    }
+
+    unsafe fn downgrade_to_upgradable(&self) {
+        self.ulock().fetch_xor(WRITER | UPGRADED, Ordering::Release);
+    }
+}
+
+/// A read-write lock that uses a writer-preferring spinlock internally.
+pub type WriterPreferringRwLock<T> = LARwLock<RawWriterPreferringRwSpinlock, T>;
+
+#[cfg(test)]
+mod tests {
+    use super::RawWriterPreferringRwSpinlock;
+    use crate::test_util::writer_among_readers;
+
+    #[cfg(all(not(loom), feature = "upgrade"))]
+    use {super::WriterPreferringRwLock, std::thread};
+
+    #[cfg(all(not(loom), feature = "upgrade"))]
+    #[test]
+    fn basics() {
+        use lock_api::RwLockUpgradableReadGuard;
+
+        let rwlock = WriterPreferringRwLock::new(0);
+        let guards = [rwlock.read(), rwlock.read()];
+        assert!(rwlock.try_write().is_none());
+        assert!(rwlock.try_upgradable_read().is_some());
+        drop(guards);
+
+        let guard = rwlock.upgradable_read();
+        assert!(rwlock.try_read().is_none());
+        let mut guard = RwLockUpgradableReadGuard::upgrade(guard);
+        *guard += 1;
+        drop(guard);
+
+        assert!(!rwlock.is_locked());
+        assert_eq!(*rwlock.try_write().unwrap(), 1);
+    }
+
+    // test that new readers are turned away while a writer waits for the current ones to leave
+    #[cfg(all(not(loom), feature = "upgrade"))]
+    #[test]
+    fn writer_preferred() {
+        let rwlock = WriterPreferringRwLock::new(0);
+        let raw = unsafe { rwlock.raw() };
+        let reader = rwlock.read();
+
+        thread::scope(|s| {
+            s.spawn(|| *rwlock.write() += 1);
+            while !raw.writer_waiting() {
+                thread::yield_now();
+            }
+
+            assert!(rwlock.try_read().is_none());
+            assert!(rwlock.try_upgradable_read().is_none());
+            // the reader can still take recursive reads instead of deadlocking with the writer
+            drop(rwlock.read_recursive());
+            drop(reader);
+        });
+
+        assert!(!raw.writer_waiting());
+        assert_eq!(*rwlock.read(), 1);
+    }
+
+    // test that a writer gets in, exclusively, among reads that keep coming back, and that only
+    // the readers already inside when it started waiting finish a read after that
+    fn continuous_readers_kernel(readers: usize, rounds: usize) {
+        let late =
+            writer_among_readers::<RawWriterPreferringRwSpinlock>(readers, rounds, 1, |raw, _| {
+                raw.writer_waiting()
+            });
+        assert!(
+            late <= readers,
+            "{} reads finished while the writer waited",
+            late
+        );
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn continuous_readers() {
+        loom::model(|| continuous_readers_kernel(1, 3));
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn continuous_readers() {
+        // Miri is slow enough to interleave the threads plenty in a few rounds
+        for _ in 0..if cfg!(miri) { 2 } else { 100 } {
+            continuous_readers_kernel(2, if cfg!(miri) { 20 } else { 1000 });
+        }
+    }
+}